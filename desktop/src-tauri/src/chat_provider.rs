@@ -0,0 +1,889 @@
+//! Chat backends for `run_chat_stream`, `chat_once` and `compact_history`.
+//!
+//! Each provider builds its own request body and parses its own SSE schema, but
+//! both report through the same `ChatEvent`s, so the frontend doesn't need to
+//! know which backend is configured. Messages and tools always arrive in
+//! Anthropic's format; the OpenAI provider translates them, and translates
+//! non-streaming replies back into an Anthropic-shaped message.
+
+use crate::{
+    apply_message_cache_breakpoints, mark_last_tool_cacheable, select_model, ApiConfig, ChatEvent,
+    ChatRequest, Message, PendingToolUse, StopEvent, ToolUseEvent, UsageEvent, MAX_CACHE_BREAKPOINTS,
+    MAX_OUTPUT_TOKENS, MODELS, MODEL_HAIKU,
+};
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Which API the chat is sent to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ChatProviderKind {
+    #[default]
+    Anthropic,
+    /// Any server speaking OpenAI's chat completions API (OpenAI, OpenRouter, vLLM, ...)
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+impl ChatProviderKind {
+    pub(crate) fn provider(self, config: &ApiConfig) -> Box<dyn ChatProvider> {
+        match self {
+            ChatProviderKind::Anthropic => Box::new(AnthropicProvider),
+            ChatProviderKind::OpenAi => Box::new(OpenAiProvider {
+                default_model: config.model.clone(),
+            }),
+        }
+    }
+}
+
+/// What one streamed response has produced so far
+pub(crate) struct StreamTurn {
+    pub usage: UsageEvent,
+    pub stop: StopEvent,
+    /// Assembled assistant output, for the chat log
+    pub text: String,
+    pub tool_calls: Vec<serde_json::Value>,
+    /// Tool calls being built, keyed by content block (or tool call) index
+    pub pending_tools: HashMap<u64, PendingToolUse>,
+    /// Set once the provider has seen the end of the response
+    pub finished: bool,
+}
+
+impl StreamTurn {
+    pub(crate) fn new() -> Self {
+        Self {
+            usage: UsageEvent::zero(),
+            stop: StopEvent::default(),
+            text: String::new(),
+            tool_calls: Vec::new(),
+            pending_tools: HashMap::new(),
+            finished: false,
+        }
+    }
+
+    pub(crate) fn text_event(&mut self, text: &str) -> ChatEvent {
+        self.text.push_str(text);
+        ChatEvent {
+            event_type: "text".to_string(),
+            content: Some(text.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Progress of a tool call whose input is still streaming: the input so far
+    /// in `content`, so the UI can show the call forming. `tool_use` follows once it's complete.
+    fn tool_input_event(tool: &PendingToolUse) -> ChatEvent {
+        ChatEvent {
+            event_type: "tool_input_delta".to_string(),
+            content: Some(tool.input.clone()),
+            tool_use: Some(ToolUseEvent {
+                id: tool.id.clone(),
+                name: tool.name.clone(),
+                input: serde_json::Value::Null,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Parse a completed tool call's input: a `tool_use` event if it's valid JSON,
+    /// otherwise a `tool_error` carrying the raw input
+    fn finish_tool_use(&mut self, tool: PendingToolUse) -> ChatEvent {
+        let PendingToolUse { id, name, input: raw_input } = tool;
+        // Tools without arguments stream no input at all
+        let input: serde_json::Value = if raw_input.trim().is_empty() {
+            serde_json::Value::Object(serde_json::Map::new())
+        } else {
+            match serde_json::from_str(&raw_input) {
+                Ok(input) => input,
+                Err(e) => {
+                    // Don't dispatch a truncated call as an empty-argument one
+                    log::warn!("Invalid input JSON for tool {}: {}", name, e);
+                    return ChatEvent {
+                        event_type: "tool_error".to_string(),
+                        content: Some(raw_input),
+                        tool_use: Some(ToolUseEvent {
+                            id,
+                            name,
+                            input: serde_json::Value::Null,
+                        }),
+                        error: Some(format!("Invalid tool input JSON: {}", e)),
+                        ..Default::default()
+                    };
+                }
+            }
+        };
+        self.tool_calls.push(serde_json::json!({ "id": id, "name": name, "input": input }));
+
+        ChatEvent {
+            event_type: "tool_use".to_string(),
+            tool_use: Some(ToolUseEvent { id, name, input }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Splits a streamed response body into the `data:` payloads of its SSE events
+#[derive(Default)]
+pub(crate) struct SseDecoder {
+    buffer: String,
+    /// Start of a multibyte character cut off at the end of the last chunk
+    partial: Vec<u8>,
+}
+
+impl SseDecoder {
+    /// Add a chunk and return the payloads of every event it completed
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.partial.extend_from_slice(chunk);
+        self.decode_partial();
+
+        let mut payloads = Vec::new();
+        while let Some(event_end) = self.buffer.find("\n\n") {
+            let event: String = self.buffer.drain(..event_end + 2).collect();
+            payloads.extend(event_payloads(&event));
+        }
+        payloads
+    }
+
+    /// Payloads of an event the stream ended in the middle of
+    pub(crate) fn finish(&mut self) -> Vec<String> {
+        self.buffer.push_str(&String::from_utf8_lossy(&std::mem::take(&mut self.partial)));
+        let event = std::mem::take(&mut self.buffer);
+        event_payloads(&event)
+    }
+
+    /// Move the complete UTF-8 text in `partial` to `buffer`, keeping back an
+    /// unfinished character so it isn't decoded as replacement characters
+    fn decode_partial(&mut self) {
+        loop {
+            match std::str::from_utf8(&self.partial) {
+                Ok(text) => {
+                    self.buffer.push_str(text);
+                    self.partial.clear();
+                    return;
+                }
+                Err(e) => {
+                    let valid = e.valid_up_to();
+                    self.buffer.push_str(&String::from_utf8_lossy(&self.partial[..valid]));
+                    match e.error_len() {
+                        // Truncated at the end: the rest is in the next chunk
+                        None => {
+                            self.partial.drain(..valid);
+                            return;
+                        }
+                        // Genuinely invalid bytes
+                        Some(len) => {
+                            self.buffer.push(char::REPLACEMENT_CHARACTER);
+                            self.partial.drain(..valid + len);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// SSE event types that only keep the connection alive
+const KEEP_ALIVE_EVENTS: &[&str] = &["ping"];
+
+/// `data:` payloads of one event. Keep-alives and frames with only an
+/// `event:` line or `:` comments yield nothing.
+fn event_payloads(event: &str) -> Vec<String> {
+    let keep_alive = event
+        .lines()
+        .filter_map(|line| line.strip_prefix("event:"))
+        .any(|name| KEEP_ALIVE_EVENTS.contains(&name.trim()));
+    if keep_alive {
+        return Vec::new();
+    }
+    event
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .map(String::from)
+        .collect()
+}
+
+/// A chat API that streams over SSE
+pub(crate) trait ChatProvider: Send + Sync {
+    /// Model id to send for the model the chat asked for
+    fn model(&self, requested: Option<&str>) -> Result<String, String>;
+
+    /// Context window of `model` in tokens, if known
+    fn context_window(&self, _model: &str) -> Option<usize> {
+        None
+    }
+
+    /// Whether `ChatRequest.seed` is sent to the backend
+    fn supports_seed(&self) -> bool {
+        false
+    }
+
+    /// Body of a streaming request
+    fn stream_body(&self, model: &str, request: &ChatRequest) -> Result<serde_json::Value, String>;
+
+    /// Start the POST to the streaming endpoint with auth and config headers set
+    fn post(&self, client: &Client, config: &ApiConfig, api_key: &str, request: &ChatRequest) -> RequestBuilder;
+
+    /// Handle the payload of one SSE `data:` line, returning the events to emit.
+    /// Errors reported inside the stream are returned as `Err`.
+    fn handle_data(&self, data: &str, turn: &mut StreamTurn) -> Result<Vec<ChatEvent>, String>;
+
+    /// Start a non-streaming request for an Anthropic Messages body (model,
+    /// max_tokens, system, messages and optionally tools and temperature)
+    fn message_request(
+        &self,
+        client: &Client,
+        config: &ApiConfig,
+        api_key: &str,
+        body: &serde_json::Value,
+    ) -> Result<RequestBuilder, String>;
+
+    /// Convert a non-streaming response into an Anthropic message: content
+    /// blocks, stop_reason and usage
+    fn parse_message(&self, response: serde_json::Value) -> Result<serde_json::Value, String>;
+
+    /// The cheapest request that is rejected with 401 when `api_key` is invalid
+    fn key_check(&self, client: &Client, config: &ApiConfig, api_key: &str) -> RequestBuilder;
+}
+
+// ============================================================================
+// Anthropic Messages API
+// ============================================================================
+
+pub(crate) struct AnthropicProvider;
+
+impl ChatProvider for AnthropicProvider {
+    fn model(&self, requested: Option<&str>) -> Result<String, String> {
+        Ok(select_model(requested).to_string())
+    }
+
+    fn context_window(&self, model: &str) -> Option<usize> {
+        MODELS
+            .iter()
+            .find(|m| m.id == model)
+            .map(|m| m.context_window as usize)
+    }
+
+    fn stream_body(&self, model: &str, request: &ChatRequest) -> Result<serde_json::Value, String> {
+        let prompt_caching = request.prompt_caching;
+
+        let mut system_block = serde_json::json!({
+            "type": "text",
+            "text": request.system_prompt,
+        });
+        if prompt_caching {
+            system_block["cache_control"] = serde_json::json!({ "type": "ephemeral" });
+        }
+
+        let mut messages = request.messages.clone();
+        if prompt_caching && !request.cache_breakpoints.is_empty() {
+            // System prompt and last tool already use one breakpoint each
+            let has_tools = request.tools.as_ref().is_some_and(|t| !t.is_empty());
+            let available = MAX_CACHE_BREAKPOINTS - 1 - usize::from(has_tools);
+            apply_message_cache_breakpoints(&mut messages, &request.cache_breakpoints, available)?;
+        }
+
+        let mut body = serde_json::json!({
+            "model": model,
+            "max_tokens": MAX_OUTPUT_TOKENS,
+            "stream": true,
+            "system": [system_block],
+            "messages": messages,
+        });
+        if let Some(temperature) = request.temperature {
+            body["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            body["top_p"] = serde_json::json!(top_p);
+        }
+        if !request.stop_sequences.is_empty() {
+            body["stop_sequences"] = serde_json::json!(request.stop_sequences);
+        }
+        if let Some(tier) = &request.service_tier {
+            body["service_tier"] = serde_json::json!(tier);
+        }
+        if let Some(user_id) = request.provider_user_id() {
+            body["metadata"] = serde_json::json!({ "user_id": user_id });
+        }
+
+        // Add tools with cache control on last tool
+        if let Some(tools) = request.tools.clone().filter(|t| !t.is_empty()) {
+            let tools = if prompt_caching {
+                mark_last_tool_cacheable(tools)
+            } else {
+                tools
+            };
+            body["tools"] = serde_json::Value::Array(tools);
+        }
+
+        Ok(body)
+    }
+
+    fn post(&self, client: &Client, config: &ApiConfig, api_key: &str, request: &ChatRequest) -> RequestBuilder {
+        let caching_beta = config.prompt_caching_beta.as_deref().filter(|_| request.prompt_caching);
+        config.post_with_betas(client, api_key, "/v1/messages", caching_beta.as_slice())
+    }
+
+    fn message_request(
+        &self,
+        client: &Client,
+        config: &ApiConfig,
+        api_key: &str,
+        body: &serde_json::Value,
+    ) -> Result<RequestBuilder, String> {
+        Ok(config.post(client, api_key, "/v1/messages").json(body))
+    }
+
+    fn parse_message(&self, response: serde_json::Value) -> Result<serde_json::Value, String> {
+        Ok(response)
+    }
+
+    fn key_check(&self, client: &Client, config: &ApiConfig, api_key: &str) -> RequestBuilder {
+        // Free, and fails with 401 just like a real request would
+        config.post(client, api_key, "/v1/messages/count_tokens").json(&serde_json::json!({
+            "model": MODEL_HAIKU,
+            "messages": [{ "role": "user", "content": "ping" }],
+        }))
+    }
+
+    fn handle_data(&self, data: &str, turn: &mut StreamTurn) -> Result<Vec<ChatEvent>, String> {
+        let Ok(event) = serde_json::from_str::<serde_json::Value>(data) else {
+            return Ok(Vec::new());
+        };
+        let block_index = event["index"].as_u64().unwrap_or(0);
+        let mut events = Vec::new();
+
+        match event["type"].as_str().unwrap_or("") {
+            "message_start" => {
+                if let Some(usage) = event["message"]["usage"].as_object() {
+                    let count = |field: &str| usage.get(field).and_then(|v| v.as_u64());
+                    turn.usage.input_tokens += count("input_tokens").unwrap_or(0) as u32;
+                    if let Some(cr) = count("cache_read_input_tokens") {
+                        turn.usage.cache_read_tokens = Some(turn.usage.cache_read_tokens.unwrap_or(0) + cr as u32);
+                    }
+                    if let Some(cw) = count("cache_creation_input_tokens") {
+                        turn.usage.cache_write_tokens = Some(turn.usage.cache_write_tokens.unwrap_or(0) + cw as u32);
+                    }
+                    if let Some(tier) = usage.get("service_tier").and_then(|v| v.as_str()) {
+                        turn.usage.service_tier = Some(tier.to_string());
+                    }
+                }
+            }
+            "content_block_start" => {
+                let block = &event["content_block"];
+                if block["type"].as_str() == Some("tool_use") {
+                    if let (Some(id), Some(name)) = (block["id"].as_str(), block["name"].as_str()) {
+                        let tool = PendingToolUse {
+                            id: id.to_string(),
+                            name: name.to_string(),
+                            input: String::new(),
+                        };
+                        events.push(StreamTurn::tool_input_event(&tool));
+                        turn.pending_tools.insert(block_index, tool);
+                    }
+                }
+            }
+            "content_block_delta" => {
+                let delta = &event["delta"];
+                if delta["type"].as_str() == Some("text_delta") {
+                    if let Some(text) = delta["text"].as_str() {
+                        events.push(turn.text_event(text));
+                    }
+                } else if delta["type"].as_str() == Some("input_json_delta") {
+                    if let (Some(json), Some(tool)) =
+                        (delta["partial_json"].as_str(), turn.pending_tools.get_mut(&block_index))
+                    {
+                        tool.input.push_str(json);
+                        events.push(StreamTurn::tool_input_event(tool));
+                    }
+                }
+            }
+            "content_block_stop" => {
+                // Emit tool use if this block was one
+                if let Some(tool) = turn.pending_tools.remove(&block_index) {
+                    events.push(turn.finish_tool_use(tool));
+                }
+            }
+            "message_delta" => {
+                if let Some(usage) = event["usage"].as_object() {
+                    turn.usage.output_tokens +=
+                        usage.get("output_tokens").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+                }
+                if let Some(reason) = event["delta"]["stop_reason"].as_str() {
+                    turn.stop.reason = Some(reason.to_string());
+                    turn.stop.sequence = event["delta"]["stop_sequence"].as_str().map(String::from);
+                }
+            }
+            "message_stop" => turn.finished = true,
+            // Keep-alive sent with `data: {"type": "ping"}` during long turns
+            "ping" => {}
+            "error" => {
+                // Mid-stream failure (e.g. overloaded_error); the stream ends after this
+                let error_type = event["error"]["type"].as_str().unwrap_or("error");
+                let message = event["error"]["message"].as_str().unwrap_or("Unknown error");
+                return Err(format!("{}: {}", error_type, message));
+            }
+            _ => {}
+        }
+
+        Ok(events)
+    }
+}
+
+// ============================================================================
+// OpenAI-compatible Chat Completions API
+// ============================================================================
+
+pub(crate) struct OpenAiProvider {
+    /// Model used when the chat asks for one of the Claude models
+    default_model: Option<String>,
+}
+
+impl ChatProvider for OpenAiProvider {
+    fn model(&self, requested: Option<&str>) -> Result<String, String> {
+        // The model picker offers Claude aliases; anything else is a model id for this server
+        requested
+            .filter(|r| !MODELS.iter().any(|m| m.alias == *r || m.id == *r))
+            .map(String::from)
+            .or_else(|| self.default_model.clone())
+            .ok_or_else(|| "No model configured for the OpenAI-compatible endpoint".to_string())
+    }
+
+    fn supports_seed(&self) -> bool {
+        true
+    }
+
+    fn stream_body(&self, model: &str, request: &ChatRequest) -> Result<serde_json::Value, String> {
+        let mut messages = vec![serde_json::json!({ "role": "system", "content": request.system_prompt })];
+        for message in &request.messages {
+            messages.extend(openai_messages(message));
+        }
+
+        let mut body = serde_json::json!({
+            "model": model,
+            "max_tokens": MAX_OUTPUT_TOKENS,
+            "stream": true,
+            "stream_options": { "include_usage": true },
+            "messages": messages,
+        });
+        if let Some(temperature) = request.temperature {
+            body["temperature"] = serde_json::json!(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            body["top_p"] = serde_json::json!(top_p);
+        }
+        if !request.stop_sequences.is_empty() {
+            body["stop"] = serde_json::json!(request.stop_sequences);
+        }
+        if let Some(seed) = request.seed {
+            body["seed"] = serde_json::json!(seed);
+        }
+        if let Some(tier) = &request.service_tier {
+            body["service_tier"] = serde_json::json!(tier);
+        }
+        if let Some(user_id) = request.provider_user_id() {
+            body["user"] = serde_json::json!(user_id);
+        }
+        if let Some(tools) = request.tools.as_ref().filter(|t| !t.is_empty()) {
+            body["tools"] = openai_tools(tools);
+        }
+
+        Ok(body)
+    }
+
+    fn post(&self, client: &Client, config: &ApiConfig, api_key: &str, _request: &ChatRequest) -> RequestBuilder {
+        openai_request(client.post(openai_url(config, "/chat/completions")), config, api_key)
+    }
+
+    fn message_request(
+        &self,
+        client: &Client,
+        config: &ApiConfig,
+        api_key: &str,
+        body: &serde_json::Value,
+    ) -> Result<RequestBuilder, String> {
+        let anthropic_messages: Vec<Message> = serde_json::from_value(body["messages"].clone())
+            .map_err(|e| format!("Invalid messages: {}", e))?;
+        let mut messages = vec![serde_json::json!({ "role": "system", "content": body["system"] })];
+        for message in &anthropic_messages {
+            messages.extend(openai_messages(message));
+        }
+
+        let mut openai_body = serde_json::json!({
+            "model": body["model"],
+            "max_tokens": body["max_tokens"],
+            "messages": messages,
+        });
+        if !body["temperature"].is_null() {
+            openai_body["temperature"] = body["temperature"].clone();
+        }
+        if let Some(tools) = body["tools"].as_array().filter(|t| !t.is_empty()) {
+            openai_body["tools"] = openai_tools(tools);
+        }
+        let url = openai_url(config, "/chat/completions");
+        Ok(openai_request(client.post(url), config, api_key).json(&openai_body))
+    }
+
+    fn parse_message(&self, response: serde_json::Value) -> Result<serde_json::Value, String> {
+        let choice = &response["choices"][0];
+        let message = &choice["message"];
+        if message.is_null() {
+            return Err(format!("Response has no choices: {}", response));
+        }
+
+        let mut content = Vec::new();
+        if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+            content.push(serde_json::json!({ "type": "text", "text": text }));
+        }
+        for call in message["tool_calls"].as_array().into_iter().flatten() {
+            let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+            let input: serde_json::Value = serde_json::from_str(arguments)
+                .map_err(|e| format!("Invalid tool call arguments: {}", e))?;
+            content.push(serde_json::json!({
+                "type": "tool_use",
+                "id": call["id"],
+                "name": call["function"]["name"],
+                "input": input,
+            }));
+        }
+
+        let usage = &response["usage"];
+        let cached = usage["prompt_tokens_details"]["cached_tokens"].as_u64().unwrap_or(0);
+        Ok(serde_json::json!({
+            "content": content,
+            "stop_reason": choice["finish_reason"].as_str().map(openai_stop_reason),
+            "usage": {
+                // Prompt tokens include cached ones; Anthropic reports them separately
+                "input_tokens": usage["prompt_tokens"].as_u64().unwrap_or(0).saturating_sub(cached),
+                "output_tokens": usage["completion_tokens"],
+                "cache_read_input_tokens": cached,
+                "service_tier": response["service_tier"],
+            },
+        }))
+    }
+
+    fn key_check(&self, client: &Client, config: &ApiConfig, api_key: &str) -> RequestBuilder {
+        openai_request(client.get(openai_url(config, "/models")), config, api_key)
+    }
+
+    fn handle_data(&self, data: &str, turn: &mut StreamTurn) -> Result<Vec<ChatEvent>, String> {
+        if data == "[DONE]" {
+            // Servers that never send a finish_reason still get their tool calls dispatched
+            let events = finish_pending_tools(turn);
+            turn.finished = true;
+            return Ok(events);
+        }
+        let Ok(chunk) = serde_json::from_str::<serde_json::Value>(data) else {
+            return Ok(Vec::new());
+        };
+        if let Some(error) = chunk.get("error") {
+            let message = error["message"].as_str().unwrap_or("Unknown error");
+            return Err(format!("error: {}", message));
+        }
+
+        if let Some(tier) = chunk["service_tier"].as_str() {
+            turn.usage.service_tier = Some(tier.to_string());
+        }
+
+        // Sent in a final chunk with no choices when include_usage is set
+        if let Some(usage) = chunk["usage"].as_object() {
+            let count = |field: &str| usage.get(field).and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+            let cached = chunk["usage"]["prompt_tokens_details"]["cached_tokens"].as_u64().unwrap_or(0) as u32;
+            // Prompt tokens include cached ones; Anthropic reports them separately
+            turn.usage.input_tokens += count("prompt_tokens").saturating_sub(cached);
+            turn.usage.output_tokens += count("completion_tokens");
+            turn.usage.cache_read_tokens = Some(turn.usage.cache_read_tokens.unwrap_or(0) + cached);
+        }
+
+        let mut events = Vec::new();
+        let Some(choice) = chunk["choices"].get(0) else {
+            return Ok(events);
+        };
+        let delta = &choice["delta"];
+
+        if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+            events.push(turn.text_event(text));
+        }
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let index = call["index"].as_u64().unwrap_or(0);
+            let tool = turn.pending_tools.entry(index).or_insert_with(|| PendingToolUse {
+                id: String::new(),
+                name: String::new(),
+                input: String::new(),
+            });
+            if let Some(id) = call["id"].as_str() {
+                tool.id = id.to_string();
+            }
+            if let Some(name) = call["function"]["name"].as_str() {
+                tool.name.push_str(name);
+            }
+            if let Some(arguments) = call["function"]["arguments"].as_str() {
+                tool.input.push_str(arguments);
+            }
+            if !tool.name.is_empty() {
+                events.push(StreamTurn::tool_input_event(tool));
+            }
+        }
+
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            turn.stop.reason = Some(openai_stop_reason(reason).to_string());
+            events.extend(finish_pending_tools(turn));
+        }
+
+        Ok(events)
+    }
+}
+
+/// `path` under the configured base URL. Base URLs for these servers
+/// conventionally include the version, e.g. http://localhost:8000/v1
+fn openai_url(config: &ApiConfig, path: &str) -> String {
+    format!("{}{}", config.base_url.trim_end_matches('/'), path)
+}
+
+/// Add auth and the configured extra headers
+fn openai_request(mut request: RequestBuilder, config: &ApiConfig, api_key: &str) -> RequestBuilder {
+    request = request.header("content-type", "application/json");
+    // Local servers often run without a key
+    if !api_key.is_empty() {
+        request = request.bearer_auth(api_key);
+    }
+    for (name, value) in &config.extra_headers {
+        request = request.header(name, value);
+    }
+    request
+}
+
+/// Anthropic's name for a finish_reason, so the frontend handles both backends alike
+fn openai_stop_reason(reason: &str) -> &str {
+    match reason {
+        "stop" => "end_turn",
+        "length" => "max_tokens",
+        "tool_calls" | "function_call" => "tool_use",
+        other => other,
+    }
+}
+
+/// Anthropic tool definitions as chat completions functions
+fn openai_tools(tools: &[serde_json::Value]) -> serde_json::Value {
+    tools
+        .iter()
+        .map(|tool| {
+            serde_json::json!({
+                "type": "function",
+                "function": {
+                    "name": tool["name"],
+                    "description": tool["description"],
+                    "parameters": tool["input_schema"],
+                },
+            })
+        })
+        .collect()
+}
+
+/// Emit every tool call still being built, in index order
+fn finish_pending_tools(turn: &mut StreamTurn) -> Vec<ChatEvent> {
+    let mut pending: Vec<_> = turn.pending_tools.drain().collect();
+    pending.sort_by_key(|(index, _)| *index);
+    pending.into_iter().map(|(_, tool)| turn.finish_tool_use(tool)).collect()
+}
+
+/// Translate one Anthropic-format message into chat completions messages.
+/// Tool results become separate `tool` messages, so one message can turn into several.
+fn openai_messages(message: &Message) -> Vec<serde_json::Value> {
+    let blocks = match &message.content {
+        serde_json::Value::Array(blocks) => blocks,
+        content => return vec![serde_json::json!({ "role": message.role, "content": content })],
+    };
+
+    let mut messages = Vec::new();
+    let mut parts = Vec::new();
+    let mut tool_calls = Vec::new();
+    for block in blocks {
+        match block["type"].as_str().unwrap_or("") {
+            "text" => parts.push(serde_json::json!({ "type": "text", "text": block["text"] })),
+            "image" => parts.push(serde_json::json!({
+                "type": "image_url",
+                "image_url": {
+                    "url": format!(
+                        "data:{};base64,{}",
+                        block["source"]["media_type"].as_str().unwrap_or("image/png"),
+                        block["source"]["data"].as_str().unwrap_or("")
+                    ),
+                },
+            })),
+            "tool_use" => tool_calls.push(serde_json::json!({
+                "id": block["id"],
+                "type": "function",
+                "function": {
+                    "name": block["name"],
+                    "arguments": block["input"].to_string(),
+                },
+            })),
+            "tool_result" => {
+                let content = match &block["content"] {
+                    serde_json::Value::String(text) => text.clone(),
+                    // A list of content blocks: keep the text
+                    serde_json::Value::Array(items) => items
+                        .iter()
+                        .filter_map(|item| item["text"].as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    other => other.to_string(),
+                };
+                messages.push(serde_json::json!({
+                    "role": "tool",
+                    "tool_call_id": block["tool_use_id"],
+                    "content": content,
+                }));
+            }
+            other => log::warn!("Dropping {} block unsupported by OpenAI-compatible chat", other),
+        }
+    }
+
+    if message.role == "assistant" {
+        let text: String = parts.iter().filter_map(|p| p["text"].as_str()).collect();
+        let mut assistant = serde_json::json!({
+            "role": "assistant",
+            "content": if text.is_empty() { serde_json::Value::Null } else { text.into() },
+        });
+        if !tool_calls.is_empty() {
+            assistant["tool_calls"] = serde_json::Value::Array(tool_calls);
+        }
+        messages.push(assistant);
+    } else if !parts.is_empty() {
+        messages.push(serde_json::json!({ "role": message.role, "content": parts }));
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Anthropic events for a reply of "Bonjour le monde"
+    fn anthropic_stream() -> String {
+        [
+            r#"{"type":"message_start","message":{"usage":{"input_tokens":12}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Bonjour"}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":" le"}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":" monde"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":3}}"#,
+        ]
+        .iter()
+        .map(|data| format!("data: {}\n\n", data))
+        .collect()
+    }
+
+    /// Feed `chunks` through the decoder and provider, returning the streamed text
+    fn decode(chunks: &[&[u8]]) -> (String, StreamTurn) {
+        let mut sse = SseDecoder::default();
+        let mut turn = StreamTurn::new();
+        let mut text = String::new();
+        let mut payloads: Vec<String> = chunks.iter().flat_map(|chunk| sse.push(chunk)).collect();
+        payloads.extend(sse.finish());
+        for data in payloads {
+            for event in AnthropicProvider.handle_data(&data, &mut turn).unwrap() {
+                text.push_str(event.content.as_deref().unwrap_or(""));
+            }
+        }
+        (text, turn)
+    }
+
+    #[test]
+    fn frames_split_across_chunks_lose_nothing() {
+        let stream = anthropic_stream();
+        let bytes = stream.as_bytes();
+
+        // Every split point, including inside "data: " and inside the JSON
+        for split in 1..bytes.len() {
+            let (text, turn) = decode(&[&bytes[..split], &bytes[split..]]);
+            assert_eq!(text, "Bonjour le monde", "split at {}", split);
+            assert_eq!(turn.usage.input_tokens, 12);
+            assert_eq!(turn.usage.output_tokens, 3);
+        }
+
+        // One byte at a time
+        let chunks: Vec<&[u8]> = bytes.chunks(1).collect();
+        assert_eq!(decode(&chunks).0, "Bonjour le monde");
+    }
+
+    #[test]
+    fn multibyte_characters_split_across_chunks_decode_cleanly() {
+        let reply = "Привет, 世界 🌍";
+        let stream = format!(
+            "data: {}\n\n",
+            serde_json::json!({
+                "type": "content_block_delta",
+                "index": 0,
+                "delta": { "type": "text_delta", "text": reply },
+            })
+        );
+        let bytes = stream.as_bytes();
+
+        for split in 1..bytes.len() {
+            let (text, _) = decode(&[&bytes[..split], &bytes[split..]]);
+            assert_eq!(text, reply, "split at {}", split);
+        }
+        let chunks: Vec<&[u8]> = bytes.chunks(1).collect();
+        let (text, _) = decode(&chunks);
+        assert!(!text.contains(char::REPLACEMENT_CHARACTER));
+        assert_eq!(text, reply);
+    }
+
+    #[test]
+    fn ping_events_are_ignored() {
+        let ping = "event: ping\ndata: {\"type\": \"ping\"}\n\n";
+        let stream: String = anthropic_stream()
+            .split_inclusive("\n\n")
+            .flat_map(|frame| [ping, frame, "event: content_block_delta\n\n"])
+            .collect();
+        assert_eq!(SseDecoder::default().push(ping.as_bytes()), Vec::<String>::new());
+
+        let (text, turn) = decode(&[stream.as_bytes()]);
+        assert_eq!(text, "Bonjour le monde");
+        assert_eq!(turn.usage.input_tokens, 12);
+        assert_eq!(turn.usage.output_tokens, 3);
+        assert_eq!(turn.stop.reason.as_deref(), Some("end_turn"));
+    }
+
+    #[test]
+    fn openai_reply_is_translated_to_an_anthropic_message() {
+        let provider = OpenAiProvider { default_model: None };
+        let response = serde_json::json!({
+            "choices": [{
+                "finish_reason": "tool_calls",
+                "message": {
+                    "content": "Checking",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "function": { "name": "get_sdlxliff_segment", "arguments": "{\"segment_id\":\"4\"}" },
+                    }],
+                },
+            }],
+            "usage": { "prompt_tokens": 100, "completion_tokens": 7, "prompt_tokens_details": { "cached_tokens": 60 } },
+        });
+
+        let message = provider.parse_message(response).unwrap();
+        assert_eq!(message["stop_reason"], "tool_use");
+        assert_eq!(message["content"][0], serde_json::json!({ "type": "text", "text": "Checking" }));
+        assert_eq!(message["content"][1]["type"], "tool_use");
+        assert_eq!(message["content"][1]["input"]["segment_id"], "4");
+        assert_eq!(message["usage"]["input_tokens"], 40);
+        assert_eq!(message["usage"]["cache_read_input_tokens"], 60);
+
+        assert!(provider.parse_message(serde_json::json!({ "choices": [] })).is_err());
+    }
+
+    #[test]
+    fn trailing_event_is_flushed_at_stream_end() {
+        let mut stream = anthropic_stream();
+        stream.push_str(r#"data: {"type":"message_stop"}"#);
+
+        let (text, turn) = decode(&[stream.as_bytes()]);
+        assert_eq!(text, "Bonjour le monde");
+        assert!(turn.finished);
+        assert_eq!(turn.stop.reason.as_deref(), Some("end_turn"));
+    }
+}
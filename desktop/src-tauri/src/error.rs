@@ -0,0 +1,93 @@
+//! Structured errors for the Tauri command boundary.
+//!
+//! Commands returning `AppError` reach the frontend as
+//! `{ "kind": "network", "message": "..." }`, so the UI can branch on the kind
+//! instead of matching message text.
+
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum AppError {
+    /// A client, API key or server hasn't been set up yet
+    NotInitialized(String),
+    /// The server couldn't be reached or returned an unexpected error
+    Network(String),
+    /// The server answered, but not with something usable (malformed JSON,
+    /// the wrong number of results)
+    InvalidResponse(String),
+    /// Credentials are missing or were rejected
+    Auth(String),
+    /// The request didn't finish in time
+    Timeout(String),
+    /// A file, index, model or resource doesn't exist
+    NotFound(String),
+    /// The user cancelled the operation
+    Cancelled(String),
+    /// Anything not covered above
+    Other(String),
+}
+
+impl AppError {
+    /// Classify a failed HTTP response by its status code
+    pub fn from_status(status: reqwest::StatusCode, message: String) -> Self {
+        match status.as_u16() {
+            401 | 403 => AppError::Auth(message),
+            404 => AppError::NotFound(message),
+            408 | 504 => AppError::Timeout(message),
+            _ => AppError::Network(message),
+        }
+    }
+
+    /// Classify a request that failed before getting a response
+    pub fn request(context: &str, error: reqwest::Error) -> Self {
+        let message = format!("{}: {}", context, error);
+        if error.is_timeout() {
+            AppError::Timeout(message)
+        } else {
+            AppError::Network(message)
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::NotInitialized(message)
+            | AppError::Network(message)
+            | AppError::InvalidResponse(message)
+            | AppError::Auth(message)
+            | AppError::Timeout(message)
+            | AppError::NotFound(message)
+            | AppError::Cancelled(message)
+            | AppError::Other(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// Existing `Result<_, String>` helpers can still be used with `?`
+impl From<String> for AppError {
+    fn from(message: String) -> Self {
+        AppError::Other(message)
+    }
+}
+
+impl From<&str> for AppError {
+    fn from(message: &str) -> Self {
+        AppError::Other(message.to_string())
+    }
+}
+
+// ...and `AppError` can flow back into them, keeping just the message
+impl From<AppError> for String {
+    fn from(error: AppError) -> Self {
+        error.to_string()
+    }
+}
@@ -0,0 +1,170 @@
+//! Outbound HTTP client construction.
+//!
+//! Requests to Anthropic, embedding providers and Ollama all share one client
+//! held in `HttpClientState`, so proxy and certificate settings apply everywhere
+//! at once and connections are pooled between calls.
+//!
+//! Reusing the client matters most for back-to-back requests such as embedding
+//! batches or agentic chat turns: a fresh client pays a DNS lookup, TCP connect
+//! and TLS handshake (two to three round trips to a hosted API) on every
+//! request, while a pooled connection sends immediately. `pooling_benchmark`
+//! measures the difference against a real endpoint.
+
+use reqwest::{Certificate, Client, ClientBuilder, NoProxy, Proxy};
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Proxy and TLS settings for outbound requests
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HttpConfig {
+    /// Proxy for all requests. When unset, HTTP_PROXY / HTTPS_PROXY / ALL_PROXY
+    /// from the environment are used.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Hosts that bypass `proxy`, comma separated like NO_PROXY
+    #[serde(default)]
+    pub no_proxy: Option<String>,
+    /// PEM files with extra root certificates, e.g. a TLS-inspecting proxy's CA
+    #[serde(default)]
+    pub ca_certificates: Vec<String>,
+}
+
+static CONFIG: RwLock<HttpConfig> = RwLock::new(HttpConfig {
+    proxy: None,
+    no_proxy: None,
+    ca_certificates: Vec::new(),
+});
+
+/// Local servers (Ollama, TEI) should never be sent through a proxy
+const LOCAL_HOSTS: &str = "localhost,127.0.0.1,::1";
+
+/// How long an unused pooled connection is kept open
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// Idle connections kept per host; enough for concurrent embedding batches
+const POOL_MAX_IDLE_PER_HOST: usize = 8;
+/// TCP keep-alive interval, so proxies and NATs don't drop idle connections
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// A client builder with `config` applied
+fn builder(config: &HttpConfig) -> Result<ClientBuilder, String> {
+    let mut builder = Client::builder()
+        .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+        .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+        .tcp_keepalive(TCP_KEEPALIVE);
+
+    if let Some(url) = config.proxy.as_deref().filter(|url| !url.trim().is_empty()) {
+        let no_proxy = match config.no_proxy.as_deref().filter(|hosts| !hosts.trim().is_empty()) {
+            Some(hosts) => format!("{},{}", LOCAL_HOSTS, hosts),
+            None => LOCAL_HOSTS.to_string(),
+        };
+        let proxy = Proxy::all(url.trim())
+            .map_err(|e| format!("Invalid proxy URL {}: {}", url, e))?
+            .no_proxy(NoProxy::from_string(&no_proxy));
+        builder = builder.proxy(proxy);
+    }
+
+    for path in &config.ca_certificates {
+        let pem = std::fs::read(path).map_err(|e| format!("Failed to read certificate {}: {}", path, e))?;
+        let certificate =
+            Certificate::from_pem(&pem).map_err(|e| format!("Invalid certificate {}: {}", path, e))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+
+    Ok(builder)
+}
+
+/// Build a client using the current proxy and certificate settings
+fn build_client() -> Client {
+    let config = config();
+    match builder(&config).and_then(|builder| builder.build().map_err(|e| e.to_string())) {
+        Ok(client) => client,
+        Err(e) => {
+            // The config was valid when set; a certificate file may have moved since
+            log::warn!("Failed to apply HTTP settings, using defaults: {}", e);
+            Client::new()
+        }
+    }
+}
+
+pub fn config() -> HttpConfig {
+    CONFIG.read().map(|config| config.clone()).unwrap_or_default()
+}
+
+/// Replace the settings after checking that a client can be built from them
+pub fn set_config(config: HttpConfig) -> Result<(), String> {
+    builder(&config)?
+        .build()
+        .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
+    *CONFIG.write().map_err(|e| e.to_string())? = config;
+    Ok(())
+}
+
+/// The app-wide client. Cloning it is cheap and shares the connection pool.
+pub struct HttpClientState(RwLock<Client>);
+
+impl HttpClientState {
+    pub fn new() -> Self {
+        Self(RwLock::new(build_client()))
+    }
+
+    pub fn client(&self) -> Client {
+        self.0.read().map(|client| client.clone()).unwrap_or_else(|e| e.into_inner().clone())
+    }
+
+    /// Swap in a client built from the current settings, returning it
+    pub fn rebuild(&self) -> Result<Client, String> {
+        let client = build_client();
+        *self.0.write().map_err(|e| e.to_string())? = client.clone();
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Sequential requests with a fresh client each time vs one pooled client.
+    /// Needs network access; the URL defaults to the Anthropic API (a 401 is fine,
+    /// only the round trip is timed). Run with
+    /// `HTTP_BENCH_URL=... cargo test --release -- --ignored --nocapture pooling_benchmark`
+    #[test]
+    #[ignore]
+    fn pooling_benchmark() {
+        let url = std::env::var("HTTP_BENCH_URL").unwrap_or_else(|_| "https://api.anthropic.com/v1/models".to_string());
+        const REQUESTS: u32 = 10;
+
+        tauri::async_runtime::block_on(async {
+            let send = |client: Client| {
+                let url = url.clone();
+                async move {
+                    client.get(url).send().await.expect("request failed");
+                }
+            };
+
+            let started = Instant::now();
+            for _ in 0..REQUESTS {
+                send(builder(&HttpConfig::default()).unwrap().build().unwrap()).await;
+            }
+            let fresh = started.elapsed() / REQUESTS;
+
+            let pooled_client = build_client();
+            // Open the connection first, as an earlier request would have
+            send(pooled_client.clone()).await;
+            let started = Instant::now();
+            for _ in 0..REQUESTS {
+                send(pooled_client.clone()).await;
+            }
+            let pooled = started.elapsed() / REQUESTS;
+
+            println!(
+                "{}: fresh client {:?}/request, pooled {:?}/request ({:.1}x)",
+                url,
+                fresh,
+                pooled,
+                fresh.as_secs_f64() / pooled.as_secs_f64()
+            );
+        });
+    }
+}
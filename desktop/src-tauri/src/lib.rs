@@ -1,16 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{BufRead, BufReader, Write};
 use std::path::Path;
-use std::process::{Child, ChildStdin, Command, Stdio};
-use std::sync::Mutex;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
 
+use futures::future::{AbortHandle, Abortable, Aborted};
 use futures::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use tauri::{AppHandle, Emitter, State};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_store::StoreExt;
 
+mod chat_provider;
+mod error;
+mod http;
 mod rag;
-use rag::{RagState, Segment, SearchResult, SearchMode};
+use chat_provider::{ChatProviderKind, SseDecoder, StreamTurn};
+use error::AppError;
+use http::HttpClientState;
+use rag::{BothWeights, EmbeddingProvider, IndexStats, IndexStatus, PersistedIndex, Quantization, RagState, RateLimits, Segment, SearchResult, SimilarPair, SearchMode, ScoreNormalization, SearchOptions, SortOrder};
 
 // ============================================================================
 // MCP Server State
@@ -19,333 +27,2398 @@ use rag::{RagState, Segment, SearchResult, SearchMode};
 struct McpServer {
     child: Option<Child>,
     stdin: Option<ChildStdin>,
-    stdout_reader: Option<BufReader<std::process::ChildStdout>>,
+    /// Shared with the stdout reader thread, which routes responses back to callers
+    pending: Arc<Mutex<McpPending>>,
+    /// Declared by the server in its initialize response
+    capabilities: Option<McpCapabilities>,
+    /// tools/list result, fetched on first use
+    tools: Option<Vec<McpTool>>,
+    /// Shared with the reader thread so both directions are recorded
+    trace: Arc<Mutex<McpTrace>>,
+    retry: McpRetryConfig,
+    /// Set by the first spawn, so a server found dead can be started again
+    app: Option<AppHandle>,
 }
 
-struct McpState(Mutex<McpServer>);
-
-// ============================================================================
-// Anthropic API State
-// ============================================================================
-
-struct ApiKeyState(Mutex<Option<String>>);
+/// Requests waiting on the reader thread, keyed by the JSON text of their id
+#[derive(Default)]
+struct McpPending {
+    responses: HashMap<String, mpsc::Sender<serde_json::Value>>,
+    /// progressToken -> id of the request that carried it
+    progress_tokens: HashMap<String, serde_json::Value>,
+    /// Set when the server says its tool list changed, so the cached list is refetched
+    tools_changed: bool,
+}
 
-#[derive(Clone, Serialize)]
-struct ChatEvent {
-    event_type: String,
-    content: Option<String>,
-    tool_use: Option<ToolUseEvent>,
-    usage: Option<UsageEvent>,
-    error: Option<String>,
+/// Which way a traced frame went
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum McpTraceDirection {
+    Sent,
+    Received,
 }
 
+/// One JSON-RPC line exchanged with the server, as recorded by the trace
 #[derive(Clone, Serialize)]
-struct ToolUseEvent {
-    id: String,
-    name: String,
-    input: serde_json::Value,
+struct McpTraceFrame {
+    /// Milliseconds since the Unix epoch
+    timestamp_ms: u64,
+    direction: McpTraceDirection,
+    /// "request", "response", "notification", or "invalid" for non-JSON lines
+    kind: &'static str,
+    /// The frame as sent; lines that weren't JSON are kept as strings
+    message: serde_json::Value,
 }
 
-#[derive(Clone, Serialize)]
-struct UsageEvent {
-    input_tokens: u32,
-    output_tokens: u32,
-    cache_read_tokens: Option<u32>,
-    cache_write_tokens: Option<u32>,
+const DEFAULT_MCP_TRACE_CAPACITY: usize = 500;
+
+/// Opt-in debug record of raw MCP traffic. Kept across server restarts.
+struct McpTrace {
+    enabled: bool,
+    capacity: usize,
+    frames: std::collections::VecDeque<McpTraceFrame>,
+    /// Set when frames should also be emitted live as "mcp-trace" events
+    live: Option<AppHandle>,
 }
 
-#[derive(Deserialize)]
-struct ChatRequest {
-    messages: Vec<Message>,
-    system_prompt: String,
-    tools: Option<Vec<serde_json::Value>>,
-    stream_id: String,
-    model: Option<String>,
+impl Default for McpTrace {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: DEFAULT_MCP_TRACE_CAPACITY,
+            frames: std::collections::VecDeque::new(),
+            live: None,
+        }
+    }
 }
 
-// Model constants
-const MODEL_HAIKU: &str = "claude-haiku-4-5-20251001";
-const MODEL_SONNET: &str = "claude-sonnet-4-5-20250929";
+impl McpTrace {
+    /// Record `line` if tracing is on, dropping the oldest frames beyond capacity
+    fn record(&mut self, direction: McpTraceDirection, line: &str) {
+        if !self.enabled {
+            return;
+        }
+        let (kind, message) = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(message) => {
+                let kind = match (message.get("method").is_some(), message.get("id").is_some()) {
+                    (true, true) => "request",
+                    (true, false) => "notification",
+                    _ => "response",
+                };
+                (kind, message)
+            }
+            Err(_) => ("invalid", serde_json::Value::String(line.to_string())),
+        };
+        let frame = McpTraceFrame {
+            timestamp_ms: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            direction,
+            kind,
+            message,
+        };
 
-fn select_model(requested: Option<&str>) -> &'static str {
-    match requested {
-        Some("haiku") => MODEL_HAIKU,
-        Some("sonnet") | None => MODEL_SONNET, // Default to Sonnet
-        _ => MODEL_SONNET,
+        if let Some(app) = &self.live {
+            let _ = app.emit("mcp-trace", frame.clone());
+        }
+        while self.frames.len() >= self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
     }
 }
 
-#[derive(Clone, Deserialize, Serialize)]
-struct Message {
-    role: String,
-    content: serde_json::Value,
+/// Record `line` in `trace`, ignoring a poisoned lock
+fn trace_mcp_frame(trace: &Mutex<McpTrace>, direction: McpTraceDirection, line: &str) {
+    if let Ok(mut trace) = trace.lock() {
+        trace.record(direction, line);
+    }
 }
 
-// ============================================================================
-// MCP Server Commands
-// ============================================================================
-
-fn find_python() -> Result<String, String> {
-    let candidates = [
-        "/opt/homebrew/bin/python3",
-        "/usr/local/bin/python3",
-        "python3",
-        "/usr/bin/python3",
-    ];
-
-    let mut tried = Vec::new();
+#[derive(Clone, Serialize)]
+struct McpProgressEvent {
+    request_id: Option<serde_json::Value>,
+    progress_token: serde_json::Value,
+    progress: f64,
+    total: Option<f64>,
+    message: Option<String>,
+}
 
-    for candidate in candidates {
-        if candidate.starts_with('/') {
-            if Path::new(candidate).exists() {
-                return Ok(candidate.to_string());
+/// Read the server's stdout until it closes, handing responses to waiting
+/// requests and turning notifications into Tauri events
+fn run_mcp_reader(
+    app: AppHandle,
+    stdout: ChildStdout,
+    pending: Arc<Mutex<McpPending>>,
+    trace: Arc<Mutex<McpTrace>>,
+) {
+    let reader = BufReader::new(stdout);
+    for line in reader.lines() {
+        let Ok(line) = line else { break };
+        trace_mcp_frame(&trace, McpTraceDirection::Received, &line);
+        let message = match serde_json::from_str::<serde_json::Value>(&line) {
+            Ok(message) => message,
+            Err(_) => {
+                log::warn!("Ignoring non-JSON MCP output: {}", line);
+                continue;
             }
-            tried.push(format!("{} (not found)", candidate));
+        };
+
+        if message.get("method").is_some() {
+            dispatch_mcp_notification(&app, &pending, &message);
         } else {
-            match Command::new(candidate).arg("--version").output() {
-                Ok(_) => return Ok(candidate.to_string()),
-                Err(e) => tried.push(format!("{} ({})", candidate, e)),
-            }
+            route_mcp_response(&pending, message);
         }
     }
-    Err(format!("Python not found. Tried: {}", tried.join(", ")))
-}
 
-#[tauri::command]
-fn spawn_mcp_server(state: State<McpState>) -> Result<String, String> {
-    let mut server = state.0.lock().map_err(|e| e.to_string())?;
+    // Dropping the senders wakes any waiters with an error
+    if let Ok(mut pending) = pending.lock() {
+        pending.responses.clear();
+        pending.progress_tokens.clear();
+    }
+}
 
-    if server.child.is_some() {
-        return Ok("Server already running".to_string());
+/// Hand a response to the request waiting on its id
+fn route_mcp_response(pending: &Mutex<McpPending>, message: serde_json::Value) {
+    let key = message["id"].to_string();
+    let Ok(mut pending) = pending.lock() else { return };
+    pending.progress_tokens.retain(|_, id| id != &message["id"]);
+    match pending.responses.remove(&key) {
+        Some(sender) => {
+            let _ = sender.send(message);
+        }
+        None => log::warn!("MCP response for unknown request id {}", key),
     }
+}
 
-    let python = find_python()?;
+fn dispatch_mcp_notification(
+    app: &AppHandle,
+    pending: &Mutex<McpPending>,
+    message: &serde_json::Value,
+) {
+    let params = &message["params"];
+    match message["method"].as_str().unwrap_or("") {
+        "notifications/progress" => {
+            let token = params["progressToken"].clone();
+            let request_id = pending
+                .lock()
+                .ok()
+                .and_then(|p| p.progress_tokens.get(&token.to_string()).cloned());
+            let _ = app.emit(
+                "mcp-progress",
+                McpProgressEvent {
+                    request_id,
+                    progress_token: token,
+                    progress: params["progress"].as_f64().unwrap_or(0.0),
+                    total: params["total"].as_f64(),
+                    message: params["message"].as_str().map(String::from),
+                },
+            );
+        }
+        "notifications/message" => {
+            // data may be any JSON value; pass strings through as-is
+            let text = match &params["data"] {
+                serde_json::Value::String(text) => text.clone(),
+                data => data.to_string(),
+            };
+            let _ = app.emit(
+                "mcp-log",
+                McpLogEvent {
+                    level: params["level"].as_str().unwrap_or("info").to_string(),
+                    logger: params["logger"].as_str().map(String::from),
+                    text,
+                },
+            );
+        }
+        "notifications/tools/list_changed" => {
+            if let Ok(mut pending) = pending.lock() {
+                pending.tools_changed = true;
+            }
+        }
+        method => log::debug!("Unhandled MCP message: {}", method),
+    }
+}
 
-    let check = Command::new(&python)
-        .args(["-c", "import mcp_server_sdlxliff"])
-        .output()
-        .map_err(|e| format!("Failed to check for mcp_server_sdlxliff: {}", e))?;
+#[derive(Clone, Serialize)]
+struct McpLogEvent {
+    level: String,
+    logger: Option<String>,
+    text: String,
+}
 
-    if !check.status.success() {
-        let stderr = String::from_utf8_lossy(&check.stderr);
-        return Err(format!(
-            "mcp-server-sdlxliff not installed for {}. Run: pip install mcp-server-sdlxliff\nError: {}",
-            python, stderr.trim()
-        ));
-    }
+// Syslog severities accepted by logging/setLevel
+const MCP_LOG_LEVELS: &[&str] = &[
+    "debug", "info", "notice", "warning", "error", "critical", "alert", "emergency",
+];
 
-    let mut child = Command::new(&python)
-        .args(["-m", "mcp_server_sdlxliff"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to spawn MCP server with {}: {}", python, e))?;
+/// Which optional MCP features the server advertises
+#[derive(Clone, Serialize)]
+struct McpCapabilities {
+    tools: bool,
+    resources: bool,
+    prompts: bool,
+    logging: bool,
+}
 
-    let stdin = child.stdin.take().ok_or("Failed to get stdin")?;
-    let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
-    let reader = BufReader::new(stdout);
+impl McpCapabilities {
+    fn from_initialize_result(result: &serde_json::Value) -> Self {
+        let capabilities = &result["capabilities"];
+        Self {
+            tools: capabilities.get("tools").is_some(),
+            resources: capabilities.get("resources").is_some(),
+            prompts: capabilities.get("prompts").is_some(),
+            logging: capabilities.get("logging").is_some(),
+        }
+    }
+}
 
-    server.child = Some(child);
-    server.stdin = Some(stdin);
-    server.stdout_reader = Some(reader);
+/// Retries for requests that failed in transport. A request that never reached
+/// the server is always sent again; one that may have (a partial write, or no
+/// response in time) only if it's safe to repeat, see `is_idempotent`. JSON-RPC
+/// error responses are answers, so they're never retried.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct McpRetryConfig {
+    /// Extra attempts after the first. Default: 2
+    #[serde(default = "default_mcp_max_retries")]
+    max_retries: u32,
+    /// Per-attempt timeout for requests that don't set their own. Default: 120
+    #[serde(default = "default_mcp_timeout_secs")]
+    timeout_secs: u64,
+}
 
-    Ok("MCP server started".to_string())
+fn default_mcp_max_retries() -> u32 {
+    2
 }
 
-#[tauri::command]
-fn mcp_request(state: State<McpState>, message: String) -> Result<String, String> {
-    let mut server = state.0.lock().map_err(|e| e.to_string())?;
+/// Long enough for a QA check or full read of a large file
+fn default_mcp_timeout_secs() -> u64 {
+    120
+}
 
-    {
-        let stdin = server.stdin.as_mut().ok_or("No stdin available")?;
-        writeln!(stdin, "{}", message).map_err(|e| e.to_string())?;
-        stdin.flush().map_err(|e| e.to_string())?;
+impl Default for McpRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: default_mcp_max_retries(),
+            timeout_secs: default_mcp_timeout_secs(),
+        }
     }
+}
 
-    let reader = server.stdout_reader.as_mut().ok_or("No stdout reader")?;
-    let mut response = String::new();
-    reader.read_line(&mut response).map_err(|e| e.to_string())?;
+/// Delay before the first retry, doubled for each one after
+const MCP_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(200);
 
-    Ok(response)
+/// Why a single request attempt failed
+enum McpAttemptError {
+    /// The write failed before any of the request was sent
+    NotSent(String),
+    /// The request may have reached the server, but no response came back
+    Unanswered(String),
+    /// The server's stdin is closed, so it has exited
+    Closed(String),
+    /// The request is malformed or the server stopped answering anything
+    Fatal(String),
 }
 
-#[tauri::command]
-fn mcp_notify(state: State<McpState>, message: String) -> Result<(), String> {
-    let mut server = state.0.lock().map_err(|e| e.to_string())?;
+impl McpAttemptError {
+    fn message(self) -> String {
+        match self {
+            McpAttemptError::NotSent(message)
+            | McpAttemptError::Unanswered(message)
+            | McpAttemptError::Closed(message)
+            | McpAttemptError::Fatal(message) => message,
+        }
+    }
+}
 
-    let stdin = server.stdin.as_mut().ok_or("No stdin available")?;
-    writeln!(stdin, "{}", message).map_err(|e| e.to_string())?;
-    stdin.flush().map_err(|e| e.to_string())?;
+/// Tools that only read, so a call that may have been lost can be repeated
+const MCP_READ_TOOLS: &[&str] = &[
+    "read_sdlxliff",
+    "get_sdlxliff_segment",
+    "get_sdlxliff_statistics",
+    "validate_sdlxliff_segment",
+    "qa_check_sdlxliff",
+];
 
-    Ok(())
+/// Methods without side effects on the server
+const MCP_IDEMPOTENT_METHODS: &[&str] = &[
+    "ping",
+    "tools/list",
+    "resources/list",
+    "resources/read",
+    "prompts/list",
+    "prompts/get",
+];
+
+/// Whether sending `message` twice is harmless
+fn is_idempotent(message: &serde_json::Value) -> bool {
+    match message["method"].as_str() {
+        Some("tools/call") => message["params"]["name"]
+            .as_str()
+            .is_some_and(|name| MCP_READ_TOOLS.contains(&name)),
+        Some(method) => MCP_IDEMPOTENT_METHODS.contains(&method),
+        None => false,
+    }
 }
 
-#[tauri::command]
-fn stop_mcp_server(state: State<McpState>) -> Result<String, String> {
-    let mut server = state.0.lock().map_err(|e| e.to_string())?;
+/// A request that has been sent, waiting for the reader thread to route its response back
+struct McpWaiter {
+    id: serde_json::Value,
+    receiver: mpsc::Receiver<serde_json::Value>,
+    /// Routing table the request was registered in, so it can be removed on timeout
+    pending: Arc<Mutex<McpPending>>,
+}
 
-    server.stdin = None;
-    server.stdout_reader = None;
+impl McpWaiter {
+    /// Stop routing responses for this request
+    fn forget(&self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.responses.remove(&self.id.to_string());
+            pending.progress_tokens.retain(|_, request_id| request_id != &self.id);
+        }
+    }
 
-    if let Some(mut child) = server.child.take() {
-        let _ = child.kill();
-        let _ = child.wait();
-        Ok("MCP server stopped".to_string())
-    } else {
-        Ok("No server running".to_string())
+    fn wait(self, timeout: std::time::Duration) -> Result<serde_json::Value, McpAttemptError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(response) => Ok(response),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                self.forget();
+                Err(McpAttemptError::Unanswered(format!(
+                    "MCP server did not respond within {:?}",
+                    timeout
+                )))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(McpAttemptError::Fatal("MCP server closed its output".to_string()))
+            }
+        }
     }
 }
 
-// ============================================================================
-// Anthropic API Commands
-// ============================================================================
+// JSON-RPC ids for requests made by the backend itself (the frontend uses small integers)
+static NEXT_MCP_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
 
-#[tauri::command]
-fn set_api_key(state: State<ApiKeyState>, key: String) -> Result<(), String> {
-    let mut api_key = state.0.lock().map_err(|e| e.to_string())?;
-    *api_key = Some(key);
-    Ok(())
+fn next_mcp_id() -> serde_json::Value {
+    serde_json::json!(format!(
+        "backend-{}",
+        NEXT_MCP_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    ))
 }
 
-#[tauri::command]
-fn clear_api_key(state: State<ApiKeyState>) -> Result<(), String> {
-    let mut api_key = state.0.lock().map_err(|e| e.to_string())?;
-    *api_key = None;
-    Ok(())
-}
+impl McpServer {
+    /// Write one line-delimited JSON-RPC message
+    fn send(&mut self, message: &str) -> Result<(), String> {
+        self.write_line(message).map_err(McpAttemptError::message)
+    }
 
-#[tauri::command]
-fn has_api_key(state: State<ApiKeyState>) -> bool {
-    state.0.lock().map(|k| k.is_some()).unwrap_or(false)
-}
+    /// Write `message` and a newline, reporting how far a failed write got
+    fn write_line(&mut self, message: &str) -> Result<(), McpAttemptError> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| McpAttemptError::Fatal("No stdin available".to_string()))?;
+        // Before writing, so the frame can't be recorded after its response
+        trace_mcp_frame(&self.trace, McpTraceDirection::Sent, message);
 
-#[tauri::command]
-async fn chat_stream(
-    app: AppHandle,
-    state: State<'_, ApiKeyState>,
-    request: ChatRequest,
-) -> Result<(), String> {
-    let api_key = {
-        let key_guard = state.0.lock().map_err(|e| e.to_string())?;
-        key_guard.clone().ok_or("API key not set")?
-    };
+        let failed = |e: std::io::Error, sent_any: bool| match e.kind() {
+            std::io::ErrorKind::BrokenPipe => McpAttemptError::Closed(format!("MCP server has exited: {}", e)),
+            _ if sent_any => McpAttemptError::Unanswered(format!("Failed to write MCP request: {}", e)),
+            _ => McpAttemptError::NotSent(format!("Failed to write MCP request: {}", e)),
+        };
+        let line = format!("{}\n", message);
+        let written = stdin.write(line.as_bytes()).map_err(|e| failed(e, false))?;
+        stdin
+            .write_all(&line.as_bytes()[written..])
+            .and_then(|_| stdin.flush())
+            .map_err(|e| failed(e, written > 0))
+    }
 
-    let stream_id = request.stream_id.clone();
-    let app_clone = app.clone();
+    /// Register `message` with the reader thread and write it
+    fn start_request(&mut self, message: serde_json::Value) -> Result<McpWaiter, McpAttemptError> {
+        let (message, waiter) = self.register(message).map_err(McpAttemptError::Fatal)?;
+        if let Err(e) = self.write_line(&message.to_string()) {
+            waiter.forget();
+            return Err(e);
+        }
+        Ok(waiter)
+    }
 
-    // Spawn the streaming task
-    tauri::async_runtime::spawn(async move {
-        if let Err(e) = run_chat_stream(app_clone.clone(), api_key, request).await {
-            let _ = app_clone.emit(
-                &format!("chat-event-{}", stream_id),
-                ChatEvent {
-                    event_type: "error".to_string(),
-                    content: None,
-                    tool_use: None,
-                    usage: None,
-                    error: Some(e),
-                },
-            );
+    /// Have the reader thread route the response to `message` back, adding a
+    /// progressToken to tools/call requests that lack one
+    fn register(&mut self, mut message: serde_json::Value) -> Result<(serde_json::Value, McpWaiter), String> {
+        let id = message
+            .get("id")
+            .cloned()
+            .ok_or("MCP request has no id")?;
+
+        let is_tool_call = message["method"].as_str() == Some("tools/call");
+        if is_tool_call && message["params"]["_meta"]["progressToken"].is_null() {
+            if let Some(params) = message.get_mut("params").and_then(|p| p.as_object_mut()) {
+                params.insert("_meta".to_string(), serde_json::json!({ "progressToken": id }));
+            }
         }
-    });
 
-    Ok(())
+        let (sender, receiver) = mpsc::channel();
+        {
+            let mut pending = self.pending.lock().map_err(|e| e.to_string())?;
+            pending.responses.insert(id.to_string(), sender);
+            let token = &message["params"]["_meta"]["progressToken"];
+            if !token.is_null() {
+                pending.progress_tokens.insert(token.to_string(), id.clone());
+            }
+        }
+        let waiter = McpWaiter {
+            id,
+            receiver,
+            pending: self.pending.clone(),
+        };
+        Ok((message, waiter))
+    }
+
+    /// Close stdin so the server can exit on its own, then kill it if it hasn't
+    /// within a grace period. Returns false if no server was running.
+    fn shutdown(&mut self) -> bool {
+        self.stdin = None;
+        self.capabilities = None;
+        self.tools = None;
+
+        let Some(mut child) = self.child.take() else {
+            return false;
+        };
+
+        let deadline = std::time::Instant::now() + MCP_SHUTDOWN_GRACE;
+        while std::time::Instant::now() < deadline {
+            if let Ok(Some(_)) = child.try_wait() {
+                return true;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        log::warn!("MCP server did not exit after stdin closed; killing it");
+        let _ = child.kill();
+        let _ = child.wait();
+        true
+    }
 }
 
-async fn run_chat_stream(
-    app: AppHandle,
-    api_key: String,
-    request: ChatRequest,
-) -> Result<(), String> {
-    let client = Client::new();
-    let stream_id = request.stream_id;
-    let event_name = format!("chat-event-{}", stream_id);
+const MCP_SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(2);
+/// How long a freshly spawned server has to answer initialize. Generous, since
+/// the first import of the Python package can be slow.
+const MCP_STARTUP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
-    // Select model based on user choice (default: Sonnet)
-    let model = select_model(request.model.as_deref());
-    log::info!("Using model: {}", model);
+/// The server, locked only to write a request or read its settings. Responses
+/// are awaited with the lock released, so a slow tool call doesn't hold up a
+/// ping or a read sent after it.
+struct McpState(Mutex<McpServer>);
 
-    // Emit model selection event
-    let _ = app.emit(
-        &event_name,
-        ChatEvent {
-            event_type: "model_selected".to_string(),
-            content: Some(model.to_string()),
-            tool_use: None,
-            usage: None,
-            error: None,
-        },
-    );
+impl McpState {
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, McpServer>, String> {
+        self.0.lock().map_err(|e| e.to_string())
+    }
 
-    // Build the request body
-    let mut body = serde_json::json!({
-        "model": model,
-        "max_tokens": 8192,
-        "stream": true,
-        "system": [{
-            "type": "text",
-            "text": request.system_prompt,
-            "cache_control": { "type": "ephemeral" }
-        }],
-        "messages": request.messages,
-    });
+    /// Send a request and wait up to `timeout` (the configured default if None)
+    /// for its response.
+    /// Transport failures are retried per the server's retry settings, each time
+    /// under a fresh id; the response carries the original one. If the server
+    /// has exited, this fails at once and the server is restarted for the next request.
+    fn request(
+        &self,
+        message: serde_json::Value,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<serde_json::Value, String> {
+        let original_id = message.get("id").cloned().ok_or("MCP request has no id")?;
+        let retry = self.lock()?.retry.clone();
+        let timeout = timeout.unwrap_or(std::time::Duration::from_secs(retry.timeout_secs));
+        let mut message = message;
+        let idempotent = is_idempotent(&message);
+        let retryable = |e: &McpAttemptError| match e {
+            McpAttemptError::NotSent(_) => true,
+            McpAttemptError::Unanswered(_) => idempotent,
+            McpAttemptError::Closed(_) | McpAttemptError::Fatal(_) => false,
+        };
+        let mut delay = MCP_RETRY_DELAY;
+        let mut attempt = 0;
 
-    // Add tools with cache control on last tool
-    if let Some(tools) = request.tools {
-        if !tools.is_empty() {
-            let mut tools_with_cache: Vec<serde_json::Value> = tools
-                .into_iter()
-                .enumerate()
-                .map(|(i, mut tool)| {
-                    if let serde_json::Value::Object(ref mut obj) = tool {
-                        // Add cache_control to last tool
-                        if i == obj.len() - 1 {
-                            obj.insert(
-                                "cache_control".to_string(),
-                                serde_json::json!({ "type": "ephemeral" }),
-                            );
-                        }
-                    }
-                    tool
-                })
-                .collect();
-
-            // Fix: apply cache_control to actual last tool
-            if let Some(last) = tools_with_cache.last_mut() {
-                if let serde_json::Value::Object(ref mut obj) = last {
-                    obj.insert(
-                        "cache_control".to_string(),
-                        serde_json::json!({ "type": "ephemeral" }),
+        loop {
+            // Locked only while writing; the wait and any backoff happen unlocked
+            let sent = self.lock()?.start_request(message.clone());
+            match sent.and_then(|waiter| waiter.wait(timeout)) {
+                Ok(mut response) => {
+                    response["id"] = original_id;
+                    return Ok(response);
+                }
+                Err(McpAttemptError::Closed(e)) => {
+                    self.restart_exited();
+                    return Err(e);
+                }
+                Err(e) if retryable(&e) && attempt < retry.max_retries => {
+                    let e = e.message();
+                    attempt += 1;
+                    log::warn!(
+                        "MCP request failed (attempt {}/{}): {}, retrying in {:?}",
+                        attempt, retry.max_retries, e, delay
                     );
+                    std::thread::sleep(delay);
+                    delay *= 2;
+                    // A late response to the old id must not be taken for this one
+                    message["id"] = next_mcp_id();
                 }
+                Err(e) => return Err(e.message()),
             }
-
-            body["tools"] = serde_json::Value::Array(tools_with_cache);
         }
     }
 
-    // Retry logic with exponential backoff for transient errors (529 Overloaded, 503 Service Unavailable)
-    let max_retries = 3;
-    let mut attempts = 0;
-    let mut retry_delay = std::time::Duration::from_secs(1);
+    /// Replace a server whose stdin was found closed, in the background so the
+    /// failing request can report it straight away
+    fn restart_exited(&self) {
+        let app = {
+            let Ok(mut server) = self.lock() else { return };
+            // Already stopped on purpose, or never spawned
+            if server.child.is_none() {
+                return;
+            }
+            server.shutdown();
+            server.app.clone()
+        };
+        let Some(app) = app else { return };
 
-    let response = loop {
-        let result = client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", &api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("anthropic-beta", "prompt-caching-2024-07-31")
-            .header("content-type", "application/json")
-            .body(body.to_string())
-            .send()
-            .await;
+        log::warn!("MCP server exited unexpectedly; restarting it");
+        std::thread::spawn(move || {
+            let handle = app.clone();
+            if let Err(e) = start_mcp_server(handle, &app.state::<McpState>()) {
+                log::error!("Failed to restart MCP server: {}", e);
+            }
+        });
+    }
 
-        match result {
-            Ok(resp) => {
-                let status = resp.status();
-                // Retry on 529 (Overloaded) or 503 (Service Unavailable)
-                if status.as_u16() == 529 || status.as_u16() == 503 {
+    /// Call `method` and return the result of its response
+    fn call(&self, method: &str, params: serde_json::Value) -> Result<serde_json::Value, String> {
+        self.call_with_timeout(method, params, None)
+    }
+
+    fn call_with_timeout(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<serde_json::Value, String> {
+        let response = self.request(
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": next_mcp_id(),
+                "method": method,
+                "params": params,
+            }),
+            timeout,
+        )?;
+
+        if let Some(error) = response.get("error") {
+            return Err(format!(
+                "MCP error: {}",
+                error["message"].as_str().unwrap_or("unknown error")
+            ));
+        }
+        Ok(response["result"].clone())
+    }
+
+    /// Run the initialize handshake as `client_name`, remembering the server's capabilities
+    fn initialize(
+        &self,
+        client_name: &str,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<serde_json::Value, String> {
+        let params = serde_json::json!({
+            "protocolVersion": "2024-11-05",
+            "capabilities": {},
+            "clientInfo": { "name": client_name, "version": env!("CARGO_PKG_VERSION") },
+        });
+        let result = self.call_with_timeout("initialize", params, timeout)?;
+        self.lock()?.capabilities = Some(McpCapabilities::from_initialize_result(&result));
+        Ok(result)
+    }
+
+    /// The server's tools, from the cache unless it's cold or the server said they changed
+    fn tools(&self) -> Result<Vec<McpTool>, String> {
+        {
+            let server = self.lock()?;
+            let changed = std::mem::take(&mut server.pending.lock().map_err(|e| e.to_string())?.tools_changed);
+            if let Some(tools) = server.tools.as_ref().filter(|_| !changed) {
+                return Ok(tools.clone());
+            }
+        }
+
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => serde_json::json!({ "cursor": cursor }),
+                None => serde_json::json!({}),
+            };
+            let result = self.call("tools/list", params)?;
+            let page: McpToolList =
+                serde_json::from_value(result).map_err(|e| format!("Invalid tools/list result: {}", e))?;
+            tools.extend(page.tools);
+
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        self.lock()?.tools = Some(tools.clone());
+        Ok(tools)
+    }
+
+    /// Whether a server process has been started (and not stopped)
+    fn is_running(&self) -> Result<bool, String> {
+        Ok(self.lock()?.child.is_some())
+    }
+}
+
+/// Run blocking MCP work on a blocking thread, so waiting on the server ties up
+/// neither the main thread nor an async runtime worker
+async fn run_mcp<T, E>(app: &AppHandle, work: impl FnOnce(&McpState) -> Result<T, E> + Send + 'static) -> Result<T, E>
+where
+    T: Send + 'static,
+    E: From<String> + Send + 'static,
+{
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || work(&app.state::<McpState>()))
+        .await
+        .map_err(|e| E::from(format!("MCP task failed: {}", e)))?
+}
+
+/// Tools that change an SDLXLIFF file, in the server's memory or on disk
+const MCP_WRITE_TOOLS: &[&str] = &["update_sdlxliff_segment", "save_sdlxliff"];
+
+/// Tool arguments that name a file the call touches
+const MCP_PATH_ARGUMENTS: &[&str] = &["file_path", "output_path"];
+
+/// A read/write lock per SDLXLIFF path: tool calls that modify a file run one
+/// at a time, while reads of it run alongside each other. Every path that sends
+/// tools/call takes these, including the frontend's raw mcp_request.
+#[derive(Default)]
+struct FileLocks(Mutex<HashMap<String, Arc<tokio::sync::RwLock<()>>>>);
+
+/// Held for the duration of a tool call; dropping it releases the file
+#[allow(dead_code)] // the guards are never read, only kept alive
+enum FileGuard {
+    Read(tokio::sync::OwnedRwLockReadGuard<()>),
+    Write(tokio::sync::OwnedRwLockWriteGuard<()>),
+}
+
+impl FileLocks {
+    /// Lock the files named by `message` if it's a tools/call request
+    async fn acquire_for_request(&self, message: &serde_json::Value) -> Result<Vec<FileGuard>, String> {
+        if message["method"].as_str() != Some("tools/call") {
+            return Ok(Vec::new());
+        }
+        let params = &message["params"];
+        self.acquire(params["name"].as_str().unwrap_or_default(), &params["arguments"]).await
+    }
+
+    /// Lock every file a tool call names: exclusively for write tools, shared otherwise
+    async fn acquire(&self, tool: &str, arguments: &serde_json::Value) -> Result<Vec<FileGuard>, String> {
+        let mut paths: Vec<String> = MCP_PATH_ARGUMENTS
+            .iter()
+            .filter_map(|name| arguments[*name].as_str())
+            .map(|path| {
+                std::fs::canonicalize(path)
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .unwrap_or_else(|_| path.to_string())
+            })
+            .collect();
+        // A fixed order keeps two multi-file calls from deadlocking
+        paths.sort();
+        paths.dedup();
+
+        let locks: Vec<_> = {
+            let mut locks = self.0.lock().map_err(|e| e.to_string())?;
+            // Forget files nobody holds
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            paths
+                .into_iter()
+                .map(|path| locks.entry(path).or_default().clone())
+                .collect()
+        };
+
+        let write = MCP_WRITE_TOOLS.contains(&tool);
+        let mut guards = Vec::new();
+        for lock in locks {
+            guards.push(if write {
+                FileGuard::Write(lock.write_owned().await)
+            } else {
+                FileGuard::Read(lock.read_owned().await)
+            });
+        }
+        Ok(guards)
+    }
+}
+
+// ============================================================================
+// Anthropic API State
+// ============================================================================
+
+struct ApiKeyState(Mutex<Option<String>>);
+
+const DEFAULT_API_BASE_URL: &str = "https://api.anthropic.com";
+
+fn default_api_base_url() -> String {
+    DEFAULT_API_BASE_URL.to_string()
+}
+
+const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+fn default_anthropic_version() -> String {
+    DEFAULT_ANTHROPIC_VERSION.to_string()
+}
+
+const DEFAULT_PROMPT_CACHING_BETA: &str = "prompt-caching-2024-07-31";
+
+fn default_prompt_caching_beta() -> Option<String> {
+    Some(DEFAULT_PROMPT_CACHING_BETA.to_string())
+}
+
+/// Endpoint settings for proxies and gateways
+#[derive(Clone, Deserialize, Serialize)]
+struct ApiConfig {
+    /// API the chat is sent to. Default: Anthropic
+    #[serde(default)]
+    provider: ChatProviderKind,
+    /// Base URL the API paths are appended to. For OpenAI-compatible servers
+    /// include the version, e.g. "http://localhost:8000/v1"
+    #[serde(default = "default_api_base_url")]
+    base_url: String,
+    /// Extra headers sent with every request (e.g. gateway auth tokens)
+    #[serde(default)]
+    extra_headers: HashMap<String, String>,
+    /// Model for OpenAI-compatible servers when the chat asks for a Claude model
+    #[serde(default)]
+    model: Option<String>,
+    /// `anthropic-version` header. Default: 2023-06-01
+    #[serde(default = "default_anthropic_version")]
+    anthropic_version: String,
+    /// `anthropic-beta` features enabled on every request
+    #[serde(default)]
+    anthropic_betas: Vec<String>,
+    /// Beta added when a chat uses prompt caching; None to send none.
+    /// Default: prompt-caching-2024-07-31
+    #[serde(default = "default_prompt_caching_beta")]
+    prompt_caching_beta: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            provider: ChatProviderKind::default(),
+            base_url: default_api_base_url(),
+            extra_headers: HashMap::new(),
+            model: None,
+            anthropic_version: default_anthropic_version(),
+            anthropic_betas: Vec::new(),
+            prompt_caching_beta: default_prompt_caching_beta(),
+        }
+    }
+}
+
+impl ApiConfig {
+    /// Start a POST to `path` with auth, version, betas and any extra headers set
+    fn post(&self, client: &Client, api_key: &str, path: &str) -> reqwest::RequestBuilder {
+        self.post_with_betas(client, api_key, path, &[])
+    }
+
+    /// Like `post`, enabling `betas` on top of the configured ones
+    fn post_with_betas(&self, client: &Client, api_key: &str, path: &str, betas: &[&str]) -> reqwest::RequestBuilder {
+        let url = format!("{}{}", self.base_url.trim_end_matches('/'), path);
+        let mut request = client
+            .post(url)
+            .header("x-api-key", api_key)
+            .header("anthropic-version", &self.anthropic_version)
+            .header("content-type", "application/json");
+
+        let mut all_betas: Vec<&str> = self.anthropic_betas.iter().map(String::as_str).collect();
+        for beta in betas {
+            if !all_betas.contains(beta) {
+                all_betas.push(beta);
+            }
+        }
+        if !all_betas.is_empty() {
+            request = request.header("anthropic-beta", all_betas.join(","));
+        }
+
+        for (name, value) in &self.extra_headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+}
+
+struct ApiConfigState(Mutex<ApiConfig>);
+
+/// The key to send to the configured chat provider
+fn chat_api_key(state: &ApiKeyState, config: &ApiConfig) -> Result<String, String> {
+    let key_guard = state.0.lock().map_err(|e| e.to_string())?;
+    match (key_guard.clone(), config.provider) {
+        (Some(key), _) => Ok(key),
+        // Local OpenAI-compatible servers usually don't need a key
+        (None, ChatProviderKind::OpenAi) => Ok(String::new()),
+        (None, ChatProviderKind::Anthropic) => Err("API key not set".to_string()),
+    }
+}
+
+#[derive(Clone, Default, Serialize)]
+struct ChatEvent {
+    event_type: String,
+    content: Option<String>,
+    tool_use: Option<ToolUseEvent>,
+    usage: Option<UsageEvent>,
+    error: Option<String>,
+    stop: Option<StopEvent>,
+    /// Seed the backend sampled with, sent with the `done` event (None if it ignores seeds)
+    seed: Option<u64>,
+}
+
+/// Why generation ended, sent with the `done` event
+#[derive(Clone, Default, Serialize)]
+struct StopEvent {
+    reason: Option<String>,
+    /// The stop sequence that matched, when reason is "stop_sequence"
+    sequence: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+struct ToolUseEvent {
+    id: String,
+    name: String,
+    input: serde_json::Value,
+}
+
+/// A streamed tool_use block whose input JSON is still arriving
+struct PendingToolUse {
+    id: String,
+    name: String,
+    input: String,
+}
+
+#[derive(Clone, Serialize)]
+struct UsageEvent {
+    input_tokens: u32,
+    output_tokens: u32,
+    cache_read_tokens: Option<u32>,
+    cache_write_tokens: Option<u32>,
+    /// Tier the request was actually served on, when the API reports it
+    service_tier: Option<String>,
+    /// Share of the prompt read from cache: cache_read / (cache_read + input).
+    /// Set on the final usage of a turn; None when there was no prompt.
+    cache_hit_ratio: Option<f64>,
+}
+
+impl UsageEvent {
+    /// Usage before any tokens are counted
+    fn zero() -> Self {
+        Self {
+            input_tokens: 0,
+            output_tokens: 0,
+            cache_read_tokens: Some(0),
+            cache_write_tokens: Some(0),
+            service_tier: None,
+            cache_hit_ratio: None,
+        }
+    }
+
+    fn with_cache_hit_ratio(mut self) -> Self {
+        let cache_read = self.cache_read_tokens.unwrap_or(0) as f64;
+        let prompt = cache_read + self.input_tokens as f64;
+        self.cache_hit_ratio = (prompt > 0.0).then(|| cache_read / prompt);
+        self
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatRequest {
+    messages: Vec<Message>,
+    system_prompt: String,
+    tools: Option<Vec<serde_json::Value>>,
+    /// Events are emitted as `chat-event-{stream_id}`. Use a fresh UUID per
+    /// request: ASCII letters, digits, '-' and '_', at most 64 characters
+    stream_id: String,
+    model: Option<String>,
+    /// Cache the system prompt and tools (sends cache_control + beta header). Default: true
+    #[serde(default = "default_true")]
+    prompt_caching: bool,
+    /// Message content blocks to mark as extra cache breakpoints (e.g. a pinned glossary)
+    #[serde(default)]
+    cache_breakpoints: Vec<CacheBreakpoint>,
+    /// Accumulate this turn's usage under a session (see get_session_usage)
+    session_id: Option<String>,
+    /// Image/PDF files to attach to the last user message
+    #[serde(default)]
+    attachments: Vec<String>,
+    /// Sampling temperature (0.0-1.0); API default when omitted
+    temperature: Option<f32>,
+    /// Nucleus sampling (0.0-1.0). Set temperature or top_p, not both
+    top_p: Option<f32>,
+    /// Strings that end generation when produced
+    #[serde(default)]
+    stop_sequences: Vec<String>,
+    /// Identifies the translator to the provider (Anthropic `metadata.user_id`,
+    /// OpenAI `user`), so rate limits and abuse reports can be traced to one
+    /// person instead of the whole team's key. Use an opaque id, not a name or email
+    user_id: Option<String>,
+    /// Send a BLAKE3 hash of `user_id` instead of the id itself
+    #[serde(default)]
+    hash_user_id: bool,
+    /// Processing tier, e.g. "auto" or "standard_only" for Anthropic. Omitted
+    /// (the API default) when unset
+    service_tier: Option<String>,
+    /// Start of the reply (e.g. "{" to force JSON). Sent as a trailing assistant
+    /// message that the model continues; streamed back as the first text event
+    prefill: Option<String>,
+    /// Drop the oldest turns instead of failing when the conversation won't fit
+    /// the model's context window
+    #[serde(default)]
+    auto_truncate: bool,
+    /// Sampling seed for reproducible output. OpenAI-compatible backends honor
+    /// it (best effort); Anthropic has no seed parameter and ignores it
+    seed: Option<u64>,
+}
+
+/// A message content block to cache up to
+#[derive(Clone, Deserialize)]
+struct CacheBreakpoint {
+    message_index: usize,
+    /// Content block within the message (default: last block)
+    block_index: Option<usize>,
+}
+
+// The API accepts at most this many cache_control blocks per request
+const MAX_CACHE_BREAKPOINTS: usize = 4;
+
+fn default_true() -> bool {
+    true
+}
+
+const MAX_STREAM_ID_LEN: usize = 64;
+
+/// A prefill becomes the final assistant message, so it has to follow a user turn
+fn validate_prefill(messages: &[Message], prefill: &str) -> Result<(), String> {
+    if prefill.trim().is_empty() {
+        return Err("prefill is empty".to_string());
+    }
+    match messages.last() {
+        Some(last) if last.role == "user" => Ok(()),
+        Some(last) => Err(format!(
+            "prefill needs the conversation to end with a user message, not {}",
+            last.role
+        )),
+        None => Err("prefill needs at least one user message".to_string()),
+    }
+}
+
+impl ChatRequest {
+    /// The user id to send, hashed if requested
+    fn provider_user_id(&self) -> Option<String> {
+        let user_id = self.user_id.as_deref().filter(|id| !id.trim().is_empty())?;
+        Some(if self.hash_user_id {
+            blake3::hash(user_id.as_bytes()).to_hex().to_string()
+        } else {
+            user_id.to_string()
+        })
+    }
+}
+
+/// Tokens reserved for the reply
+const MAX_OUTPUT_TOKENS: usize = 8192;
+
+/// Rough cost of an image block; the API downsizes larger images to about this
+const IMAGE_TOKEN_ESTIMATE: usize = 1_600;
+
+/// Rough token count (about 4 characters per token) of a JSON value's text.
+/// Images count a flat estimate; other base64 data (documents) isn't counted.
+fn estimate_json_tokens(value: &serde_json::Value) -> usize {
+    match value {
+        serde_json::Value::String(text) => text.chars().count().div_ceil(4),
+        serde_json::Value::Array(items) => items.iter().map(estimate_json_tokens).sum(),
+        serde_json::Value::Object(map) => match map.get("type").and_then(|t| t.as_str()) {
+            Some("image") => IMAGE_TOKEN_ESTIMATE,
+            Some("base64") => 0,
+            _ => map.values().map(estimate_json_tokens).sum(),
+        },
+        _ => 1,
+    }
+}
+
+/// Make sure the conversation fits `context_window` before paying for the request.
+/// With `auto_truncate`, drops the oldest turns until it fits and returns how many
+/// messages were dropped; otherwise fails with how many need trimming.
+fn check_context_window(request: &mut ChatRequest, context_window: usize) -> Result<usize, String> {
+    let fixed = request.system_prompt.chars().count().div_ceil(4)
+        + request.tools.as_ref().map_or(0, |tools| tools.iter().map(estimate_json_tokens).sum())
+        + MAX_OUTPUT_TOKENS;
+    let budget = context_window.saturating_sub(fixed);
+
+    let sizes: Vec<usize> = request
+        .messages
+        .iter()
+        .map(|m| estimate_json_tokens(&m.content))
+        .collect();
+    let total: usize = sizes.iter().sum();
+    if total <= budget {
+        return Ok(0);
+    }
+
+    // Oldest messages to drop so the rest fits, then keep dropping until the
+    // conversation starts on a plain user message (not a tool result, which
+    // would be orphaned from its tool call)
+    let mut drop = 0;
+    let mut remaining = total;
+    while drop < request.messages.len() && remaining > budget {
+        remaining -= sizes[drop];
+        drop += 1;
+    }
+    while drop < request.messages.len() && !starts_turn(&request.messages[drop]) {
+        remaining -= sizes[drop];
+        drop += 1;
+    }
+    if drop >= request.messages.len() {
+        return Err(format!(
+            "Context window exceeded: the latest turn doesn't fit the {}-token budget on its own",
+            budget
+        ));
+    }
+    if !request.auto_truncate {
+        return Err(format!(
+            "Context window exceeded: about {} tokens of messages for a {}-token budget; trim the oldest {} messages",
+            total, budget, drop
+        ));
+    }
+
+    request.messages.drain(..drop);
+    // Breakpoint indexes refer to the untrimmed list
+    request.cache_breakpoints.retain_mut(|breakpoint| {
+        breakpoint.message_index = match breakpoint.message_index.checked_sub(drop) {
+            Some(index) => index,
+            None => return false,
+        };
+        true
+    });
+    log::info!("Dropped {} oldest messages to fit the context window (~{} tokens left)", drop, remaining);
+    Ok(drop)
+}
+
+/// A user message that can open a conversation
+fn starts_turn(message: &Message) -> bool {
+    message.role == "user"
+        && !message
+            .content
+            .as_array()
+            .is_some_and(|blocks| blocks.iter().any(|b| b["type"].as_str() == Some("tool_result")))
+}
+
+/// Reject stream ids that would make an odd or ambiguous event name
+fn validate_stream_id(stream_id: &str) -> Result<(), String> {
+    if stream_id.is_empty() || stream_id.len() > MAX_STREAM_ID_LEN {
+        return Err(format!("stream_id must be 1-{} characters", MAX_STREAM_ID_LEN));
+    }
+    if !stream_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(format!("Invalid stream_id {:?}: use letters, digits, '-' or '_'", stream_id));
+    }
+    Ok(())
+}
+
+// Model constants
+const MODEL_HAIKU: &str = "claude-haiku-4-5-20251001";
+const MODEL_SONNET: &str = "claude-sonnet-4-5-20250929";
+
+fn select_model(requested: Option<&str>) -> &'static str {
+    // Accept either an alias ("haiku") or a full model id from list_models
+    requested
+        .and_then(|r| MODELS.iter().find(|m| m.alias == r || m.id == r))
+        .map(|m| m.id)
+        .unwrap_or(MODEL_SONNET) // Default to Sonnet
+}
+
+/// USD per million tokens
+#[derive(Clone, Copy, Serialize)]
+struct ModelPricing {
+    input: f64,
+    output: f64,
+    cache_write: f64,
+    cache_read: f64,
+}
+
+/// A model the chat can use, as shown in the model picker
+#[derive(Clone, Copy, Serialize)]
+struct ModelInfo {
+    id: &'static str,
+    /// Short name accepted in ChatRequest.model
+    alias: &'static str,
+    display_name: &'static str,
+    context_window: u32,
+    pricing: ModelPricing,
+    supports_extended_thinking: bool,
+}
+
+const MODELS: &[ModelInfo] = &[
+    ModelInfo {
+        id: MODEL_SONNET,
+        alias: "sonnet",
+        display_name: "Claude Sonnet 4.5",
+        context_window: 200_000,
+        pricing: ModelPricing { input: 3.0, output: 15.0, cache_write: 3.75, cache_read: 0.30 },
+        supports_extended_thinking: true,
+    },
+    ModelInfo {
+        id: MODEL_HAIKU,
+        alias: "haiku",
+        display_name: "Claude Haiku 4.5",
+        context_window: 200_000,
+        pricing: ModelPricing { input: 1.0, output: 5.0, cache_write: 1.25, cache_read: 0.10 },
+        supports_extended_thinking: true,
+    },
+];
+
+/// Prices of a Claude model; None for anything else (e.g. an OpenAI-compatible server's)
+fn model_pricing(model: &str) -> Option<ModelPricing> {
+    MODELS.iter().find(|m| m.id == model).map(|m| m.pricing)
+}
+
+#[tauri::command]
+fn list_models() -> Vec<ModelInfo> {
+    MODELS.to_vec()
+}
+
+/// Running token totals and estimated cost for a chat session
+#[derive(Clone, Serialize)]
+struct SessionUsage {
+    turns: u32,
+    input_tokens: u64,
+    output_tokens: u64,
+    cache_read_tokens: u64,
+    cache_write_tokens: u64,
+    /// None once a turn used a model without known pricing
+    estimated_cost_usd: Option<f64>,
+    /// Consecutive turns that wrote to the cache without reading from it
+    #[serde(skip)]
+    writes_without_read: u32,
+}
+
+/// Turns of cache writes with no read before the cache is reported as unused
+const CACHE_MISS_STREAK_WARNING: u32 = 3;
+
+impl Default for SessionUsage {
+    fn default() -> Self {
+        Self {
+            turns: 0,
+            input_tokens: 0,
+            output_tokens: 0,
+            cache_read_tokens: 0,
+            cache_write_tokens: 0,
+            estimated_cost_usd: Some(0.0),
+            writes_without_read: 0,
+        }
+    }
+}
+
+impl SessionUsage {
+    fn add_turn(&mut self, model: &str, usage: &UsageEvent) {
+        let cache_read = usage.cache_read_tokens.unwrap_or(0) as u64;
+        let cache_write = usage.cache_write_tokens.unwrap_or(0) as u64;
+
+        self.turns += 1;
+        self.input_tokens += usage.input_tokens as u64;
+        self.output_tokens += usage.output_tokens as u64;
+        self.cache_read_tokens += cache_read;
+        self.cache_write_tokens += cache_write;
+        self.estimated_cost_usd = self.estimated_cost_usd.zip(model_pricing(model)).map(|(cost, pricing)| {
+            cost + (usage.input_tokens as f64 * pricing.input
+                + usage.output_tokens as f64 * pricing.output
+                + cache_write as f64 * pricing.cache_write
+                + cache_read as f64 * pricing.cache_read)
+                / 1_000_000.0
+        });
+
+        if cache_read > 0 {
+            self.writes_without_read = 0;
+        } else if cache_write > 0 {
+            self.writes_without_read += 1;
+        }
+    }
+
+    /// Whether the cache has been written on every one of the last few turns
+    /// and never read, i.e. the prefix changes between turns. Reported once per streak.
+    fn cache_never_reused(&self) -> bool {
+        self.writes_without_read == CACHE_MISS_STREAK_WARNING
+    }
+}
+
+struct SessionUsageState(Mutex<HashMap<String, SessionUsage>>);
+
+/// Whether each chat turn is written to the turn log (off by default)
+struct ChatLogState(Mutex<bool>);
+
+const CHAT_LOG_FILE: &str = "chat-turns.jsonl";
+const CHAT_LOG_MAX_BYTES: u64 = 5 * 1024 * 1024;
+
+#[derive(Clone, Deserialize, Serialize)]
+struct Message {
+    role: String,
+    content: serde_json::Value,
+}
+
+// API limits for base64 attachments
+const MAX_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+const MAX_DOCUMENT_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Build a base64 image or document content block from a file
+fn attachment_block(path: &str) -> Result<serde_json::Value, String> {
+    use base64::Engine;
+
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .unwrap_or_default();
+    let (block_type, media_type, max_bytes) = match extension.as_str() {
+        "png" => ("image", "image/png", MAX_IMAGE_BYTES),
+        "jpg" | "jpeg" => ("image", "image/jpeg", MAX_IMAGE_BYTES),
+        "gif" => ("image", "image/gif", MAX_IMAGE_BYTES),
+        "webp" => ("image", "image/webp", MAX_IMAGE_BYTES),
+        "pdf" => ("document", "application/pdf", MAX_DOCUMENT_BYTES),
+        _ => return Err(format!("Unsupported attachment type: {}", path)),
+    };
+
+    let size = std::fs::metadata(path)
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?
+        .len();
+    if size > max_bytes {
+        return Err(format!(
+            "{} is {} bytes; {} attachments are limited to {} bytes",
+            path, size, block_type, max_bytes
+        ));
+    }
+
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    Ok(serde_json::json!({
+        "type": block_type,
+        "source": {
+            "type": "base64",
+            "media_type": media_type,
+            "data": base64::engine::general_purpose::STANDARD.encode(bytes),
+        },
+    }))
+}
+
+/// Put attachment blocks ahead of the text of the last user message
+fn attach_to_last_user_message(
+    messages: &mut [Message],
+    blocks: Vec<serde_json::Value>,
+) -> Result<(), String> {
+    let message = messages
+        .iter_mut()
+        .rev()
+        .find(|m| m.role == "user")
+        .ok_or("No user message to attach files to")?;
+
+    let existing = match message.content.take() {
+        serde_json::Value::String(text) => vec![serde_json::json!({ "type": "text", "text": text })],
+        serde_json::Value::Array(existing) => existing,
+        _ => Vec::new(),
+    };
+    message.content = serde_json::Value::Array(blocks.into_iter().chain(existing).collect());
+    Ok(())
+}
+
+// ============================================================================
+// MCP Server Commands
+// ============================================================================
+
+fn find_python() -> Result<String, String> {
+    let candidates = [
+        "/opt/homebrew/bin/python3",
+        "/usr/local/bin/python3",
+        "python3",
+        "/usr/bin/python3",
+    ];
+
+    let mut tried = Vec::new();
+
+    for candidate in candidates {
+        if candidate.starts_with('/') {
+            if Path::new(candidate).exists() {
+                return Ok(candidate.to_string());
+            }
+            tried.push(format!("{} (not found)", candidate));
+        } else {
+            match Command::new(candidate).arg("--version").output() {
+                Ok(_) => return Ok(candidate.to_string()),
+                Err(e) => tried.push(format!("{} ({})", candidate, e)),
+            }
+        }
+    }
+    Err(format!("Python not found. Tried: {}", tried.join(", ")))
+}
+
+#[tauri::command]
+async fn spawn_mcp_server(app: AppHandle) -> Result<String, String> {
+    let handle = app.clone();
+    run_mcp(&app, move |state| start_mcp_server(handle, state)).await
+}
+
+/// Start the server and wait for it to answer initialize
+fn start_mcp_server(app: AppHandle, state: &McpState) -> Result<String, String> {
+    let mut server = state.lock()?;
+
+    if server.child.is_some() {
+        return Ok("Server already running".to_string());
+    }
+
+    let python = find_python()?;
+
+    let check = Command::new(&python)
+        .args(["-c", "import mcp_server_sdlxliff"])
+        .output()
+        .map_err(|e| format!("Failed to check for mcp_server_sdlxliff: {}", e))?;
+
+    if !check.status.success() {
+        let stderr = String::from_utf8_lossy(&check.stderr);
+        return Err(format!(
+            "mcp-server-sdlxliff not installed for {}. Run: pip install mcp-server-sdlxliff\nError: {}",
+            python, stderr.trim()
+        ));
+    }
+
+    let mut child = Command::new(&python)
+        .args(["-m", "mcp_server_sdlxliff"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to spawn MCP server with {}: {}", python, e))?;
+
+    let stdin = child.stdin.take().ok_or("Failed to get stdin")?;
+    let stdout = child.stdout.take().ok_or("Failed to get stdout")?;
+
+    // Fresh routing table so a previous server's reader can't answer for this one
+    let pending = Arc::new(Mutex::new(McpPending::default()));
+    let reader_pending = pending.clone();
+    let reader_trace = server.trace.clone();
+    server.app = Some(app.clone());
+    std::thread::spawn(move || run_mcp_reader(app, stdout, reader_pending, reader_trace));
+
+    server.child = Some(child);
+    server.stdin = Some(stdin);
+    server.pending = pending;
+    server.tools = None;
+    drop(server);
+
+    // Don't return until the server answers, so the first mcp_request can't race its startup
+    if let Err(e) = state.initialize("sdlxliff-editor", Some(MCP_STARTUP_TIMEOUT)) {
+        state.lock()?.shutdown();
+        return Err(format!("MCP server did not become ready: {}", e));
+    }
+    // Complete the handshake, so a server restarted behind the frontend's back
+    // accepts requests straight away
+    state
+        .lock()?
+        .send(&serde_json::json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }).to_string())?;
+
+    Ok("MCP server started".to_string())
+}
+
+#[tauri::command]
+async fn mcp_request(
+    app: AppHandle,
+    file_locks: State<'_, FileLocks>,
+    message: String,
+) -> Result<String, String> {
+    let message: serde_json::Value =
+        serde_json::from_str(&message).map_err(|e| format!("Invalid JSON-RPC message: {}", e))?;
+    let is_initialize = message["method"].as_str() == Some("initialize");
+    let _guards = file_locks.acquire_for_request(&message).await?;
+
+    run_mcp(&app, move |state| {
+        let response = state.request(message, None)?;
+
+        // Remember what the server supports when the frontend does the handshake
+        if is_initialize {
+            state.lock()?.capabilities = Some(McpCapabilities::from_initialize_result(&response["result"]));
+        }
+
+        Ok(response.to_string())
+    })
+    .await
+}
+
+/// JSON-RPC "internal error", for requests in a batch that got no response
+const JSONRPC_INTERNAL_ERROR: i64 = -32603;
+
+/// Send `messages` (a JSON array of requests) together and return their
+/// responses, in the same order, as a JSON array. MCP has no JSON-RPC batches,
+/// so each request is written on its own with its own id; they're pipelined,
+/// not waited on one by one. A request that fails in transport gets an error
+/// response rather than failing the rest.
+#[tauri::command]
+async fn mcp_request_batch(
+    app: AppHandle,
+    file_locks: State<'_, FileLocks>,
+    messages: String,
+) -> Result<String, String> {
+    let messages: Vec<serde_json::Value> =
+        serde_json::from_str(&messages).map_err(|e| format!("Invalid JSON-RPC batch: {}", e))?;
+    if messages.is_empty() {
+        return Err("JSON-RPC batch must not be empty".to_string());
+    }
+    let mut ids = HashSet::new();
+    if !messages.iter().all(|message| !message["id"].is_null() && ids.insert(message["id"].to_string())) {
+        return Err("Every request in a batch needs its own id".to_string());
+    }
+
+    let requests = messages.into_iter().map(|message| {
+        let id = message["id"].clone();
+        let (app, file_locks) = (&app, &file_locks);
+        async move {
+            let response = async {
+                let _guards = file_locks.acquire_for_request(&message).await?;
+                run_mcp(app, move |state| state.request(message, None)).await
+            };
+            response.await.unwrap_or_else(|e: String| {
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": JSONRPC_INTERNAL_ERROR, "message": e },
+                })
+            })
+        }
+    });
+    let responses = futures::future::join_all(requests).await;
+    Ok(serde_json::Value::Array(responses).to_string())
+}
+
+const MCP_PING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Round-trip a JSON-RPC ping; returns the latency in milliseconds
+#[tauri::command]
+async fn ping_mcp(app: AppHandle) -> Result<u64, String> {
+    run_mcp(&app, |state| {
+        if !state.is_running()? {
+            return Err("MCP server is not running".to_string());
+        }
+
+        let started = std::time::Instant::now();
+        state.call_with_timeout("ping", serde_json::json!({}), Some(MCP_PING_TIMEOUT))?;
+        Ok(started.elapsed().as_millis() as u64)
+    })
+    .await
+}
+
+/// Read-only tool test_mcp_roundtrip calls when given a file
+const MCP_ROUNDTRIP_TOOL: &str = "get_sdlxliff_statistics";
+/// Per-request limit for the round-trip check; reading a large file can take a while
+const MCP_ROUNDTRIP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Serialize)]
+struct McpRoundtripReport {
+    passed: bool,
+    /// Tool called on `file_path`, if one was given
+    tool: Option<&'static str>,
+    /// Time for the whole check
+    latency_ms: u64,
+    /// What passed, or what went wrong
+    message: String,
+}
+
+/// Smoke-test the tool pipeline before a session: ping the server and check
+/// that tools/list returns well-formed tools including the read-only
+/// `MCP_ROUNDTRIP_TOOL`. With `file_path` that tool must also succeed on the
+/// file and return JSON.
+#[tauri::command]
+async fn test_mcp_roundtrip(app: AppHandle, file_path: Option<String>) -> Result<McpRoundtripReport, AppError> {
+    run_mcp(&app, move |state| mcp_roundtrip_report(state, file_path.as_deref())).await
+}
+
+fn mcp_roundtrip_report(state: &McpState, file_path: Option<&str>) -> Result<McpRoundtripReport, AppError> {
+    if !state.is_running()? {
+        return Err(AppError::NotInitialized("MCP server is not running".to_string()));
+    }
+
+    let started = std::time::Instant::now();
+    let outcome = mcp_roundtrip(state, file_path);
+    let latency_ms = started.elapsed().as_millis() as u64;
+    let (passed, message) = match outcome {
+        Ok(message) => (true, message),
+        Err(message) => (false, message),
+    };
+    Ok(McpRoundtripReport {
+        passed,
+        tool: file_path.map(|_| MCP_ROUNDTRIP_TOOL),
+        latency_ms,
+        message,
+    })
+}
+
+fn mcp_roundtrip(state: &McpState, file_path: Option<&str>) -> Result<String, String> {
+    let result = state.call_with_timeout("ping", serde_json::json!({}), Some(MCP_PING_TIMEOUT))?;
+    if !result.is_object() {
+        return Err(format!("Malformed ping result: {}", result));
+    }
+
+    let result = state.call_with_timeout("tools/list", serde_json::json!({}), Some(MCP_ROUNDTRIP_TIMEOUT))?;
+    let list: McpToolList =
+        serde_json::from_value(result).map_err(|e| format!("Malformed tools/list result: {}", e))?;
+    if let Some(tool) = list.tools.iter().find(|tool| !tool.input_schema.is_object()) {
+        return Err(format!("Tool {} has no input schema", tool.name));
+    }
+    if !list.tools.iter().any(|tool| tool.name == MCP_ROUNDTRIP_TOOL) {
+        return Err(format!("The server doesn't offer {}", MCP_ROUNDTRIP_TOOL));
+    }
+
+    let Some(path) = file_path else {
+        return Ok(format!("Server answered ping and listed {} tools", list.tools.len()));
+    };
+    let result = state.call_with_timeout(
+        "tools/call",
+        serde_json::json!({ "name": MCP_ROUNDTRIP_TOOL, "arguments": { "file_path": path } }),
+        Some(MCP_ROUNDTRIP_TIMEOUT),
+    )?;
+    let result: McpToolResult =
+        serde_json::from_value(result).map_err(|e| format!("Malformed tools/call result: {}", e))?;
+    let text = result
+        .content
+        .iter()
+        .find_map(|block| block["text"].as_str())
+        .ok_or("The tool returned no text content")?;
+    if result.is_error {
+        return Err(format!("{} failed on {}: {}", MCP_ROUNDTRIP_TOOL, path, text));
+    }
+    serde_json::from_str::<serde_json::Value>(text)
+        .map_err(|e| format!("{} returned invalid JSON for {}: {}", MCP_ROUNDTRIP_TOOL, path, e))?;
+    Ok(format!("{} read {}", MCP_ROUNDTRIP_TOOL, path))
+}
+
+/// Ask the server to send `mcp-log` messages at `level` and above
+#[tauri::command]
+async fn set_mcp_log_level(app: AppHandle, level: String) -> Result<(), String> {
+    if !MCP_LOG_LEVELS.contains(&level.as_str()) {
+        return Err(format!(
+            "Invalid log level: {} (expected one of {})",
+            level,
+            MCP_LOG_LEVELS.join(", ")
+        ));
+    }
+
+    run_mcp(&app, move |state| {
+        state.call("logging/setLevel", serde_json::json!({ "level": level }))?;
+        Ok(())
+    })
+    .await
+}
+
+/// Set how transport failures of MCP requests are retried
+#[tauri::command]
+fn set_mcp_retry(state: State<McpState>, config: McpRetryConfig) -> Result<(), String> {
+    if config.timeout_secs == 0 {
+        return Err("timeout_secs must be at least 1".to_string());
+    }
+    let mut server = state.0.lock().map_err(|e| e.to_string())?;
+    server.retry = config;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_mcp_retry(state: State<McpState>) -> Result<McpRetryConfig, String> {
+    let server = state.0.lock().map_err(|e| e.to_string())?;
+    Ok(server.retry.clone())
+}
+
+/// Capabilities from the last initialize handshake, or None before one has happened
+#[tauri::command]
+fn get_mcp_capabilities(state: State<McpState>) -> Result<Option<McpCapabilities>, String> {
+    let server = state.0.lock().map_err(|e| e.to_string())?;
+    Ok(server.capabilities.clone())
+}
+
+#[derive(Deserialize, Serialize)]
+struct McpResource {
+    uri: String,
+    name: String,
+    description: Option<String>,
+    #[serde(rename(deserialize = "mimeType"))]
+    mime_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct McpResourceList {
+    resources: Vec<McpResource>,
+    #[serde(rename = "nextCursor")]
+    next_cursor: Option<String>,
+}
+
+/// One item of a resources/read result; text resources set `text`, binary ones `blob` (base64)
+#[derive(Deserialize, Serialize)]
+struct McpResourceContents {
+    uri: String,
+    #[serde(rename(deserialize = "mimeType"))]
+    mime_type: Option<String>,
+    text: Option<String>,
+    blob: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct McpResourceRead {
+    contents: Vec<McpResourceContents>,
+}
+
+/// All resources the server exposes, following pagination cursors
+#[tauri::command]
+async fn list_mcp_resources(app: AppHandle) -> Result<Vec<McpResource>, String> {
+    run_mcp(&app, list_resources).await
+}
+
+fn list_resources(state: &McpState) -> Result<Vec<McpResource>, String> {
+    let mut resources = Vec::new();
+    let mut cursor: Option<String> = None;
+
+    loop {
+        let params = match &cursor {
+            Some(cursor) => serde_json::json!({ "cursor": cursor }),
+            None => serde_json::json!({}),
+        };
+        let result = state.call("resources/list", params)?;
+        let page: McpResourceList =
+            serde_json::from_value(result).map_err(|e| format!("Invalid resources/list result: {}", e))?;
+        resources.extend(page.resources);
+
+        match page.next_cursor {
+            Some(next) => cursor = Some(next),
+            None => return Ok(resources),
+        }
+    }
+}
+
+#[tauri::command]
+async fn read_mcp_resource(app: AppHandle, uri: String) -> Result<Vec<McpResourceContents>, String> {
+    let result = run_mcp(&app, move |state| state.call("resources/read", serde_json::json!({ "uri": uri }))).await?;
+    let read: McpResourceRead =
+        serde_json::from_value(result).map_err(|e| format!("Invalid resources/read result: {}", e))?;
+    Ok(read.contents)
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct McpTool {
+    name: String,
+    description: Option<String>,
+    /// JSON Schema for the tool's arguments
+    #[serde(rename(deserialize = "inputSchema"))]
+    input_schema: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct McpToolList {
+    tools: Vec<McpTool>,
+    #[serde(rename = "nextCursor")]
+    next_cursor: Option<String>,
+}
+
+/// One tool's definition (e.g. its input schema for a custom form), from the cached tool list
+#[tauri::command]
+async fn get_mcp_tool(app: AppHandle, name: String) -> Result<McpTool, AppError> {
+    run_mcp(&app, move |state| {
+        if !state.is_running()? {
+            return Err(AppError::NotInitialized("MCP server is not running".to_string()));
+        }
+        state
+            .tools()?
+            .into_iter()
+            .find(|tool| tool.name == name)
+            .ok_or_else(|| AppError::NotFound(format!("MCP tool not found: {}", name)))
+    })
+    .await
+}
+
+/// A tools/call result: MCP content blocks (text, image, resource)
+#[derive(Deserialize, Serialize)]
+struct McpToolResult {
+    #[serde(default)]
+    content: Vec<serde_json::Value>,
+    /// The tool ran but reported failure; `content` describes why
+    #[serde(default, rename(deserialize = "isError"))]
+    is_error: bool,
+}
+
+/// Call a tool directly with explicit arguments, outside the chat loop
+/// (e.g. a "list segments" button)
+#[tauri::command]
+async fn call_mcp_tool(
+    app: AppHandle,
+    file_locks: State<'_, FileLocks>,
+    name: String,
+    arguments: Option<serde_json::Value>,
+) -> Result<McpToolResult, AppError> {
+    let arguments = arguments.unwrap_or_else(|| serde_json::json!({}));
+    if !arguments.is_object() {
+        return Err(AppError::Other("Tool arguments must be a JSON object".to_string()));
+    }
+
+    let _guards = file_locks.acquire(&name, &arguments).await?;
+    let result = run_mcp(&app, move |state| {
+        if !state.is_running()? {
+            return Err(AppError::NotInitialized("MCP server is not running".to_string()));
+        }
+        Ok(state.call("tools/call", serde_json::json!({ "name": name, "arguments": arguments }))?)
+    })
+    .await?;
+    serde_json::from_value(result).map_err(|e| AppError::Other(format!("Invalid tools/call result: {}", e)))
+}
+
+#[derive(Deserialize, Serialize)]
+struct McpPromptArgument {
+    name: String,
+    description: Option<String>,
+    #[serde(default)]
+    required: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct McpPrompt {
+    name: String,
+    description: Option<String>,
+    #[serde(default)]
+    arguments: Vec<McpPromptArgument>,
+}
+
+#[derive(Deserialize)]
+struct McpPromptList {
+    prompts: Vec<McpPrompt>,
+    #[serde(rename = "nextCursor")]
+    next_cursor: Option<String>,
+}
+
+/// A rendered prompt message; content is an MCP content block (text, image or resource)
+#[derive(Deserialize, Serialize)]
+struct McpPromptMessage {
+    role: String,
+    content: serde_json::Value,
+}
+
+#[derive(Deserialize, Serialize)]
+struct McpRenderedPrompt {
+    description: Option<String>,
+    messages: Vec<McpPromptMessage>,
+}
+
+/// All prompt templates the server offers, following pagination cursors
+#[tauri::command]
+async fn list_mcp_prompts(app: AppHandle) -> Result<Vec<McpPrompt>, String> {
+    run_mcp(&app, list_prompts).await
+}
+
+fn list_prompts(state: &McpState) -> Result<Vec<McpPrompt>, String> {
+    let mut prompts = Vec::new();
+    let mut cursor: Option<String> = None;
+
+    loop {
+        let params = match &cursor {
+            Some(cursor) => serde_json::json!({ "cursor": cursor }),
+            None => serde_json::json!({}),
+        };
+        let result = state.call("prompts/list", params)?;
+        let page: McpPromptList =
+            serde_json::from_value(result).map_err(|e| format!("Invalid prompts/list result: {}", e))?;
+        prompts.extend(page.prompts);
+
+        match page.next_cursor {
+            Some(next) => cursor = Some(next),
+            None => return Ok(prompts),
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_mcp_prompt(
+    app: AppHandle,
+    name: String,
+    args: Option<HashMap<String, String>>,
+) -> Result<McpRenderedPrompt, String> {
+    let params = serde_json::json!({ "name": name, "arguments": args.unwrap_or_default() });
+    let result = run_mcp(&app, move |state| state.call("prompts/get", params)).await?;
+    serde_json::from_value(result).map_err(|e| format!("Invalid prompts/get result: {}", e))
+}
+
+/// Turn the raw MCP traffic trace on or off. Frames are sent as "mcp-trace"
+/// events too when `live` is set; disabling clears what was recorded.
+#[tauri::command]
+fn set_mcp_trace(
+    app: AppHandle,
+    state: State<McpState>,
+    enabled: bool,
+    live: Option<bool>,
+    capacity: Option<usize>,
+) -> Result<(), String> {
+    let server = state.0.lock().map_err(|e| e.to_string())?;
+    let mut trace = server.trace.lock().map_err(|e| e.to_string())?;
+    if capacity == Some(0) {
+        return Err("capacity must be at least 1".to_string());
+    }
+
+    trace.enabled = enabled;
+    trace.live = (enabled && live.unwrap_or(false)).then_some(app);
+    trace.capacity = capacity.unwrap_or(DEFAULT_MCP_TRACE_CAPACITY);
+    if enabled {
+        let excess = trace.frames.len().saturating_sub(trace.capacity);
+        trace.frames.drain(..excess);
+    } else {
+        trace.frames.clear();
+    }
+    Ok(())
+}
+
+/// Frames recorded since tracing was enabled, oldest first
+#[tauri::command]
+fn get_mcp_trace(state: State<McpState>) -> Result<Vec<McpTraceFrame>, String> {
+    let server = state.0.lock().map_err(|e| e.to_string())?;
+    let trace = server.trace.lock().map_err(|e| e.to_string())?;
+    if !trace.enabled {
+        return Err("MCP tracing is not enabled".to_string());
+    }
+    Ok(trace.frames.iter().cloned().collect())
+}
+
+#[tauri::command]
+fn mcp_notify(state: State<McpState>, message: String) -> Result<(), String> {
+    let mut server = state.0.lock().map_err(|e| e.to_string())?;
+    server.send(&message)
+}
+
+#[tauri::command]
+async fn stop_mcp_server(app: AppHandle) -> Result<String, String> {
+    run_mcp(&app, |state| {
+        if state.lock()?.shutdown() {
+            Ok("MCP server stopped".to_string())
+        } else {
+            Ok("No server running".to_string())
+        }
+    })
+    .await
+}
+
+// ============================================================================
+// Anthropic API Commands
+// ============================================================================
+
+// OS credential store entry for persisting the API key between launches
+const KEYCHAIN_SERVICE: &str = "com.sdlxliff.editor";
+const KEYCHAIN_ACCOUNT: &str = "anthropic-api-key";
+
+fn keychain_entry() -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT)
+        .map_err(|e| format!("Keychain unavailable: {}", e))
+}
+
+/// Restore a previously saved API key from the OS keychain
+fn load_api_key_from_keychain() -> Option<String> {
+    let entry = keychain_entry().ok()?;
+    match entry.get_password() {
+        Ok(key) => {
+            log::info!("Restored API key from keychain");
+            Some(key)
+        }
+        Err(keyring::Error::NoEntry) => None,
+        Err(e) => {
+            log::warn!("Failed to read API key from keychain: {}", e);
+            None
+        }
+    }
+}
+
+/// Non-empty value of an environment variable
+fn env_key(name: &str) -> Option<String> {
+    std::env::var(name).ok().map(|key| key.trim().to_string()).filter(|key| !key.is_empty())
+}
+
+/// Use ANTHROPIC_API_KEY and OPENAI_API_KEY from the environment, for dev and
+/// automated runs. The Anthropic key takes precedence over one saved in the
+/// keychain; set_api_key and rag_init still replace either afterwards.
+fn load_api_keys_from_env(app: &AppHandle) {
+    if let Some(key) = env_key("ANTHROPIC_API_KEY") {
+        if let Ok(mut api_key) = app.state::<ApiKeyState>().0.lock() {
+            *api_key = Some(key);
+            log::info!("Loaded Anthropic API key from ANTHROPIC_API_KEY");
+        }
+    }
+
+    if let Some(key) = env_key("OPENAI_API_KEY") {
+        let http = app.state::<HttpClientState>().client();
+        match rag::init_client(&app.state::<RagState>(), http, Some(key), false, None, None) {
+            Ok(()) => log::info!("Initialized OpenAI embeddings with OPENAI_API_KEY"),
+            Err(e) => log::warn!("Failed to initialize embeddings from OPENAI_API_KEY: {}", e),
+        }
+    }
+}
+
+/// Remove the saved API key. A keychain that doesn't exist or can't be reached
+/// holds no key, so that counts as success too.
+fn delete_keychain_key() -> Result<(), String> {
+    let entry = match keychain_entry() {
+        Ok(entry) => entry,
+        Err(e) => {
+            log::info!("{}; no saved API key to remove", e);
+            return Ok(());
+        }
+    };
+    match entry.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e @ (keyring::Error::NoStorageAccess(_) | keyring::Error::PlatformFailure(_))) => {
+            log::info!("Keychain unavailable ({}); no saved API key to remove", e);
+            Ok(())
+        }
+        Err(e) => Err(format!("Failed to remove API key from keychain: {}", e)),
+    }
+}
+
+/// Set the API key for this session. `persist: true` also saves it to the OS
+/// keychain; `persist: false` removes any saved key; omitted leaves the keychain alone.
+#[tauri::command]
+fn set_api_key(state: State<ApiKeyState>, key: String, persist: Option<bool>) -> Result<(), String> {
+    match persist {
+        Some(true) => keychain_entry()?
+            .set_password(&key)
+            .map_err(|e| format!("Failed to save API key to keychain: {}", e))?,
+        Some(false) => delete_keychain_key()?,
+        None => {}
+    }
+
+    let mut api_key = state.0.lock().map_err(|e| e.to_string())?;
+    *api_key = Some(key);
+    Ok(())
+}
+
+/// Clear the API key from memory and the OS keychain
+#[tauri::command]
+fn clear_api_key(state: State<ApiKeyState>) -> Result<(), String> {
+    {
+        let mut api_key = state.0.lock().map_err(|e| e.to_string())?;
+        *api_key = None;
+    }
+
+    delete_keychain_key()
+}
+
+#[tauri::command]
+fn has_api_key(state: State<ApiKeyState>) -> bool {
+    state.0.lock().map(|k| k.is_some()).unwrap_or(false)
+}
+
+/// Check an API key with a free token-count request.
+/// Validates `key` if given, otherwise the currently set key.
+/// Returns Ok(false) when the API rejects the key (401).
+#[tauri::command]
+async fn validate_api_key(
+    state: State<'_, ApiKeyState>,
+    config_state: State<'_, ApiConfigState>,
+    http_state: State<'_, HttpClientState>,
+    key: Option<String>,
+) -> Result<bool, AppError> {
+    let api_key = match key {
+        Some(key) => key,
+        None => {
+            let key_guard = state.0.lock().map_err(|e| e.to_string())?;
+            key_guard
+                .clone()
+                .ok_or_else(|| AppError::NotInitialized("API key not set".to_string()))?
+        }
+    };
+
+    let config = config_state.0.lock().map_err(|e| e.to_string())?.clone();
+    check_api_key(&http_state.client(), &config, &api_key).await
+}
+
+/// Ok(false) if the key is rejected, Err for anything else that goes wrong
+async fn check_api_key(client: &Client, config: &ApiConfig, api_key: &str) -> Result<bool, AppError> {
+    let response = config
+        .provider
+        .provider(config)
+        .key_check(client, config, api_key)
+        .timeout(std::time::Duration::from_secs(10))
+        .send()
+        .await
+        .map_err(|e| AppError::request("Validation request failed", e))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(true)
+    } else if status.as_u16() == 401 {
+        Ok(false)
+    } else {
+        let error_body = response.text().await.unwrap_or_default();
+        Err(AppError::from_status(status, format!("API error {}: {}", status, error_body)))
+    }
+}
+
+#[tauri::command]
+fn get_api_config(config_state: State<ApiConfigState>) -> Result<ApiConfig, String> {
+    Ok(config_state.0.lock().map_err(|e| e.to_string())?.clone())
+}
+
+/// Override the API base URL (e.g. a corporate gateway), attach extra headers,
+/// or pin the API version and enable beta features
+#[tauri::command]
+fn set_api_config(config_state: State<ApiConfigState>, config: ApiConfig) -> Result<(), String> {
+    if !config.base_url.starts_with("https://") && !config.base_url.starts_with("http://") {
+        return Err(format!("Invalid base URL: {}", config.base_url));
+    }
+    for (name, value) in &config.extra_headers {
+        reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid header name: {}", name))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| format!("Invalid value for header {}", name))?;
+    }
+    if config.anthropic_version.trim().is_empty() {
+        return Err("anthropic_version must not be empty".to_string());
+    }
+    for beta in config.anthropic_betas.iter().chain(&config.prompt_caching_beta) {
+        if beta.is_empty() || beta.contains(',') || reqwest::header::HeaderValue::from_str(beta).is_err() {
+            return Err(format!("Invalid beta name: {:?}", beta));
+        }
+    }
+    reqwest::header::HeaderValue::from_str(&config.anthropic_version)
+        .map_err(|_| format!("Invalid anthropic_version: {}", config.anthropic_version))?;
+
+    let mut current = config_state.0.lock().map_err(|e| e.to_string())?;
+    *current = config;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_http_config() -> http::HttpConfig {
+    http::config()
+}
+
+/// Settings file shared with the frontend's plugin-store settings
+const SETTINGS_STORE: &str = "settings.json";
+const HTTP_CONFIG_KEY: &str = "httpConfig";
+
+/// Route outbound requests through a proxy and/or trust extra root certificates.
+/// Saved, and applied again on the next launch.
+#[tauri::command]
+fn set_http_config(
+    app: AppHandle,
+    http_state: State<HttpClientState>,
+    rag_state: State<RagState>,
+    config: http::HttpConfig,
+) -> Result<(), String> {
+    let saved = serde_json::to_value(&config).map_err(|e| e.to_string())?;
+    http::set_config(config)?;
+    let client = http_state.rebuild()?;
+    rag::set_http_client(&rag_state, client)?;
+
+    let store = app.store(SETTINGS_STORE).map_err(|e| e.to_string())?;
+    store.set(HTTP_CONFIG_KEY, saved);
+    store.save().map_err(|e| format!("Failed to save HTTP settings: {}", e))
+}
+
+/// Apply the HTTP settings saved by set_http_config, if any
+fn load_http_config(app: &AppHandle) {
+    let saved = match app.store(SETTINGS_STORE) {
+        Ok(store) => store.get(HTTP_CONFIG_KEY),
+        Err(e) => {
+            log::warn!("Settings store not loaded: {}", e);
+            return;
+        }
+    };
+    let Some(saved) = saved else {
+        return;
+    };
+    let result = serde_json::from_value(saved)
+        .map_err(|e| e.to_string())
+        .and_then(http::set_config)
+        .and_then(|()| app.state::<HttpClientState>().rebuild())
+        .and_then(|client| rag::set_http_client(&app.state::<RagState>(), client));
+    match result {
+        Ok(()) => log::info!("Applied saved HTTP settings"),
+        // e.g. a CA certificate that has since moved; the defaults stay in place
+        Err(e) => log::warn!("Saved HTTP settings not applied: {}", e),
+    }
+}
+
+#[tauri::command]
+async fn chat_stream(
+    app: AppHandle,
+    state: State<'_, ApiKeyState>,
+    config_state: State<'_, ApiConfigState>,
+    http_state: State<'_, HttpClientState>,
+    request: ChatRequest,
+) -> Result<(), String> {
+    validate_stream_id(&request.stream_id)?;
+    let config = config_state.0.lock().map_err(|e| e.to_string())?.clone();
+    let api_key = chat_api_key(&state, &config)?;
+
+    for (name, value) in [("temperature", request.temperature), ("top_p", request.top_p)] {
+        if let Some(value) = value {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(format!("{} must be between 0.0 and 1.0, got {}", name, value));
+            }
+        }
+    }
+    if request.temperature.is_some() && request.top_p.is_some() {
+        log::warn!("Both temperature and top_p set; usually only one should be adjusted");
+    }
+    if let Some(prefill) = &request.prefill {
+        validate_prefill(&request.messages, prefill)?;
+    }
+
+    let client = http_state.client();
+    let stream_id = request.stream_id.clone();
+    let app_clone = app.clone();
+
+    // Spawn the streaming task
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = run_chat_stream(app_clone.clone(), client, api_key, config, request).await {
+            let _ = app_clone.emit(
+                &format!("chat-event-{}", stream_id),
+                ChatEvent {
+                    event_type: "error".to_string(),
+                    content: None,
+                    tool_use: None,
+                    usage: None,
+                    error: Some(e),
+                    stop: None,
+                    seed: None,
+                },
+            );
+        }
+    });
+
+    Ok(())
+}
+
+#[tauri::command]
+fn get_session_usage(
+    state: State<SessionUsageState>,
+    session_id: String,
+) -> Result<SessionUsage, String> {
+    let sessions = state.0.lock().map_err(|e| e.to_string())?;
+    Ok(sessions.get(&session_id).cloned().unwrap_or_default())
+}
+
+#[tauri::command]
+fn reset_session_usage(state: State<SessionUsageState>, session_id: String) -> Result<(), String> {
+    let mut sessions = state.0.lock().map_err(|e| e.to_string())?;
+    sessions.remove(&session_id);
+    Ok(())
+}
+
+/// Build the content block chat_stream would send for an attachment (for previews/validation)
+#[tauri::command]
+fn build_attachment(path: String) -> Result<serde_json::Value, String> {
+    attachment_block(&path)
+}
+
+/// Put a cache breakpoint on the last tool, which caches all tool definitions
+fn mark_last_tool_cacheable(mut tools: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+    if let Some(serde_json::Value::Object(last)) = tools.last_mut() {
+        last.insert(
+            "cache_control".to_string(),
+            serde_json::json!({ "type": "ephemeral" }),
+        );
+    }
+    tools
+}
+
+/// Add cache_control to the requested message content blocks, using at most
+/// `available` breakpoints (later breakpoints win since they cache a longer prefix)
+fn apply_message_cache_breakpoints(
+    messages: &mut [Message],
+    breakpoints: &[CacheBreakpoint],
+    available: usize,
+) -> Result<(), String> {
+    if breakpoints.len() > available {
+        log::warn!(
+            "{} cache breakpoints requested but only {} available; keeping the last {}",
+            breakpoints.len(),
+            available,
+            available
+        );
+    }
+
+    for breakpoint in breakpoints.iter().rev().take(available) {
+        let message = messages
+            .get_mut(breakpoint.message_index)
+            .ok_or_else(|| format!("Cache breakpoint message index {} out of range", breakpoint.message_index))?;
+
+        // Plain string content has to become a text block to carry cache_control
+        if let serde_json::Value::String(text) = &message.content {
+            message.content = serde_json::json!([{ "type": "text", "text": text }]);
+        }
+
+        let blocks = message
+            .content
+            .as_array_mut()
+            .ok_or("Cache breakpoint message has no content blocks")?;
+        let block_index = breakpoint
+            .block_index
+            .unwrap_or(blocks.len().saturating_sub(1));
+        let block = blocks
+            .get_mut(block_index)
+            .and_then(|b| b.as_object_mut())
+            .ok_or_else(|| format!("Cache breakpoint block index {} out of range", block_index))?;
+
+        block.insert(
+            "cache_control".to_string(),
+            serde_json::json!({ "type": "ephemeral" }),
+        );
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+fn set_chat_logging(state: State<ChatLogState>, enabled: bool) -> Result<(), String> {
+    let mut logging = state.0.lock().map_err(|e| e.to_string())?;
+    *logging = enabled;
+    Ok(())
+}
+
+/// Copy of a request body that is safe to write to disk: attachment data is
+/// elided and the API key is masked wherever it appears
+fn sanitize_for_log(body: &serde_json::Value, api_key: &str) -> serde_json::Value {
+    fn elide_attachments(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                if map.get("type").and_then(|t| t.as_str()) == Some("base64") {
+                    if let Some(serde_json::Value::String(data)) = map.get_mut("data") {
+                        *data = format!("<{} base64 chars>", data.len());
+                    }
+                }
+                // OpenAI-style image_url data URLs
+                if let Some(serde_json::Value::String(url)) = map.get_mut("url") {
+                    if url.starts_with("data:") {
+                        *url = format!("<{} char data URL>", url.len());
+                    }
+                }
+                map.values_mut().for_each(elide_attachments);
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(elide_attachments),
+            _ => {}
+        }
+    }
+
+    let mut body = body.clone();
+    elide_attachments(&mut body);
+    if api_key.is_empty() {
+        return body;
+    }
+    let masked = body.to_string().replace(api_key, "[REDACTED]");
+    serde_json::from_str(&masked).unwrap_or(body)
+}
+
+/// Append a turn to the chat log in the app data dir, rotating it when it gets large
+fn write_chat_log(app: &AppHandle, entry: &serde_json::Value) -> Result<(), String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join("logs");
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let path = dir.join(CHAT_LOG_FILE);
+    if std::fs::metadata(&path).is_ok_and(|m| m.len() > CHAT_LOG_MAX_BYTES) {
+        std::fs::rename(&path, dir.join(format!("{}.1", CHAT_LOG_FILE))).map_err(|e| e.to_string())?;
+    }
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| e.to_string())?;
+    writeln!(file, "{}", entry).map_err(|e| e.to_string())
+}
+
+async fn run_chat_stream(
+    app: AppHandle,
+    client: Client,
+    api_key: String,
+    config: ApiConfig,
+    request: ChatRequest,
+) -> Result<(), String> {
+    let stream_id = request.stream_id.clone();
+    let event_name = format!("chat-event-{}", stream_id);
+    let provider = config.provider.provider(&config);
+
+    // Select model based on user choice (default: Sonnet)
+    let model = provider.model(request.model.as_deref())?;
+    log::info!("Using model: {}", model);
+
+    // Emit model selection event
+    let _ = app.emit(
+        &event_name,
+        ChatEvent {
+            event_type: "model_selected".to_string(),
+            content: Some(model.clone()),
+            tool_use: None,
+            usage: None,
+            error: None,
+            stop: None,
+            seed: None,
+        },
+    );
+
+    let mut request = request;
+    if !request.attachments.is_empty() {
+        let blocks = request
+            .attachments
+            .iter()
+            .map(|path| attachment_block(path))
+            .collect::<Result<Vec<_>, _>>()?;
+        attach_to_last_user_message(&mut request.messages, blocks)?;
+    }
+    // The API rejects a final assistant message ending in whitespace
+    let prefill = request.prefill.as_deref().map(str::trim_end).map(String::from);
+    if let Some(prefill) = &prefill {
+        request.messages.push(Message {
+            role: "assistant".to_string(),
+            content: serde_json::json!(prefill),
+        });
+    }
+    if let Some(context_window) = provider.context_window(&model) {
+        let dropped = check_context_window(&mut request, context_window)?;
+        if dropped > 0 {
+            let _ = app.emit(
+                &event_name,
+                ChatEvent {
+                    event_type: "context_truncated".to_string(),
+                    content: Some(dropped.to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+    let body = provider.stream_body(&model, &request)?;
+    let seed = request.seed.filter(|_| provider.supports_seed());
+    if request.seed.is_some() && seed.is_none() {
+        log::info!("Seed ignored: the configured chat provider doesn't support one");
+    }
+
+    let logging_enabled = *app
+        .state::<ChatLogState>()
+        .0
+        .lock()
+        .map_err(|e| e.to_string())?;
+    let logged_request = logging_enabled.then(|| sanitize_for_log(&body, &api_key));
+
+    // Retry logic with exponential backoff for transient errors (529 Overloaded, 503 Service Unavailable)
+    let max_retries = 3;
+    let mut attempts = 0;
+    let mut retry_delay = std::time::Duration::from_secs(1);
+
+    let response = loop {
+        let http_request = provider.post(&client, &config, &api_key, &request);
+        let result = http_request.body(body.to_string()).send().await;
+
+        match result {
+            Ok(resp) => {
+                let status = resp.status();
+                // Retry on 529 (Overloaded) or 503 (Service Unavailable)
+                if status.as_u16() == 529 || status.as_u16() == 503 {
                     attempts += 1;
                     if attempts <= max_retries {
                         log::warn!(
@@ -379,233 +2452,1366 @@ async fn run_chat_stream(
         }
     };
 
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_body = response.text().await.unwrap_or_default();
-        return Err(format!("API error {}: {}", status, error_body));
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(format!("API error {}: {}", status, error_body));
+    }
+
+    let mut stream = response.bytes_stream();
+    let mut sse = SseDecoder::default();
+    let mut turn = StreamTurn::new();
+    if let Some(prefill) = &prefill {
+        // The reply continues from the prefill; send it first so the UI shows the whole answer
+        let _ = app.emit(&event_name, turn.text_event(prefill));
+    }
+
+    loop {
+        let chunk = stream.next().await;
+        let payloads = match &chunk {
+            Some(chunk_result) => {
+                let chunk = chunk_result.as_ref().map_err(|e| format!("Stream error: {}", e))?;
+                sse.push(chunk)
+            }
+            // The last event may arrive without its closing blank line
+            None => sse.finish(),
+        };
+
+        for data in payloads {
+            for event in provider.handle_data(&data, &mut turn)? {
+                let _ = app.emit(&event_name, event);
+            }
+            if turn.finished {
+                finish_chat_turn(&app, &event_name, &request, &model, seed, logged_request.as_ref(), &turn)?;
+                return Ok(());
+            }
+        }
+
+        if chunk.is_none() {
+            // Without message_stop (or [DONE]) the reply may be cut short; the
+            // caller turns this into an error event so the UI doesn't wait for done
+            return Err("Stream ended unexpectedly".to_string());
+        }
+    }
+}
+
+/// Record a completed turn (session usage, chat log) and emit the final usage and done events
+fn finish_chat_turn(
+    app: &AppHandle,
+    event_name: &str,
+    request: &ChatRequest,
+    model: &str,
+    seed: Option<u64>,
+    logged_request: Option<&serde_json::Value>,
+    turn: &StreamTurn,
+) -> Result<(), String> {
+    if let Some(session_id) = &request.session_id {
+        let state = app.state::<SessionUsageState>();
+        let mut sessions = state.0.lock().map_err(|e| e.to_string())?;
+        let session = sessions.entry(session_id.clone()).or_default();
+        session.add_turn(model, &turn.usage);
+        if session.cache_never_reused() {
+            log::warn!(
+                "Session {}: prompt cache written on {} turns in a row but never read; \
+                 the cached prefix is probably changing between turns",
+                session_id, CACHE_MISS_STREAK_WARNING
+            );
+        }
+    }
+
+    if let Some(logged_request) = logged_request {
+        let entry = serde_json::json!({
+            "timestamp": unix_now(),
+            "stream_id": request.stream_id,
+            "model": model,
+            "seed": seed,
+            "request": logged_request,
+            "response": {
+                "text": turn.text,
+                "tool_calls": turn.tool_calls,
+                "stop_reason": turn.stop.reason,
+            },
+            "usage": turn.usage,
+        });
+        if let Err(e) = write_chat_log(app, &entry) {
+            log::warn!("Failed to write chat log: {}", e);
+        }
+    }
+
+    // Emit final usage
+    let _ = app.emit(
+        event_name,
+        ChatEvent {
+            event_type: "usage".to_string(),
+            content: None,
+            tool_use: None,
+            usage: Some(turn.usage.clone().with_cache_hit_ratio()),
+            error: None,
+            stop: None,
+            seed: None,
+        },
+    );
+
+    let _ = app.emit(
+        event_name,
+        ChatEvent {
+            event_type: "done".to_string(),
+            content: None,
+            tool_use: None,
+            usage: None,
+            error: None,
+            stop: Some(turn.stop.clone()),
+            seed,
+        },
+    );
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ChatOnceRequest {
+    messages: Vec<Message>,
+    system_prompt: String,
+    tools: Option<Vec<serde_json::Value>>,
+    model: Option<String>,
+    temperature: Option<f32>,
+}
+
+/// A tool call made by chat_once on the model's behalf
+#[derive(Serialize)]
+struct ToolCallRecord {
+    name: String,
+    input: serde_json::Value,
+    result: serde_json::Value,
+    is_error: bool,
+}
+
+#[derive(Serialize)]
+struct ChatOnceResponse {
+    text: String,
+    tool_call: Option<ToolCallRecord>,
+    stop_reason: Option<String>,
+    usage: UsageEvent,
+}
+
+/// Send one non-streaming request for a Messages-format `body` to the configured
+/// provider, returning the reply as an Anthropic message and adding its usage to `usage`
+async fn send_message(
+    client: &Client,
+    api_key: &str,
+    config: &ApiConfig,
+    body: &serde_json::Value,
+    usage: &mut UsageEvent,
+) -> Result<serde_json::Value, String> {
+    let provider = config.provider.provider(config);
+    let response = provider
+        .message_request(client, config, api_key, body)?
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_body = response.text().await.unwrap_or_default();
+        return Err(format!("API error {}: {}", status, error_body));
+    }
+
+    let message: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response: {}", e))?;
+    let message = provider.parse_message(message)?;
+
+    let count = |field: &str| message["usage"][field].as_u64().unwrap_or(0) as u32;
+    usage.input_tokens += count("input_tokens");
+    usage.output_tokens += count("output_tokens");
+    usage.cache_read_tokens = Some(usage.cache_read_tokens.unwrap_or(0) + count("cache_read_input_tokens"));
+    usage.cache_write_tokens = Some(usage.cache_write_tokens.unwrap_or(0) + count("cache_creation_input_tokens"));
+    if let Some(tier) = message["usage"]["service_tier"].as_str() {
+        usage.service_tier = Some(tier.to_string());
+    }
+
+    Ok(message)
+}
+
+/// Concatenate the text blocks of a response
+fn response_text(message: &serde_json::Value) -> String {
+    message["content"]
+        .as_array()
+        .map(|blocks| {
+            blocks
+                .iter()
+                .filter(|b| b["type"].as_str() == Some("text"))
+                .filter_map(|b| b["text"].as_str())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// How long chat_once waits on its tool call before reporting it as failed
+const CHAT_TOOL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Non-streaming chat for scripted jobs: runs at most one tool call through the
+/// MCP server and returns the final assistant text
+#[tauri::command]
+async fn chat_once(
+    app: AppHandle,
+    state: State<'_, ApiKeyState>,
+    config_state: State<'_, ApiConfigState>,
+    http_state: State<'_, HttpClientState>,
+    file_locks: State<'_, FileLocks>,
+    request: ChatOnceRequest,
+) -> Result<ChatOnceResponse, String> {
+    let config = config_state.0.lock().map_err(|e| e.to_string())?.clone();
+    let api_key = chat_api_key(&state, &config)?;
+    let client = http_state.client();
+    let model = config.provider.provider(&config).model(request.model.as_deref())?;
+
+    let mut body = serde_json::json!({
+        "model": model,
+        "max_tokens": MAX_OUTPUT_TOKENS,
+        "system": request.system_prompt,
+        "messages": request.messages,
+    });
+    if let Some(tools) = request.tools.filter(|t| !t.is_empty()) {
+        body["tools"] = serde_json::Value::Array(tools);
+    }
+    if let Some(temperature) = request.temperature {
+        body["temperature"] = serde_json::json!(temperature);
+    }
+
+    let mut usage = UsageEvent::zero();
+    let mut message = send_message(&client, &api_key, &config, &body, &mut usage).await?;
+    let mut tool_call = None;
+
+    if message["stop_reason"].as_str() == Some("tool_use") {
+        let tool_uses: Vec<serde_json::Value> = message["content"]
+            .as_array()
+            .map(|blocks| {
+                blocks
+                    .iter()
+                    .filter(|b| b["type"].as_str() == Some("tool_use"))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Every tool_use needs a tool_result; only the first one is actually run
+        let mut tool_results = Vec::new();
+        for (i, tool_use) in tool_uses.iter().enumerate() {
+            let (content, is_error) = if i > 0 {
+                (
+                    serde_json::json!("Only one tool call is run per chat_once request"),
+                    true,
+                )
+            } else {
+                let name = tool_use["name"].as_str().unwrap_or_default().to_string();
+                let input = tool_use["input"].clone();
+                let _guards = file_locks.acquire(&name, &input).await?;
+                let params = serde_json::json!({ "name": name, "arguments": input });
+                let result = run_mcp(&app, move |state| {
+                    state.call_with_timeout("tools/call", params, Some(CHAT_TOOL_TIMEOUT))
+                })
+                .await;
+                let (result, is_error) = match result {
+                    Ok(result) => {
+                        let is_error = result["isError"].as_bool().unwrap_or(false);
+                        (result, is_error)
+                    }
+                    Err(e) => (serde_json::json!({ "content": [{ "type": "text", "text": e }] }), true),
+                };
+                let content = result["content"].clone();
+                tool_call = Some(ToolCallRecord { name, input, result, is_error });
+                (content, is_error)
+            };
+            tool_results.push(serde_json::json!({
+                "type": "tool_result",
+                "tool_use_id": tool_use["id"],
+                "content": content,
+                "is_error": is_error,
+            }));
+        }
+
+        if let Some(messages) = body["messages"].as_array_mut() {
+            messages.push(serde_json::json!({ "role": "assistant", "content": message["content"] }));
+            messages.push(serde_json::json!({ "role": "user", "content": tool_results }));
+        }
+        message = send_message(&client, &api_key, &config, &body, &mut usage).await?;
+    }
+
+    Ok(ChatOnceResponse {
+        text: response_text(&message),
+        tool_call,
+        stop_reason: message["stop_reason"].as_str().map(String::from),
+        usage: usage.with_cache_hit_ratio(),
+    })
+}
+
+#[derive(Deserialize)]
+struct CompactHistoryRequest {
+    messages: Vec<Message>,
+    /// Summarize only when the history is estimated above this many tokens
+    token_threshold: usize,
+    /// Most recent turns (a user message and the replies to it) kept verbatim
+    #[serde(default = "default_keep_turns")]
+    keep_turns: usize,
+}
+
+fn default_keep_turns() -> usize {
+    4
+}
+
+#[derive(Serialize)]
+struct CompactHistoryResponse {
+    messages: Vec<Message>,
+    /// Number of original messages replaced by the summary (0 if under the threshold)
+    summarized: usize,
+    usage: Option<UsageEvent>,
+}
+
+const SUMMARY_SYSTEM_PROMPT: &str = "You summarize the earlier part of a conversation between a translator and \
+an assistant working on SDLXLIFF files. Keep decisions, terminology and style choices, file names, segment IDs \
+and unfinished tasks. Be concise and write in the conversation's language.";
+
+// Tool results can be whole file dumps; the summary only needs their gist
+const SUMMARY_TOOL_RESULT_CHARS: usize = 2_000;
+
+/// Plain-text transcript of messages for the summarizer
+fn transcript(messages: &[Message]) -> String {
+    let mut text = String::new();
+    for message in messages {
+        let blocks = match &message.content {
+            serde_json::Value::Array(blocks) => blocks.clone(),
+            content => vec![serde_json::json!({ "type": "text", "text": content })],
+        };
+        for block in blocks {
+            let line = match block["type"].as_str().unwrap_or("") {
+                "text" => block["text"].as_str().unwrap_or("").to_string(),
+                "tool_use" => format!("[called {} with {}]", block["name"].as_str().unwrap_or("tool"), block["input"]),
+                "tool_result" => {
+                    let result = match &block["content"] {
+                        serde_json::Value::String(result) => result.clone(),
+                        other => other.to_string(),
+                    };
+                    let result: String = result.chars().take(SUMMARY_TOOL_RESULT_CHARS).collect();
+                    format!("[tool result: {}]", result)
+                }
+                other => format!("[{} attachment]", other),
+            };
+            text.push_str(&format!("{}: {}\n\n", message.role, line));
+        }
+    }
+    text
+}
+
+/// Keep long chat histories inside the context window: once the history is over
+/// `token_threshold`, summarize everything but the last `keep_turns` turns and
+/// put the summary in their place. Anthropic summarizes with Haiku; other
+/// providers use their configured model. Returns the list to persist.
+#[tauri::command]
+async fn compact_history(
+    state: State<'_, ApiKeyState>,
+    config_state: State<'_, ApiConfigState>,
+    http_state: State<'_, HttpClientState>,
+    request: CompactHistoryRequest,
+) -> Result<CompactHistoryResponse, String> {
+    let mut messages = request.messages;
+    let unchanged = |messages| CompactHistoryResponse { messages, summarized: 0, usage: None };
+
+    let total: usize = messages.iter().map(|m| estimate_json_tokens(&m.content)).sum();
+    if total <= request.token_threshold {
+        return Ok(unchanged(messages));
+    }
+
+    // Summarize up to the start of the oldest kept turn
+    let turn_starts: Vec<usize> = (0..messages.len()).filter(|&i| starts_turn(&messages[i])).collect();
+    let split = match turn_starts.len().checked_sub(request.keep_turns.max(1)) {
+        Some(oldest_kept) if turn_starts[oldest_kept] > 0 => turn_starts[oldest_kept],
+        _ => return Ok(unchanged(messages)),
+    };
+
+    let config = config_state.0.lock().map_err(|e| e.to_string())?.clone();
+    let api_key = chat_api_key(&state, &config)?;
+    let model = config.provider.provider(&config).model(Some(MODEL_HAIKU))?;
+
+    let body = serde_json::json!({
+        "model": model,
+        "max_tokens": 2048,
+        "system": SUMMARY_SYSTEM_PROMPT,
+        "messages": [{
+            "role": "user",
+            "content": format!("Summarize this conversation:\n\n{}", transcript(&messages[..split])),
+        }],
+    });
+    let mut usage = UsageEvent::zero();
+    let response = send_message(&http_state.client(), &api_key, &config, &body, &mut usage).await?;
+    let summary = response_text(&response);
+    if summary.trim().is_empty() {
+        return Err("Summary request returned no text".to_string());
+    }
+
+    // A user/assistant pair keeps roles alternating before the kept turns
+    let summary_messages = [
+        Message {
+            role: "user".to_string(),
+            content: serde_json::json!(format!("Summary of our earlier conversation:\n\n{}", summary)),
+        },
+        Message {
+            role: "assistant".to_string(),
+            content: serde_json::json!("Understood, I'll continue from that summary."),
+        },
+    ];
+    messages.splice(..split, summary_messages);
+    log::info!("Summarized {} messages (~{} tokens)", split, total);
+
+    Ok(CompactHistoryResponse {
+        messages,
+        summarized: split,
+        usage: Some(usage),
+    })
+}
+
+// ============================================================================
+// Conversation History
+// ============================================================================
+
+#[derive(Deserialize, Serialize)]
+struct SavedConversation {
+    session_id: String,
+    /// Seconds since the Unix epoch
+    saved_at: u64,
+    messages: Vec<Message>,
+}
+
+#[derive(Serialize)]
+struct ConversationSummary {
+    session_id: String,
+    saved_at: u64,
+    message_count: usize,
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn conversations_dir(app: &AppHandle) -> Result<std::path::PathBuf, String> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| e.to_string())?
+        .join("conversations");
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    Ok(dir)
+}
+
+/// File for a session; ids are restricted so they can't escape the directory
+fn conversation_path(app: &AppHandle, session_id: &str) -> Result<std::path::PathBuf, String> {
+    let valid = !session_id.is_empty()
+        && session_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        return Err(format!("Invalid session id: {}", session_id));
+    }
+    Ok(conversations_dir(app)?.join(format!("{}.json", session_id)))
+}
+
+#[tauri::command]
+fn save_conversation(app: AppHandle, session_id: String, messages: Vec<Message>) -> Result<(), String> {
+    let path = conversation_path(&app, &session_id)?;
+    let conversation = SavedConversation {
+        session_id,
+        saved_at: unix_now(),
+        messages,
+    };
+    let json = serde_json::to_string(&conversation).map_err(|e| e.to_string())?;
+
+    // Write then rename so a crash mid-save doesn't corrupt the previous copy
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, json).map_err(|e| format!("Failed to save conversation: {}", e))?;
+    std::fs::rename(&tmp_path, &path).map_err(|e| format!("Failed to save conversation: {}", e))
+}
+
+#[tauri::command]
+fn load_conversation(app: AppHandle, session_id: String) -> Result<Vec<Message>, String> {
+    let path = conversation_path(&app, &session_id)?;
+    let json = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to load conversation {}: {}", session_id, e))?;
+    let conversation: SavedConversation = serde_json::from_str(&json)
+        .map_err(|e| format!("Invalid conversation file {}: {}", session_id, e))?;
+    Ok(conversation.messages)
+}
+
+/// Saved sessions, most recent first
+#[tauri::command]
+fn list_conversations(app: AppHandle) -> Result<Vec<ConversationSummary>, String> {
+    let dir = conversations_dir(&app)?;
+    let mut summaries = Vec::new();
+
+    for entry in std::fs::read_dir(&dir).map_err(|e| e.to_string())?.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let Ok(json) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        match serde_json::from_str::<SavedConversation>(&json) {
+            Ok(conversation) => summaries.push(ConversationSummary {
+                session_id: conversation.session_id,
+                saved_at: conversation.saved_at,
+                message_count: conversation.messages.len(),
+            }),
+            Err(e) => log::warn!("Skipping unreadable conversation {}: {}", path.display(), e),
+        }
+    }
+
+    summaries.sort_by_key(|s| std::cmp::Reverse(s.saved_at));
+    Ok(summaries)
+}
+
+/// A fenced code block around `text`, with a fence longer than any run of
+/// backticks inside it
+fn markdown_fence(language: &str, text: &str) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{}{}\n{}\n{}\n\n", fence, language, text.trim_end(), fence)
+}
+
+/// Render a chat history as Markdown: a heading per turn, text as-is, tool
+/// calls and results in code blocks, and a note in place of each attachment
+fn conversation_markdown(messages: &[Message]) -> String {
+    let mut markdown = String::from("# Conversation\n\n");
+    for message in messages {
+        let mut role: String = message.role.chars().take(1).flat_map(char::to_uppercase).collect();
+        role.extend(message.role.chars().skip(1));
+        markdown.push_str(&format!("## {}\n\n", role));
+
+        let blocks = match &message.content {
+            serde_json::Value::Array(blocks) => blocks.clone(),
+            content => vec![serde_json::json!({ "type": "text", "text": content })],
+        };
+        for block in blocks {
+            match block["type"].as_str().unwrap_or("") {
+                "text" => {
+                    let text = block["text"].as_str().unwrap_or("").trim();
+                    if !text.is_empty() {
+                        markdown.push_str(&format!("{}\n\n", text));
+                    }
+                }
+                "tool_use" => {
+                    let input = serde_json::to_string_pretty(&block["input"]).unwrap_or_default();
+                    markdown.push_str(&format!(
+                        "**Tool call:** `{}`\n\n",
+                        block["name"].as_str().unwrap_or("tool")
+                    ));
+                    markdown.push_str(&markdown_fence("json", &input));
+                }
+                "tool_result" => {
+                    let result = match &block["content"] {
+                        serde_json::Value::String(result) => result.clone(),
+                        serde_json::Value::Array(parts) => parts
+                            .iter()
+                            .map(|part| match part["text"].as_str() {
+                                Some(text) => text.to_string(),
+                                None => format!("[{} attachment]", part["type"].as_str().unwrap_or("unknown")),
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                        other => other.to_string(),
+                    };
+                    let label = if block["is_error"].as_bool() == Some(true) {
+                        "**Tool error:**"
+                    } else {
+                        "**Tool result:**"
+                    };
+                    markdown.push_str(&format!("{}\n\n", label));
+                    markdown.push_str(&markdown_fence("", &result));
+                }
+                other => {
+                    let media_type = block["source"]["media_type"].as_str();
+                    markdown.push_str(&match media_type {
+                        Some(media_type) => format!("_[{} attachment: {}]_\n\n", other, media_type),
+                        None => format!("_[{} attachment]_\n\n", other),
+                    });
+                }
+            }
+        }
+    }
+    markdown
+}
+
+/// Save `messages` to `path` as a Markdown document for sharing or review
+#[tauri::command]
+fn export_conversation_markdown(messages: Vec<Message>, path: String) -> Result<(), String> {
+    std::fs::write(&path, conversation_markdown(&messages))
+        .map_err(|e| format!("Failed to export conversation to {}: {}", path, e))
+}
+
+// ============================================================================
+// Diagnostics
+// ============================================================================
+
+#[derive(Serialize)]
+struct CheckResult {
+    ok: bool,
+    message: String,
+}
+
+impl CheckResult {
+    fn pass(message: impl Into<String>) -> Self {
+        Self { ok: true, message: message.into() }
+    }
+
+    fn fail(message: impl Into<String>) -> Self {
+        Self { ok: false, message: message.into() }
+    }
+}
+
+#[derive(Serialize)]
+struct Diagnostics {
+    python: CheckResult,
+    mcp_server: CheckResult,
+    api_key: CheckResult,
+    ollama: CheckResult,
+}
+
+fn check_mcp_server(state: &McpState) -> Result<CheckResult, String> {
+    {
+        let mut server = state.lock()?;
+        let Some(child) = server.child.as_mut() else {
+            return Ok(CheckResult::fail("MCP server is not running"));
+        };
+        if let Ok(Some(status)) = child.try_wait() {
+            return Ok(CheckResult::fail(format!("MCP server exited ({})", status)));
+        }
+    }
+
+    // A ping, not initialize: re-initializing would reset the live session
+    let started = std::time::Instant::now();
+    Ok(match state.call_with_timeout("ping", serde_json::json!({}), Some(MCP_PING_TIMEOUT)) {
+        Ok(_) => CheckResult::pass(format!(
+            "MCP server responded in {} ms",
+            started.elapsed().as_millis()
+        )),
+        Err(e) => CheckResult::fail(format!("MCP server did not respond: {}", e)),
+    })
+}
+
+/// Whether Ollama is up and has `model` pulled
+async fn check_ollama_ready(client: &Client, model: &str) -> CheckResult {
+    match rag::check_ollama(client).await {
+        Ok(true) => {}
+        Ok(false) => return CheckResult::fail("Ollama is not reachable"),
+        Err(e) => return CheckResult::fail(e),
+    }
+    match rag::check_ollama_model(client, model).await {
+        Ok(true) => CheckResult::pass(format!("Ollama running with {}", model)),
+        Ok(false) => CheckResult::fail(format!("Ollama model {} is not pulled", model)),
+        Err(e) => CheckResult::fail(e),
+    }
+}
+
+/// Check every external dependency in one call for the diagnostics panel
+#[tauri::command]
+async fn diagnostics(
+    app: AppHandle,
+    key_state: State<'_, ApiKeyState>,
+    config_state: State<'_, ApiConfigState>,
+    http_state: State<'_, HttpClientState>,
+    rag_state: State<'_, RagState>,
+) -> Result<Diagnostics, String> {
+    let python = match find_python() {
+        Ok(python) => CheckResult::pass(format!("Using {}", python)),
+        Err(e) => CheckResult::fail(e),
+    };
+
+    let mcp_server = run_mcp(&app, check_mcp_server).await?;
+
+    let api_key = key_state.0.lock().map_err(|e| e.to_string())?.clone();
+    let config = config_state.0.lock().map_err(|e| e.to_string())?.clone();
+    let client = http_state.client();
+    let api_key = match api_key {
+        None => CheckResult::fail("API key not set"),
+        Some(key) => match check_api_key(&client, &config, &key).await {
+            Ok(true) => CheckResult::pass("API key is valid"),
+            Ok(false) => CheckResult::fail("API key was rejected"),
+            Err(e) => CheckResult::fail(e),
+        },
+    };
+
+    let ollama = match rag::ollama_model(&rag_state) {
+        Ok(model) => check_ollama_ready(&client, &model).await,
+        Err(e) => CheckResult::fail(e),
+    };
+
+    Ok(Diagnostics {
+        python,
+        mcp_server,
+        api_key,
+        ollama,
+    })
+}
+
+// ============================================================================
+// RAG Commands
+// ============================================================================
+
+#[derive(Deserialize)]
+struct RagInitRequest {
+    api_key: Option<String>,
+    use_ollama: bool,
+    /// OpenAI embedding model. Default: text-embedding-3-small
+    model: Option<String>,
+    /// OpenAI-compatible API root. Default: https://api.openai.com/v1
+    base_url: Option<String>,
+}
+
+#[tauri::command]
+fn rag_init(
+    state: State<RagState>,
+    http_state: State<HttpClientState>,
+    request: RagInitRequest,
+) -> Result<String, AppError> {
+    rag::init_client(
+        &state,
+        http_state.client(),
+        request.api_key,
+        request.use_ollama,
+        request.model,
+        request.base_url,
+    )?;
+    Ok("RAG initialized".to_string())
+}
+
+#[derive(Deserialize)]
+struct RagIndexRequest {
+    file_path: String,
+    file_hash: String,
+    segments: Vec<Segment>,
+    #[serde(default)]
+    separate_embeddings: bool,
+    /// Store embeddings as int8 to cut memory roughly 4x
+    #[serde(default)]
+    quantization: Quantization,
+    /// Text for the combined embedding, with {source}, {target}, {origin}, {status}
+    /// placeholders. Default: "Source: {source} Target: {target}"
+    combined_template: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+struct IndexEvictedEvent {
+    file_paths: Vec<String>,
+}
+
+/// Notify the UI that indexes were dropped to stay within the memory budget
+fn emit_evicted(app: &AppHandle, evicted: Vec<String>) {
+    if evicted.is_empty() {
+        return;
+    }
+    sync_index_watcher(app);
+    log::info!("Evicted {} index(es) to stay within memory budget", evicted.len());
+    let _ = app.emit("rag-index-evicted", IndexEvictedEvent { file_paths: evicted });
+}
+
+/// Hash the segments read for a file, for rag_index's file_hash
+#[tauri::command]
+async fn hash_segments(segments: Vec<Segment>) -> String {
+    rag::hash_segments(&segments)
+}
+
+#[derive(Clone, Serialize)]
+struct IndexReadyEvent {
+    file_path: String,
+    segments: usize,
+}
+
+#[derive(Clone, Serialize)]
+struct IndexFailedEvent {
+    file_path: String,
+    error: AppError,
+}
+
+/// Result of rag_index
+#[derive(Serialize)]
+struct RagIndexReport {
+    /// Segments in the index
+    count: usize,
+    /// Only source/target embeddings were computed; the combined ones were reused
+    added_separate_embeddings: bool,
+}
+
+/// Index a file's segments. Emits `index_ready` when the file can be searched,
+/// or `index_failed` with the error (including cancellation).
+#[tauri::command]
+async fn rag_index(
+    app: AppHandle,
+    state: State<'_, RagState>,
+    request: RagIndexRequest,
+) -> Result<RagIndexReport, AppError> {
+    let file_path = request.file_path.clone();
+    let result = rag::index_segments(
+        &state,
+        request.file_path,
+        request.file_hash,
+        request.segments,
+        request.separate_embeddings,
+        request.quantization,
+        request.combined_template,
+    )
+    .await;
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(error) => {
+            let _ = app.emit("index_failed", IndexFailedEvent { file_path, error: error.clone() });
+            return Err(error);
+        }
+    };
+
+    if outcome.added_separate_embeddings {
+        log::info!("Added source/target embeddings to the existing index; combined embeddings were reused");
     }
+    emit_evicted(&app, outcome.evicted);
+    sync_index_watcher(&app);
+    let _ = app.emit("index_ready", IndexReadyEvent { file_path, segments: outcome.count });
+    Ok(RagIndexReport {
+        count: outcome.count,
+        added_separate_embeddings: outcome.added_separate_embeddings,
+    })
+}
 
-    let mut stream = response.bytes_stream();
-    let mut buffer = String::new();
-    let mut total_usage = UsageEvent {
-        input_tokens: 0,
-        output_tokens: 0,
-        cache_read_tokens: Some(0),
-        cache_write_tokens: Some(0),
-    };
+/// Watches the folders of indexed files while enabled (see set_index_watching)
+struct IndexWatcherState(Mutex<Option<IndexWatcher>>);
 
-    // Current tool being built
-    let mut current_tool_id: Option<String> = None;
-    let mut current_tool_name: Option<String> = None;
-    let mut current_tool_input = String::new();
+/// Canonical path of an indexed file to the path it's indexed under
+type WatchedFiles = Arc<Mutex<HashMap<std::path::PathBuf, String>>>;
 
-    while let Some(chunk_result) = stream.next().await {
-        let chunk = chunk_result.map_err(|e| format!("Stream error: {}", e))?;
-        buffer.push_str(&String::from_utf8_lossy(&chunk));
+struct IndexWatcher {
+    watcher: notify::RecommendedWatcher,
+    dirs: HashSet<std::path::PathBuf>,
+    /// Shared with the event handler, which sees canonical paths
+    files: WatchedFiles,
+}
 
-        // Process complete SSE events
-        while let Some(event_end) = buffer.find("\n\n") {
-            let event_data = buffer[..event_end].to_string();
-            buffer = buffer[event_end + 2..].to_string();
+impl IndexWatcher {
+    /// Watch the folders containing `indexed` files and stop watching the rest.
+    /// Folders rather than files are watched because editors (Trados included)
+    /// save by replacing the file, which would silently end a per-file watch.
+    fn sync(&mut self, indexed: Vec<String>) {
+        use notify::Watcher;
 
-            // Parse SSE event
-            for line in event_data.lines() {
-                if let Some(data) = line.strip_prefix("data: ") {
-                    if data == "[DONE]" {
-                        continue;
-                    }
+        let files: HashMap<std::path::PathBuf, String> = indexed
+            .into_iter()
+            .filter_map(|file_path| Some((canonical_file_path(Path::new(&file_path))?, file_path)))
+            .collect();
+        let dirs: HashSet<std::path::PathBuf> =
+            files.keys().filter_map(|path| path.parent()).map(Path::to_path_buf).collect();
 
-                    if let Ok(event) = serde_json::from_str::<serde_json::Value>(data) {
-                        let event_type = event["type"].as_str().unwrap_or("");
-
-                        match event_type {
-                            "message_start" => {
-                                if let Some(usage) = event["message"]["usage"].as_object() {
-                                    total_usage.input_tokens +=
-                                        usage.get("input_tokens").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
-                                    if let Some(cr) = usage.get("cache_read_input_tokens").and_then(|v| v.as_u64()) {
-                                        total_usage.cache_read_tokens = Some(
-                                            total_usage.cache_read_tokens.unwrap_or(0) + cr as u32
-                                        );
-                                    }
-                                    if let Some(cw) = usage.get("cache_creation_input_tokens").and_then(|v| v.as_u64()) {
-                                        total_usage.cache_write_tokens = Some(
-                                            total_usage.cache_write_tokens.unwrap_or(0) + cw as u32
-                                        );
-                                    }
-                                }
-                            }
-                            "content_block_start" => {
-                                let block = &event["content_block"];
-                                if block["type"].as_str() == Some("tool_use") {
-                                    current_tool_id = block["id"].as_str().map(String::from);
-                                    current_tool_name = block["name"].as_str().map(String::from);
-                                    current_tool_input.clear();
-                                }
-                            }
-                            "content_block_delta" => {
-                                let delta = &event["delta"];
-                                if delta["type"].as_str() == Some("text_delta") {
-                                    if let Some(text) = delta["text"].as_str() {
-                                        let _ = app.emit(
-                                            &event_name,
-                                            ChatEvent {
-                                                event_type: "text".to_string(),
-                                                content: Some(text.to_string()),
-                                                tool_use: None,
-                                                usage: None,
-                                                error: None,
-                                            },
-                                        );
-                                    }
-                                } else if delta["type"].as_str() == Some("input_json_delta") {
-                                    if let Some(json) = delta["partial_json"].as_str() {
-                                        current_tool_input.push_str(json);
-                                    }
-                                }
-                            }
-                            "content_block_stop" => {
-                                // Emit tool use if we were building one
-                                if let (Some(id), Some(name)) = (current_tool_id.take(), current_tool_name.take()) {
-                                    let input: serde_json::Value = serde_json::from_str(&current_tool_input)
-                                        .unwrap_or(serde_json::Value::Object(serde_json::Map::new()));
-                                    current_tool_input.clear();
-
-                                    let _ = app.emit(
-                                        &event_name,
-                                        ChatEvent {
-                                            event_type: "tool_use".to_string(),
-                                            content: None,
-                                            tool_use: Some(ToolUseEvent { id, name, input }),
-                                            usage: None,
-                                            error: None,
-                                        },
-                                    );
-                                }
-                            }
-                            "message_delta" => {
-                                if let Some(usage) = event["usage"].as_object() {
-                                    total_usage.output_tokens +=
-                                        usage.get("output_tokens").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
-                                }
-                            }
-                            "message_stop" => {
-                                // Emit final usage
-                                let _ = app.emit(
-                                    &event_name,
-                                    ChatEvent {
-                                        event_type: "usage".to_string(),
-                                        content: None,
-                                        tool_use: None,
-                                        usage: Some(total_usage.clone()),
-                                        error: None,
-                                    },
-                                );
-
-                                let _ = app.emit(
-                                    &event_name,
-                                    ChatEvent {
-                                        event_type: "done".to_string(),
-                                        content: None,
-                                        tool_use: None,
-                                        usage: None,
-                                        error: None,
-                                    },
-                                );
-                            }
-                            _ => {}
-                        }
-                    }
+        for dir in self.dirs.difference(&dirs) {
+            if let Err(e) = self.watcher.unwatch(dir) {
+                log::warn!("Failed to stop watching {}: {}", dir.display(), e);
+            }
+        }
+        let mut watched = HashSet::new();
+        for dir in dirs {
+            if self.dirs.contains(&dir) {
+                watched.insert(dir);
+                continue;
+            }
+            match self.watcher.watch(&dir, notify::RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    watched.insert(dir);
                 }
+                Err(e) => log::warn!("Failed to watch {}: {}", dir.display(), e),
             }
         }
+        self.dirs = watched;
+        if let Ok(mut current) = self.files.lock() {
+            *current = files;
+        }
     }
+}
 
-    Ok(())
+/// `path` with its folder canonicalized, as notify reports it. The file itself
+/// isn't resolved, so this works for a file that was just deleted or renamed.
+fn canonical_file_path(path: &Path) -> Option<std::path::PathBuf> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    Some(dir.canonicalize().ok()?.join(path.file_name()?))
 }
 
-// ============================================================================
-// RAG Commands
-// ============================================================================
+/// Match the watched folders to the indexed files, if watching is enabled
+fn sync_index_watcher(app: &AppHandle) {
+    let indexed = match rag::indexed_files(&app.state::<RagState>()) {
+        Ok(indexed) => indexed,
+        Err(e) => {
+            log::warn!("Failed to list indexed files: {}", e);
+            return;
+        }
+    };
+    let state = app.state::<IndexWatcherState>();
+    let Ok(mut watcher) = state.0.lock() else {
+        return;
+    };
+    if let Some(watcher) = watcher.as_mut() {
+        watcher.sync(indexed);
+    }
+}
 
-#[derive(Deserialize)]
-struct RagInitRequest {
-    api_key: Option<String>,
-    use_ollama: bool,
+#[derive(Clone, Serialize)]
+struct IndexInvalidatedEvent {
+    file_path: String,
+}
+
+/// Mark the index of each changed file stale and tell the UI
+fn handle_index_file_event(app: &AppHandle, files: &WatchedFiles, result: notify::Result<notify::Event>) {
+    use notify::event::{EventKind, ModifyKind};
+
+    let event = match result {
+        Ok(event) => event,
+        Err(e) => {
+            log::warn!("File watcher error: {}", e);
+            return;
+        }
+    };
+    // Access-time and permission changes leave the content as it was
+    if !matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any)
+    ) {
+        return;
+    }
+
+    let state = app.state::<RagState>();
+    for path in event.paths {
+        let Some(file_path) = canonical_file_path(&path)
+            .and_then(|path| files.lock().ok()?.get(&path).cloned())
+        else {
+            continue;
+        };
+        match rag::invalidate_index(&state, &file_path) {
+            Ok(true) => {
+                log::info!("{} changed on disk; its index is stale", file_path);
+                let _ = app.emit("index_invalidated", IndexInvalidatedEvent { file_path });
+            }
+            Ok(false) => {}
+            Err(e) => log::warn!("Failed to invalidate index for {}: {}", file_path, e),
+        }
+    }
 }
 
+/// Watch indexed files and mark their index stale when they change on disk,
+/// emitting `index_invalidated`. Off by default; disabling stops all watches.
 #[tauri::command]
-fn rag_init(state: State<RagState>, request: RagInitRequest) -> Result<String, String> {
-    rag::init_client(&state, request.api_key, request.use_ollama)?;
-    Ok("RAG initialized".to_string())
+fn set_index_watching(
+    app: AppHandle,
+    watcher_state: State<IndexWatcherState>,
+    rag_state: State<RagState>,
+    enabled: bool,
+) -> Result<(), String> {
+    let mut current = watcher_state.0.lock().map_err(|e| e.to_string())?;
+    if !enabled {
+        // Dropped after the lock is released
+        let _stopped = current.take();
+        drop(current);
+        return Ok(());
+    }
+    if current.is_some() {
+        return Ok(());
+    }
+
+    let handler_app = app.clone();
+    let files = WatchedFiles::default();
+    let handler_files = files.clone();
+    let watcher = notify::recommended_watcher(move |result| {
+        handle_index_file_event(&handler_app, &handler_files, result)
+    })
+    .map_err(|e| format!("Failed to start file watcher: {}", e))?;
+    let mut watcher = IndexWatcher { watcher, dirs: HashSet::new(), files };
+    watcher.sync(rag::indexed_files(&rag_state)?);
+    *current = Some(watcher);
+    Ok(())
 }
 
 #[derive(Deserialize)]
-struct RagIndexRequest {
+struct RagEstimateRequest {
     file_path: String,
-    file_hash: String,
     segments: Vec<Segment>,
     #[serde(default)]
     separate_embeddings: bool,
+    combined_template: Option<String>,
 }
 
+/// Estimate tokens, requests, cost and minimum time to index a file, without calling the provider
 #[tauri::command]
-async fn rag_index(state: State<'_, RagState>, request: RagIndexRequest) -> Result<usize, String> {
-    rag::index_segments(
+fn estimate_indexing(
+    state: State<RagState>,
+    request: RagEstimateRequest,
+) -> Result<rag::IndexEstimate, AppError> {
+    rag::estimate_indexing(
         &state,
-        request.file_path,
-        request.file_hash,
-        request.segments,
+        &request.file_path,
+        &request.segments,
         request.separate_embeddings,
+        request.combined_template,
     )
-    .await
+}
+
+/// Set the maximum embedding memory (bytes) kept across indexed files.
+/// Least-recently-used indexes are evicted when it's exceeded; None = unbounded.
+#[tauri::command]
+fn rag_set_memory_budget(
+    app: AppHandle,
+    state: State<RagState>,
+    max_bytes: Option<usize>,
+) -> Result<Vec<String>, String> {
+    let evicted = rag::set_memory_budget(&state, max_bytes)?;
+    emit_evicted(&app, evicted.clone());
+    Ok(evicted)
+}
+
+/// Change the embedding provider; returns files whose indexes were cleared and need re-indexing
+#[tauri::command]
+fn switch_embedding_provider(
+    app: AppHandle,
+    state: State<RagState>,
+    http_state: State<HttpClientState>,
+    request: EmbeddingProvider,
+) -> Result<Vec<String>, String> {
+    let stale = rag::switch_provider(&state, http_state.client(), request)?;
+    emit_evicted(&app, stale.clone());
+    Ok(stale)
+}
+
+/// Tokens embedded and estimated cost per embedding model this session
+#[tauri::command]
+fn get_embedding_usage(state: State<RagState>) -> Result<rag::EmbeddingUsageReport, String> {
+    rag::embedding_usage(&state)
+}
+
+/// Average and p95 embedding time per text for the current client (None before its first request)
+#[tauri::command]
+fn get_embedding_latency(state: State<RagState>) -> Result<Option<rag::LatencyReport>, String> {
+    rag::embedding_latency(&state)
+}
+
+/// Pace hosted embedding requests to stay under provider quotas; None removes the limit
+#[tauri::command]
+fn set_embedding_rate_limits(state: State<RagState>, limits: Option<RateLimits>) -> Result<(), String> {
+    rag::set_rate_limits(&state, limits)
+}
+
+/// Rerank search results with Cohere or Voyage; None goes back to embedding order
+#[tauri::command]
+fn set_reranker(
+    state: State<RagState>,
+    http_state: State<HttpClientState>,
+    config: Option<rag::RerankConfig>,
+) -> Result<(), String> {
+    rag::set_reranker(&state, http_state.client(), config)
+}
+
+/// Check that the embedding model matches text across languages; the result's
+/// `warning` is set when it looks English-only. Run after rag_init (warmup_rag does it too).
+#[tauri::command]
+async fn check_embedding_multilingual(state: State<'_, RagState>) -> Result<rag::MultilingualCheck, AppError> {
+    rag::check_client_multilingual(&state).await
+}
+
+#[derive(Deserialize)]
+struct RagWarmupRequest {
+    /// Provider to initialize if no embedding client is set up yet
+    provider: Option<EmbeddingProvider>,
+    /// Index written by rag_export_index (with embeddings) to load into memory
+    index: Option<PersistedIndex>,
+}
+
+/// Prepare for the first search: init the client, probe the embedding endpoint,
+/// optionally load a persisted index. Returns timings so the UI can decide on a spinner.
+#[tauri::command]
+async fn warmup_rag(
+    app: AppHandle,
+    state: State<'_, RagState>,
+    http_state: State<'_, HttpClientState>,
+    request: RagWarmupRequest,
+) -> Result<rag::WarmupTiming, AppError> {
+    let mut timing = rag::warmup(&state, http_state.client(), request.provider, request.index).await?;
+    emit_evicted(&app, std::mem::take(&mut timing.evicted));
+    sync_index_watcher(&app);
+    log::info!("RAG warmup took {} ms", timing.total_ms);
+    Ok(timing)
 }
 
 #[derive(Deserialize)]
 struct RagSearchRequest {
     file_path: String,
     query: String,
+    #[serde(flatten)]
+    options: RagSearchOptions,
+}
+
+#[derive(Deserialize)]
+struct RagSearchOptions {
     limit: Option<usize>,
     #[serde(default)]
     mode: SearchMode,
     /// Minimum relevance score (0.0-1.0). Default: 0.5
     min_score: Option<f32>,
+    /// Number of neighboring segments to return around each hit. Default: 0
+    #[serde(default)]
+    context_window: usize,
+    /// "score" or "document_position"; hits are picked by score either way. Default: "score"
+    #[serde(default)]
+    sort: SortOrder,
+    /// Show one hit per identical source+target pair. Default: false
+    #[serde(default)]
+    collapse_duplicates: bool,
+    /// Diversify hits with maximal marginal relevance (0.0-1.0, lower = more diverse). Default: off
+    mmr_lambda: Option<f32>,
+    /// Restrict the search to these segment ids. Default: all segments
+    id_filter: Option<HashSet<String>>,
+    /// Language of the query (e.g. "en") when it differs from the searched side. Default: none
+    query_language: Option<String>,
+    /// Weights {source, target, combined} to blend "both" mode scores. Default: highest score wins
+    both_weights: Option<BothWeights>,
+    /// Add a 0-1 relevance next to each raw score: {"method": "min_max"} or
+    /// {"method": "sigmoid", "center": 0.5, "steepness": 10}. Default: off
+    #[serde(default)]
+    normalization: ScoreNormalization,
+}
+
+impl From<RagSearchOptions> for SearchOptions {
+    fn from(options: RagSearchOptions) -> Self {
+        SearchOptions {
+            limit: options.limit.unwrap_or(10),
+            mode: options.mode,
+            min_score: options.min_score.unwrap_or(0.5),
+            context_window: options.context_window,
+            sort: options.sort,
+            collapse_duplicates: options.collapse_duplicates,
+            mmr_lambda: options.mmr_lambda,
+            id_filter: options.id_filter,
+            query_language: options.query_language,
+            both_weights: options.both_weights,
+            normalization: options.normalization,
+        }
+    }
 }
 
 #[tauri::command]
 async fn rag_search(
     state: State<'_, RagState>,
     request: RagSearchRequest,
-) -> Result<Vec<SearchResult>, String> {
-    rag::search_segments(
+) -> Result<Vec<SearchResult>, AppError> {
+    rag::search_segments(&state, request.file_path, request.query, request.options.into()).await
+}
+
+#[derive(Deserialize)]
+struct RagCorpusSearchRequest {
+    file_paths: Vec<String>,
+    query: String,
+    #[serde(flatten)]
+    options: RagSearchOptions,
+}
+
+/// Search several indexed files at once; each hit carries its file_path
+#[tauri::command]
+async fn search_corpus(
+    state: State<'_, RagState>,
+    request: RagCorpusSearchRequest,
+) -> Result<Vec<SearchResult>, AppError> {
+    rag::search_corpus(&state, request.file_paths, request.query, request.options.into()).await
+}
+
+#[derive(Deserialize)]
+struct RagExportSearchRequest {
+    #[serde(flatten)]
+    search: RagSearchRequest,
+    /// Destination JSON file
+    output_path: String,
+    #[serde(default)]
+    include_embeddings: bool,
+}
+
+#[tauri::command]
+async fn rag_export_search_results(
+    state: State<'_, RagState>,
+    request: RagExportSearchRequest,
+) -> Result<usize, String> {
+    rag::export_search_results(
         &state,
-        request.file_path,
-        request.query,
-        request.limit.unwrap_or(10),
-        request.mode,
-        request.min_score.unwrap_or(0.5),
+        request.search.file_path,
+        request.search.query,
+        request.search.options.into(),
+        &request.output_path,
+        request.include_embeddings,
     )
     .await
 }
 
+#[tauri::command]
+fn rag_export_index(
+    state: State<RagState>,
+    file_path: String,
+    output_path: String,
+    include_embeddings: Option<bool>,
+) -> Result<usize, String> {
+    rag::export_index(&state, &file_path, &output_path, include_embeddings.unwrap_or(false))
+}
+
 #[tauri::command]
 fn rag_stats(state: State<RagState>) -> Result<HashMap<String, usize>, String> {
     rag::get_stats(&state)
 }
 
 #[tauri::command]
-fn rag_clear(state: State<RagState>, file_path: String) -> Result<(), String> {
-    rag::clear_index(&state, &file_path)
+fn rag_detailed_stats(state: State<RagState>) -> Result<IndexStats, String> {
+    rag::get_detailed_stats(&state)
+}
+
+#[tauri::command]
+fn rag_get_segment(
+    state: State<RagState>,
+    file_path: String,
+    segment_id: String,
+) -> Result<Option<Segment>, String> {
+    rag::get_segment(&state, &file_path, &segment_id)
+}
+
+/// Find segments whose source or target contains `pattern` (a literal substring
+/// unless `regex` is set). Works on the stored segments, so no embedding client is needed.
+#[tauri::command]
+fn grep_segments(
+    state: State<RagState>,
+    file_path: String,
+    pattern: String,
+    case_insensitive: Option<bool>,
+    regex: Option<bool>,
+) -> Result<Vec<rag::GrepMatch>, AppError> {
+    rag::grep_segments(
+        &state,
+        &file_path,
+        &pattern,
+        case_insensitive.unwrap_or(false),
+        regex.unwrap_or(false),
+    )
+}
+
+/// Segments most similar to an indexed segment (for consistency review), from its stored embedding
+#[tauri::command]
+fn similar_to_segment(
+    state: State<RagState>,
+    file_path: String,
+    segment_id: String,
+    limit: Option<usize>,
+) -> Result<Vec<SearchResult>, AppError> {
+    rag::similar_to_segment(&state, &file_path, &segment_id, limit.unwrap_or(10))
+}
+
+// Upper bound on pairs returned by find_similar_pairs
+const DEFAULT_SIMILAR_PAIRS_LIMIT: usize = 500;
+
+/// Near-duplicate segment pairs in an indexed file (cosine similarity >= threshold)
+#[tauri::command]
+fn find_similar_pairs(
+    state: State<RagState>,
+    file_path: String,
+    threshold: f32,
+    limit: Option<usize>,
+) -> Result<Vec<SimilarPair>, String> {
+    rag::find_similar_pairs(
+        &state,
+        &file_path,
+        threshold,
+        limit.unwrap_or(DEFAULT_SIMILAR_PAIRS_LIMIT),
+    )
+}
+
+/// Cap the on-disk embedding cache at `max_entries` embeddings (least recently used are dropped)
+#[tauri::command]
+async fn set_embedding_cache_limit(state: State<'_, RagState>, max_entries: usize) -> Result<(), String> {
+    rag::set_cache_limit(&state, max_entries).await
+}
+
+/// Whether a file is indexed (and with which hash/model), so the UI can tell if opening it will re-index
+#[tauri::command]
+fn index_status(state: State<RagState>, file_path: String) -> Result<IndexStatus, String> {
+    rag::index_status(&state, &file_path)
+}
+
+/// Stop indexing a file (e.g. when it's closed); returns false if it wasn't being indexed
+#[tauri::command]
+fn cancel_indexing(state: State<RagState>, file_path: String) -> Result<bool, String> {
+    rag::cancel_indexing(&state, &file_path)
+}
+
+/// Texts left and estimated seconds remaining while a file is being indexed (None if it isn't)
+#[tauri::command]
+fn indexing_eta(state: State<RagState>, file_path: String) -> Result<Option<rag::IndexingEta>, String> {
+    rag::indexing_eta(&state, &file_path)
+}
+
+/// Whether source/target-only search is available for a file (None if not indexed)
+#[tauri::command]
+fn rag_has_separate_embeddings(state: State<RagState>, file_path: String) -> Result<Option<bool>, String> {
+    rag::has_separate_embeddings(&state, &file_path)
+}
+
+#[tauri::command]
+fn rag_clear(app: AppHandle, state: State<RagState>, file_path: String) -> Result<(), String> {
+    rag::clear_index(&state, &file_path)?;
+    sync_index_watcher(&app);
+    Ok(())
+}
+
+// Embedding cache file in the app data dir
+const EMBEDDING_CACHE_FILE: &str = "embedding-cache.json";
+
+#[derive(Clone, Serialize)]
+struct IndexesClearedEvent {
+    files: usize,
+}
+
+/// Drop every index, e.g. to reclaim memory; returns the number of files cleared
+#[tauri::command]
+fn clear_all_indexes(app: AppHandle, state: State<RagState>) -> Result<usize, String> {
+    let files = rag::clear_all_indexes(&state)?;
+    sync_index_watcher(&app);
+    log::info!("Cleared {} index(es)", files);
+    let _ = app.emit("rag-indexes-cleared", IndexesClearedEvent { files });
+    Ok(files)
+}
+
+#[tauri::command]
+async fn rag_check_ollama(http_state: State<'_, HttpClientState>) -> Result<bool, String> {
+    rag::check_ollama(&http_state.client()).await
 }
 
 #[tauri::command]
-async fn rag_check_ollama() -> Result<bool, String> {
-    rag::check_ollama().await
+async fn rag_check_ollama_model(http_state: State<'_, HttpClientState>, model: String) -> Result<bool, String> {
+    rag::check_ollama_model(&http_state.client(), &model).await
 }
 
 #[tauri::command]
-async fn rag_check_ollama_model(model: String) -> Result<bool, String> {
-    rag::check_ollama_model(&model).await
+async fn list_ollama_models(http_state: State<'_, HttpClientState>) -> Result<Vec<rag::OllamaModel>, String> {
+    rag::list_ollama_models(&http_state.client()).await
+}
+
+/// Delete a pulled Ollama model; returns the models left. Deleting the configured
+/// embedding model fails unless `confirmed` is true.
+#[tauri::command]
+async fn delete_ollama_model(
+    state: State<'_, RagState>,
+    http_state: State<'_, HttpClientState>,
+    name: String,
+    confirmed: Option<bool>,
+) -> Result<Vec<rag::OllamaModel>, String> {
+    rag::delete_ollama_model(&state, &http_state.client(), &name, confirmed.unwrap_or(false)).await
 }
 
 #[tauri::command]
@@ -613,14 +3819,97 @@ fn rag_install_ollama() -> Result<String, String> {
     rag::install_ollama()
 }
 
+/// The `ollama serve` process this app started, if any
+struct OllamaProcessState(Mutex<Option<Child>>);
+
+#[tauri::command]
+fn rag_start_ollama(process_state: State<OllamaProcessState>) -> Result<String, String> {
+    let mut process = process_state.0.lock().map_err(|e| e.to_string())?;
+    if let Some(child) = process.as_mut() {
+        if let Ok(None) = child.try_wait() {
+            return Ok("Ollama is already running.".to_string());
+        }
+    }
+    *process = Some(rag::start_ollama()?);
+    Ok("Ollama starting... wait a few seconds then refresh.".to_string())
+}
+
+/// Stop the Ollama server started with rag_start_ollama. An Ollama the user
+/// started themselves is never touched. Returns whether a server was stopped.
+#[tauri::command]
+async fn stop_ollama(process_state: State<'_, OllamaProcessState>) -> Result<bool, String> {
+    let child = process_state.0.lock().map_err(|e| e.to_string())?.take();
+    let Some(mut child) = child else {
+        return Ok(false);
+    };
+    // Waiting out the shutdown grace period blocks, so keep it off the runtime
+    let stopped = tauri::async_runtime::spawn_blocking(move || rag::stop_ollama(&mut child))
+        .await
+        .map_err(|e| e.to_string())?;
+    if stopped {
+        log::info!("Stopped the Ollama server started by the app");
+    }
+    Ok(stopped)
+}
+
+#[derive(Clone, Serialize)]
+struct PullProgressEvent<'a> {
+    model: &'a str,
+    #[serde(flatten)]
+    progress: &'a rag::PullProgress,
+}
+
+/// Abort handles of running Ollama pulls, by model name
+#[derive(Default)]
+struct OllamaPullState(Mutex<HashMap<String, AbortHandle>>);
+
+#[derive(Clone, Serialize)]
+struct PullCancelledEvent {
+    model: String,
+}
+
+/// Pull an Ollama model, emitting `ollama-pull-progress` for each status line.
+/// Can be stopped with cancel_ollama_pull.
 #[tauri::command]
-fn rag_start_ollama() -> Result<String, String> {
-    rag::start_ollama()
+async fn rag_pull_ollama_model(
+    app: AppHandle,
+    http_state: State<'_, HttpClientState>,
+    pull_state: State<'_, OllamaPullState>,
+    model: String,
+) -> Result<String, String> {
+    let (handle, registration) = AbortHandle::new_pair();
+    {
+        let mut pulls = pull_state.0.lock().map_err(|e| e.to_string())?;
+        if pulls.contains_key(&model) {
+            return Err(format!("{} is already being pulled", model));
+        }
+        pulls.insert(model.clone(), handle);
+    }
+
+    let client = http_state.client();
+    let pull = rag::pull_ollama_model(&client, &model, |progress| {
+        let _ = app.emit("ollama-pull-progress", PullProgressEvent { model: &model, progress });
+    });
+    let result = Abortable::new(pull, registration).await;
+    if let Ok(mut pulls) = pull_state.0.lock() {
+        pulls.remove(&model);
+    }
+
+    match result {
+        Ok(result) => result,
+        Err(Aborted) => {
+            log::info!("Pull of {} was cancelled", model);
+            let _ = app.emit("pull_cancelled", PullCancelledEvent { model: model.clone() });
+            Err(format!("Pull of {} was cancelled", model))
+        }
+    }
 }
 
+/// Stop a running pull, closing its download. Returns false if the model wasn't being pulled.
 #[tauri::command]
-async fn rag_pull_ollama_model(model: String) -> Result<String, String> {
-    rag::pull_ollama_model(&model).await
+fn cancel_ollama_pull(pull_state: State<OllamaPullState>, model: String) -> Result<bool, String> {
+    let handle = pull_state.0.lock().map_err(|e| e.to_string())?.remove(&model);
+    Ok(handle.map(|handle| handle.abort()).is_some())
 }
 
 // ============================================================================
@@ -633,10 +3922,23 @@ pub fn run() {
         .manage(McpState(Mutex::new(McpServer {
             child: None,
             stdin: None,
-            stdout_reader: None,
+            pending: Arc::new(Mutex::new(McpPending::default())),
+            capabilities: None,
+            tools: None,
+            trace: Arc::new(Mutex::new(McpTrace::default())),
+            retry: McpRetryConfig::default(),
+            app: None,
         })))
-        .manage(ApiKeyState(Mutex::new(None)))
+        .manage(FileLocks::default())
+        .manage(ApiKeyState(Mutex::new(load_api_key_from_keychain())))
+        .manage(ApiConfigState(Mutex::new(ApiConfig::default())))
+        .manage(HttpClientState::new())
+        .manage(SessionUsageState(Mutex::new(HashMap::new())))
+        .manage(ChatLogState(Mutex::new(false)))
         .manage(RagState::new())
+        .manage(IndexWatcherState(Mutex::new(None)))
+        .manage(OllamaPullState::default())
+        .manage(OllamaProcessState(Mutex::new(None)))
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
@@ -646,25 +3948,87 @@ pub fn run() {
             // MCP commands
             spawn_mcp_server,
             mcp_request,
+            mcp_request_batch,
             mcp_notify,
             stop_mcp_server,
+            get_mcp_capabilities,
+            set_mcp_log_level,
+            ping_mcp,
+            set_mcp_trace,
+            set_mcp_retry,
+            get_mcp_retry,
+            get_mcp_trace,
+            test_mcp_roundtrip,
+            list_mcp_resources,
+            read_mcp_resource,
+            list_mcp_prompts,
+            get_mcp_prompt,
+            get_mcp_tool,
+            call_mcp_tool,
             // API key commands
             set_api_key,
             clear_api_key,
             has_api_key,
+            validate_api_key,
+            get_api_config,
+            set_api_config,
+            get_http_config,
+            set_http_config,
             // Chat commands
             chat_stream,
+            chat_once,
+            compact_history,
+            get_session_usage,
+            reset_session_usage,
+            build_attachment,
+            set_chat_logging,
+            list_models,
+            // Conversation history
+            save_conversation,
+            load_conversation,
+            list_conversations,
+            export_conversation_markdown,
+            diagnostics,
             // RAG commands
             rag_init,
+            hash_segments,
             rag_index,
+            set_index_watching,
+            cancel_indexing,
+            indexing_eta,
+            estimate_indexing,
+            index_status,
             rag_search,
+            search_corpus,
+            rag_export_search_results,
+            rag_export_index,
             rag_stats,
+            rag_detailed_stats,
+            rag_get_segment,
+            grep_segments,
+            similar_to_segment,
+            rag_has_separate_embeddings,
+            warmup_rag,
+            check_embedding_multilingual,
+            set_embedding_rate_limits,
+            set_reranker,
+            get_embedding_usage,
+            get_embedding_latency,
+            find_similar_pairs,
+            rag_set_memory_budget,
+            switch_embedding_provider,
             rag_clear,
+            clear_all_indexes,
+            set_embedding_cache_limit,
             rag_check_ollama,
             rag_check_ollama_model,
+            list_ollama_models,
+            delete_ollama_model,
             rag_install_ollama,
             rag_start_ollama,
+            stop_ollama,
             rag_pull_ollama_model,
+            cancel_ollama_pull,
         ])
         .setup(|app| {
             if cfg!(debug_assertions) {
@@ -674,8 +4038,128 @@ pub fn run() {
                         .build(),
                 )?;
             }
+
+            load_api_keys_from_env(app.handle());
+            load_http_config(app.handle());
+
+            // Load cached embeddings off the main thread; the file can be large
+            let handle = app.handle().clone();
+            std::thread::spawn(move || {
+                let path = match handle.path().app_data_dir() {
+                    Ok(dir) => dir.join(EMBEDDING_CACHE_FILE),
+                    Err(e) => {
+                        log::warn!("No app data dir for the embedding cache: {}", e);
+                        return;
+                    }
+                };
+                if let Some(dir) = path.parent() {
+                    let _ = std::fs::create_dir_all(dir);
+                }
+                match rag::open_embedding_cache(&handle.state::<RagState>(), path) {
+                    Ok(count) => log::info!("Loaded {} cached embeddings", count),
+                    Err(e) => log::warn!("Embedding cache not loaded: {}", e),
+                }
+            });
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
-}
\ No newline at end of file
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Don't leave an orphaned Python process holding SDLXLIFF file locks
+            if let tauri::RunEvent::Exit = event {
+                if let Ok(mut server) = app.state::<McpState>().0.lock() {
+                    server.shutdown();
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_last_tool_is_cacheable() {
+        // Tools with differing field counts; the old logic compared index to field count
+        let tools = vec![
+            serde_json::json!({ "name": "a" }),
+            serde_json::json!({ "name": "b", "description": "b" }),
+            serde_json::json!({ "name": "c", "description": "c", "input_schema": {} }),
+            serde_json::json!({ "name": "d", "description": "d", "input_schema": {} }),
+        ];
+
+        let tools = mark_last_tool_cacheable(tools);
+
+        let cached: Vec<usize> = tools
+            .iter()
+            .enumerate()
+            .filter(|(_, tool)| tool.get("cache_control").is_some())
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(cached, vec![3]);
+    }
+
+    #[test]
+    fn cache_writes_without_reads_are_flagged_once() {
+        let usage = |input, read, write| UsageEvent {
+            input_tokens: input,
+            output_tokens: 10,
+            cache_read_tokens: Some(read),
+            cache_write_tokens: Some(write),
+            service_tier: None,
+            cache_hit_ratio: None,
+        };
+        assert_eq!(usage(100, 300, 0).with_cache_hit_ratio().cache_hit_ratio, Some(0.75));
+        assert_eq!(usage(0, 0, 0).with_cache_hit_ratio().cache_hit_ratio, None);
+
+        let mut session = SessionUsage::default();
+        let mut flagged = Vec::new();
+        for _ in 0..5 {
+            session.add_turn(MODEL_HAIKU, &usage(100, 0, 2000));
+            flagged.push(session.cache_never_reused());
+        }
+        assert_eq!(flagged, vec![false, false, true, false, false]);
+
+        session.add_turn(MODEL_HAIKU, &usage(100, 2000, 0));
+        assert!(!session.cache_never_reused());
+        assert!(session.estimated_cost_usd.is_some_and(|cost| cost > 0.0));
+
+        // No guessing at the price of a model that isn't Claude
+        session.add_turn("gpt-4o", &usage(100, 0, 0));
+        assert_eq!(session.estimated_cost_usd, None);
+    }
+
+    #[test]
+    fn conversation_markdown_renders_turns_tools_and_attachments() {
+        let messages = vec![
+            Message {
+                role: "user".to_string(),
+                content: serde_json::json!([
+                    { "type": "image", "source": { "type": "base64", "media_type": "image/png", "data": "AAAA" } },
+                    { "type": "text", "text": "Check segment 4" },
+                ]),
+            },
+            Message {
+                role: "assistant".to_string(),
+                content: serde_json::json!([
+                    { "type": "tool_use", "id": "t1", "name": "get_sdlxliff_segment", "input": { "segment_id": "4" } },
+                ]),
+            },
+            Message {
+                role: "user".to_string(),
+                content: serde_json::json!([
+                    { "type": "tool_result", "tool_use_id": "t1", "content": "Uses ``` in the target" },
+                ]),
+            },
+            Message { role: "assistant".to_string(), content: serde_json::json!("Looks fine.") },
+        ];
+
+        let markdown = conversation_markdown(&messages);
+        assert!(markdown.contains("## User\n\n_[image attachment: image/png]_\n\nCheck segment 4"));
+        assert!(!markdown.contains("AAAA"));
+        assert!(markdown.contains("**Tool call:** `get_sdlxliff_segment`\n\n```json\n{\n  \"segment_id\": \"4\"\n}\n```"));
+        // The fence outgrows backticks in the content
+        assert!(markdown.contains("````\nUses ``` in the target\n````"));
+        assert!(markdown.ends_with("## Assistant\n\nLooks fine.\n\n"));
+    }
+}
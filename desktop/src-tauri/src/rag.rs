@@ -3,10 +3,17 @@
 //! Provides vector embedding and semantic search for translation segments,
 //! enabling efficient context retrieval for LLM queries.
 
+use crate::error::AppError;
+use futures::StreamExt;
+use regex::{Regex, RegexBuilder};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::Mutex;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 // ============================================================================
 // Types
@@ -28,14 +35,240 @@ pub struct Segment {
 #[derive(Clone, Debug)]
 pub(crate) struct IndexedSegment {
     segment: Segment,
+    /// Position of the segment in the file (document order)
+    position: usize,
     /// Combined source+target embedding (for general search)
-    embedding: Vec<f32>,
+    embedding: Embedding,
     /// Source-only embedding (for source language queries)
-    source_embedding: Option<Vec<f32>>,
+    source_embedding: Option<Embedding>,
     /// Target-only embedding (for target language queries)
+    target_embedding: Option<Embedding>,
+    /// Id of the first segment with identical source+target, if this is a duplicate
+    duplicate_of: Option<String>,
+    /// Ids of later segments with identical source+target (set on the first occurrence)
+    duplicate_ids: Vec<String>,
+}
+
+impl IndexedSegment {
+    /// Approximate memory used by this segment's embeddings
+    fn embedding_bytes(&self) -> usize {
+        self.embedding.bytes()
+            + self.source_embedding.as_ref().map_or(0, Embedding::bytes)
+            + self.target_embedding.as_ref().map_or(0, Embedding::bytes)
+    }
+}
+
+/// How embeddings are stored in the index
+///
+/// `Int8` keeps one byte per dimension plus a per-vector scale, about a quarter of
+/// the f32 size. Cosine scores typically move by less than 0.01, so the top-k hits
+/// largely match full precision, but near-ties can swap places and scores sitting
+/// right at `min_score` may fall on either side of it.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    /// Full-precision f32
+    #[default]
+    None,
+    /// Symmetric per-vector int8
+    Int8,
+}
+
+/// A stored embedding vector
+#[derive(Clone, Debug)]
+enum Embedding {
+    F32(Vec<f32>),
+    /// Each value is approximately `q * scale`
+    Int8 { values: Vec<i8>, scale: f32 },
+}
+
+impl Embedding {
+    fn new(vector: Vec<f32>, quantization: Quantization) -> Self {
+        match quantization {
+            Quantization::None => Embedding::F32(vector),
+            Quantization::Int8 => {
+                let max_abs = vector.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+                let scale = if max_abs > 0.0 { max_abs / i8::MAX as f32 } else { 1.0 };
+                let values = vector.iter().map(|x| (x / scale).round() as i8).collect();
+                Embedding::Int8 { values, scale }
+            }
+        }
+    }
+
+    fn quantization(&self) -> Quantization {
+        match self {
+            Embedding::F32(_) => Quantization::None,
+            Embedding::Int8 { .. } => Quantization::Int8,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Embedding::F32(values) => values.len(),
+            Embedding::Int8 { values, .. } => values.len(),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            Embedding::F32(values) => values.len() * std::mem::size_of::<f32>(),
+            Embedding::Int8 { values, .. } => values.len() + std::mem::size_of::<f32>(),
+        }
+    }
+
+    /// Cosine similarity against a full-precision query
+    fn similarity(&self, query: &[f32]) -> f32 {
+        match self {
+            Embedding::F32(values) => cosine_similarity(values, query),
+            // The scale cancels out of the cosine, so the raw int8 values score directly
+            Embedding::Int8 { values, .. } => cosine_similarity_mixed(values, query),
+        }
+    }
+
+    /// Full-precision view (dequantized if needed)
+    fn to_f32(&self) -> Cow<'_, [f32]> {
+        match self {
+            Embedding::F32(values) => Cow::Borrowed(values),
+            Embedding::Int8 { values, scale } => {
+                Cow::Owned(values.iter().map(|&q| q as f32 * scale).collect())
+            }
+        }
+    }
+}
+
+/// Export view of an indexed segment
+#[derive(Serialize)]
+struct ExportedSegment<'a> {
+    #[serde(flatten)]
+    segment: &'a Segment,
+    position: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    embedding: Option<Cow<'a, [f32]>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_embedding: Option<Cow<'a, [f32]>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_embedding: Option<Cow<'a, [f32]>>,
+}
+
+impl<'a> ExportedSegment<'a> {
+    fn new(indexed: &'a IndexedSegment, include_embeddings: bool) -> Self {
+        let embeddings = include_embeddings.then_some(indexed);
+        Self {
+            segment: &indexed.segment,
+            position: indexed.position,
+            embedding: embeddings.map(|i| i.embedding.to_f32()),
+            source_embedding: embeddings.and_then(|i| i.source_embedding.as_ref().map(Embedding::to_f32)),
+            target_embedding: embeddings.and_then(|i| i.target_embedding.as_ref().map(Embedding::to_f32)),
+        }
+    }
+}
+
+/// Export view of a search result
+#[derive(Serialize)]
+struct ExportedSearchResult<'a> {
+    #[serde(flatten)]
+    result: &'a SearchResult,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    embedding: Option<Cow<'a, [f32]>>,
+}
+
+/// Result of indexing a file
+#[derive(Debug)]
+pub struct IndexOutcome {
+    /// Number of segments in the index
+    pub count: usize,
+    /// Files evicted to stay within the memory budget
+    pub evicted: Vec<String>,
+    /// Only source/target embeddings were computed; combined ones were kept
+    pub added_separate_embeddings: bool,
+}
+
+/// Index previously written by `export_index` (with embeddings) to load on warmup
+#[derive(Debug, Deserialize)]
+pub struct PersistedIndex {
+    pub file_path: String,
+    pub file_hash: String,
+    /// Path of the exported JSON file
+    pub export_path: String,
+}
+
+/// Import view of an exported segment
+#[derive(Deserialize)]
+struct ImportedSegment {
+    #[serde(flatten)]
+    segment: Segment,
+    position: usize,
+    embedding: Option<Vec<f32>>,
+    source_embedding: Option<Vec<f32>>,
     target_embedding: Option<Vec<f32>>,
 }
 
+/// Timing of a warmup run (milliseconds)
+#[derive(Debug, Serialize)]
+pub struct WarmupTiming {
+    /// Time to set up the client (0 if it was already initialized)
+    pub init_ms: u64,
+    /// Round trip of the probe embedding
+    pub probe_ms: u64,
+    /// Time to load the persisted index, if one was given
+    pub load_ms: Option<u64>,
+    /// Segments loaded from the persisted index
+    pub loaded_segments: Option<usize>,
+    pub total_ms: u64,
+    /// Files evicted to make room for the loaded index
+    #[serde(skip)]
+    pub evicted: Vec<String>,
+    /// Whether the model matches across languages (None if the check failed)
+    pub multilingual: Option<MultilingualCheck>,
+}
+
+/// Embedding model an index was built with
+#[derive(Debug, Clone)]
+pub struct IndexModel {
+    pub model: String,
+    pub dimension: usize,
+    pub quantization: Quantization,
+    /// Whether source-only and target-only embeddings were generated
+    pub separate_embeddings: bool,
+    /// Template the combined embedding text was rendered from
+    pub combined_template: String,
+}
+
+/// Size stats for a single indexed file
+#[derive(Debug, Serialize)]
+pub struct FileIndexStats {
+    pub segments: usize,
+    /// Embedding model that built the index
+    pub model: Option<String>,
+    /// Embedding dimension (0 if the index is empty)
+    pub dimension: usize,
+    /// How the embeddings are stored
+    pub quantization: Quantization,
+    /// Whether source/target-only search is available
+    pub separate_embeddings: bool,
+    /// Approximate bytes used by embeddings (combined plus source/target copies)
+    pub bytes: usize,
+}
+
+/// Whether a file is indexed, and with what
+#[derive(Debug, Serialize)]
+pub struct IndexStatus {
+    pub indexed: bool,
+    /// Hash of the content the index was built from
+    pub file_hash: Option<String>,
+    pub segments: usize,
+    pub model: Option<String>,
+    pub separate_embeddings: bool,
+}
+
+/// Size stats across all indexed files
+#[derive(Debug, Serialize)]
+pub struct IndexStats {
+    pub files: HashMap<String, FileIndexStats>,
+    pub total_segments: usize,
+    pub total_bytes: usize,
+}
+
 /// Search mode for RAG queries
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "lowercase")]
@@ -51,21 +284,200 @@ pub enum SearchMode {
     Both,
 }
 
-#[derive(Debug, Serialize)]
+/// Order of the results returned by a search
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    /// Best match first
+    #[default]
+    Score,
+    /// Reading order, by position in the file
+    DocumentPosition,
+}
+
+#[derive(Clone, Debug, Serialize)]
 pub struct SearchResult {
+    /// Indexed file the segment belongs to
+    pub file_path: String,
     pub segment: Segment,
     pub score: f32,
+    /// Position of the segment in the file (document order)
+    pub position: usize,
+    /// Surrounding segments in document order (preceding, then following)
+    pub context: Vec<Segment>,
+    /// Ids of identical segments collapsed into this hit
+    pub duplicate_ids: Vec<String>,
+    /// Per-embedding scores behind `score` (only set in `Both` mode)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_score: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_score: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined_score: Option<f32>,
+    /// Source/Target mode scored the combined embedding because the file
+    /// was indexed without separate embeddings
+    pub fallback: bool,
+    /// `score` mapped to 0-1 by the requested normalization (None when off)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relevance: Option<f32>,
+    /// Relevance from the rerank API; results are ordered by it when set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rerank_score: Option<f32>,
+}
+
+/// Two segments in the same file with near-identical meaning
+#[derive(Debug, Serialize)]
+pub struct SimilarPair {
+    pub first: Segment,
+    pub second: Segment,
+    pub score: f32,
+}
+
+/// How raw cosine scores are mapped to a 0-1 relevance for display.
+/// Raw scores cluster differently per model (one model's 0.6 is another's 0.3),
+/// so bars drawn from them aren't comparable across models.
+#[derive(Clone, Copy, Debug, Deserialize, Default, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum ScoreNormalization {
+    /// Leave `relevance` unset
+    #[default]
+    None,
+    /// Best candidate 1, worst candidate 0 (relative to this search only)
+    MinMax,
+    /// Logistic curve around `center`; comparable across searches with one model
+    Sigmoid {
+        #[serde(default = "default_sigmoid_center")]
+        center: f32,
+        #[serde(default = "default_sigmoid_steepness")]
+        steepness: f32,
+    },
+}
+
+fn default_sigmoid_center() -> f32 {
+    0.5
+}
+
+fn default_sigmoid_steepness() -> f32 {
+    10.0
+}
+
+/// Set each result's `relevance` from its score, over all of `results`
+fn normalize_scores(results: &mut [SearchResult], normalization: ScoreNormalization) {
+    match normalization {
+        ScoreNormalization::None => {}
+        ScoreNormalization::MinMax => {
+            let min = results.iter().map(|r| r.score).fold(f32::INFINITY, f32::min);
+            let max = results.iter().map(|r| r.score).fold(f32::NEG_INFINITY, f32::max);
+            for result in results {
+                result.relevance = Some(if max > min { (result.score - min) / (max - min) } else { 1.0 });
+            }
+        }
+        ScoreNormalization::Sigmoid { center, steepness } => {
+            for result in results {
+                result.relevance = Some(1.0 / (1.0 + (-steepness * (result.score - center)).exp()));
+            }
+        }
+    }
+}
+
+/// Weights blending the three scores in `SearchMode::Both`
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct BothWeights {
+    pub source: f32,
+    pub target: f32,
+    pub combined: f32,
+}
+
+impl BothWeights {
+    fn validate(&self) -> Result<(), String> {
+        let weights = [self.source, self.target, self.combined];
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(format!("both_weights must be non-negative, got {:?}", self));
+        }
+        if weights.iter().sum::<f32>() == 0.0 {
+            return Err("both_weights must not all be zero".to_string());
+        }
+        Ok(())
+    }
+
+    /// Weighted mean of the scores present. Source/target scores are missing for
+    /// blank texts or files without separate embeddings, so their weight is
+    /// spread over the others rather than counting them as zero.
+    fn blend(&self, source: Option<f32>, target: Option<f32>, combined: f32) -> f32 {
+        let scores = [(source, self.source), (target, self.target), (Some(combined), self.combined)];
+        let (total, weight) = scores
+            .iter()
+            .filter_map(|(score, weight)| score.map(|score| (score * weight, *weight)))
+            .fold((0.0, 0.0), |(total, sum), (weighted, weight)| (total + weighted, sum + weight));
+        if weight > 0.0 {
+            total / weight
+        } else {
+            combined
+        }
+    }
+}
+
+/// Segment field a keyword search matched in
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchedField {
+    Source,
+    Target,
+}
+
+/// A segment whose source or target contains a keyword search's pattern
+#[derive(Debug, Serialize)]
+pub struct GrepMatch {
+    pub segment: Segment,
+    /// Position of the segment in the file (document order)
+    pub position: usize,
+    pub field: MatchedField,
+}
+
+/// Options controlling how search results are scored, filtered and ordered
+#[derive(Clone, Debug)]
+pub struct SearchOptions {
+    pub limit: usize,
+    pub mode: SearchMode,
+    pub min_score: f32,
+    /// Number of neighboring segments to attach to each hit
+    pub context_window: usize,
+    /// Order of the kept results (they are always selected by score)
+    pub sort: SortOrder,
+    /// Return one hit per identical source+target pair
+    pub collapse_duplicates: bool,
+    /// Rerank with maximal marginal relevance: 1.0 = pure relevance, 0.0 = pure diversity
+    pub mmr_lambda: Option<f32>,
+    /// Only score segments with these ids (e.g. the user's selection)
+    pub id_filter: Option<HashSet<String>>,
+    /// Language the query is written in (e.g. "en"), for Source/Target searches
+    pub query_language: Option<String>,
+    /// Blend the `Both` mode scores with these weights instead of taking their max
+    pub both_weights: Option<BothWeights>,
+    /// Also report each hit's score as a 0-1 relevance
+    pub normalization: ScoreNormalization,
 }
 
 // ============================================================================
 // Vector Store
 // ============================================================================
 
+/// Source and target embedding of one segment (None where the text is blank)
+type SeparateEmbeddings = (Option<Vec<f32>>, Option<Vec<f32>>);
+
 pub struct VectorStore {
     /// Indexed segments per file (keyed by file path)
     indices: HashMap<String, Vec<IndexedSegment>>,
     /// File hashes to detect changes
     file_hashes: HashMap<String, String>,
+    /// Embedding model and dimension each file was indexed with
+    index_models: HashMap<String, IndexModel>,
+    /// Segment id -> position in `indices` per file (for direct lookup)
+    id_maps: HashMap<String, HashMap<String, usize>>,
+    /// Last time each file's index was stored or queried (for LRU eviction)
+    last_access: HashMap<String, Instant>,
+    /// Maximum total embedding bytes across files (None = unbounded)
+    max_bytes: Option<usize>,
 }
 
 impl VectorStore {
@@ -73,7 +485,67 @@ impl VectorStore {
         Self {
             indices: HashMap::new(),
             file_hashes: HashMap::new(),
+            index_models: HashMap::new(),
+            id_maps: HashMap::new(),
+            last_access: HashMap::new(),
+            max_bytes: None,
+        }
+    }
+
+    /// Set the embedding memory budget, evicting files if already over it
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) -> Vec<String> {
+        self.max_bytes = max_bytes;
+        self.enforce_budget(None)
+    }
+
+    /// Mark a file's index as recently used
+    pub fn touch(&mut self, file_path: &str) {
+        if self.indices.contains_key(file_path) {
+            self.last_access.insert(file_path.to_string(), Instant::now());
+        }
+    }
+
+    fn file_bytes(&self, file_path: &str) -> usize {
+        self.indices
+            .get(file_path)
+            .map(|segs| segs.iter().map(IndexedSegment::embedding_bytes).sum())
+            .unwrap_or(0)
+    }
+
+    /// Evict least-recently-used files until under budget; never evicts `keep`
+    fn enforce_budget(&mut self, keep: Option<&str>) -> Vec<String> {
+        let Some(max_bytes) = self.max_bytes else {
+            return Vec::new();
+        };
+
+        let mut total: usize = self.indices.keys().map(|path| self.file_bytes(path)).sum();
+        let mut evicted = Vec::new();
+
+        while total > max_bytes {
+            let lru = self
+                .last_access
+                .iter()
+                .filter(|(path, _)| Some(path.as_str()) != keep)
+                .min_by_key(|(_, accessed)| **accessed)
+                .map(|(path, _)| path.clone());
+            let Some(path) = lru else {
+                break;
+            };
+
+            total -= self.file_bytes(&path);
+            self.clear(&path);
+            evicted.push(path);
+        }
+
+        if total > max_bytes {
+            log::warn!(
+                "Index memory budget exceeded: {} bytes in use, budget {} bytes",
+                total,
+                max_bytes
+            );
         }
+
+        evicted
     }
 
     /// Check if file is already indexed and unchanged
@@ -84,15 +556,159 @@ impl VectorStore {
             .unwrap_or(false)
     }
 
-    /// Store indexed segments for a file
+    /// Store indexed segments for a file, built with `model`
+    /// Returns the paths of files evicted to stay within the memory budget
     pub fn store(
         &mut self,
         file_path: String,
         file_hash: String,
+        model: String,
+        combined_template: String,
         segments: Vec<IndexedSegment>,
-    ) {
+    ) -> Vec<String> {
+        let id_map = segments
+            .iter()
+            .enumerate()
+            .map(|(i, indexed)| (indexed.segment.id.clone(), i))
+            .collect();
+        let dimension = segments.first().map(|s| s.embedding.len()).unwrap_or(0);
+        let quantization = segments
+            .first()
+            .map(|s| s.embedding.quantization())
+            .unwrap_or_default();
+        // Blank texts have no separate embedding, so look past them
+        let separate_embeddings = segments
+            .iter()
+            .any(|s| s.source_embedding.is_some() || s.target_embedding.is_some());
+        self.index_models.insert(
+            file_path.clone(),
+            IndexModel {
+                model,
+                dimension,
+                quantization,
+                separate_embeddings,
+                combined_template,
+            },
+        );
+        self.id_maps.insert(file_path.clone(), id_map);
         self.indices.insert(file_path.clone(), segments);
-        self.file_hashes.insert(file_path, file_hash);
+        self.file_hashes.insert(file_path.clone(), file_hash);
+        self.last_access.insert(file_path.clone(), Instant::now());
+
+        self.enforce_budget(Some(&file_path))
+    }
+
+    /// Attach source/target embeddings to an index built without them, in place.
+    /// `embeddings` holds one (source, target) pair per non-duplicate segment, in order
+    /// (None for blank texts).
+    /// Returns the files evicted for the extra memory, or None if the index changed
+    /// meanwhile, in which case the index is left untouched.
+    pub fn add_separate_embeddings(
+        &mut self,
+        file_path: &str,
+        file_hash: &str,
+        embeddings: Vec<SeparateEmbeddings>,
+    ) -> Option<Vec<String>> {
+        if !self.is_indexed(file_path, file_hash) {
+            return None;
+        }
+        let quantization = self.index_models.get(file_path)?.quantization;
+        let segments = self.indices.get(file_path)?;
+        let id_map = self.id_maps.get(file_path)?;
+
+        let unique = segments.iter().filter(|s| s.duplicate_of.is_none()).count();
+        if unique != embeddings.len() {
+            return None;
+        }
+
+        // Build every pair before touching the index, so a mismatch can't leave it half updated
+        let mut embeddings = embeddings.into_iter();
+        let mut pairs: Vec<(Option<Embedding>, Option<Embedding>)> = Vec::with_capacity(segments.len());
+        for indexed in segments {
+            let pair = match &indexed.duplicate_of {
+                // Duplicates always follow their first occurrence, which is already built
+                Some(first_id) => pairs.get(*id_map.get(first_id)?)?.clone(),
+                None => {
+                    let (source, target) = embeddings.next()?;
+                    (
+                        source.map(|e| Embedding::new(e, quantization)),
+                        target.map(|e| Embedding::new(e, quantization)),
+                    )
+                }
+            };
+            pairs.push(pair);
+        }
+
+        let segments = self.indices.get_mut(file_path)?;
+        for (indexed, (source, target)) in segments.iter_mut().zip(pairs) {
+            indexed.source_embedding = source;
+            indexed.target_embedding = target;
+        }
+        self.index_models.get_mut(file_path)?.separate_embeddings = true;
+
+        Some(self.enforce_budget(Some(file_path)))
+    }
+
+    fn indexed(&self, file_path: &str, segment_id: &str) -> Option<&IndexedSegment> {
+        let index = *self.id_maps.get(file_path)?.get(segment_id)?;
+        self.indices.get(file_path)?.get(index)
+    }
+
+    /// Look up a single indexed segment by id
+    pub fn get_segment(&self, file_path: &str, segment_id: &str) -> Option<Segment> {
+        self.indexed(file_path, segment_id)
+            .map(|indexed| indexed.segment.clone())
+    }
+
+    /// Segments whose source or target matches `pattern`, in document order,
+    /// one match per field. None if the file isn't indexed.
+    pub fn grep(&self, file_path: &str, pattern: &Regex) -> Option<Vec<GrepMatch>> {
+        let segments = self.indices.get(file_path)?;
+        let mut matches = Vec::new();
+        for indexed in segments {
+            for (field, text) in [
+                (MatchedField::Source, &indexed.segment.source),
+                (MatchedField::Target, &indexed.segment.target),
+            ] {
+                if pattern.is_match(text) {
+                    matches.push(GrepMatch {
+                        segment: indexed.segment.clone(),
+                        position: indexed.position,
+                        field,
+                    });
+                }
+            }
+        }
+        Some(matches)
+    }
+
+    /// Export view of a file's index, optionally including embeddings
+    fn exported_segments(&self, file_path: &str, include_embeddings: bool) -> Option<Vec<ExportedSegment<'_>>> {
+        let segments = self.indices.get(file_path)?;
+        Some(
+            segments
+                .iter()
+                .map(|indexed| ExportedSegment::new(indexed, include_embeddings))
+                .collect(),
+        )
+    }
+
+    /// Get up to `window` segments before and after a segment, in document order
+    pub fn neighbors(&self, file_path: &str, segment_id: &str, window: usize) -> Vec<Segment> {
+        let Some(index) = self.id_maps.get(file_path).and_then(|m| m.get(segment_id)).copied() else {
+            return Vec::new();
+        };
+        let Some(segments) = self.indices.get(file_path) else {
+            return Vec::new();
+        };
+
+        let start = index.saturating_sub(window);
+        let end = (index + window + 1).min(segments.len());
+
+        (start..end)
+            .filter(|&i| i != index)
+            .map(|i| segments[i].segment.clone())
+            .collect()
     }
 
     /// Search for similar segments with mode and threshold
@@ -100,47 +716,109 @@ impl VectorStore {
         &self,
         file_path: &str,
         query_embedding: &[f32],
-        limit: usize,
-        mode: &SearchMode,
-        min_score: f32,
+        options: &SearchOptions,
     ) -> Vec<SearchResult> {
         let Some(segments) = self.indices.get(file_path) else {
             return Vec::new();
         };
+        let in_filter = |indexed: &IndexedSegment| match &options.id_filter {
+            Some(ids) => ids.contains(&indexed.segment.id),
+            None => true,
+        };
+
+        // Duplicates score identically, so one per group is scored: the first
+        // one the id filter lets through, with the group's other filtered ids
+        let collapsed: Option<HashMap<&str, Vec<String>>> = options.collapse_duplicates.then(|| {
+            let mut representatives: HashMap<&str, &str> = HashMap::new();
+            let mut groups: HashMap<&str, Vec<String>> = HashMap::new();
+            for indexed in segments.iter().filter(|indexed| in_filter(indexed)) {
+                let id = indexed.segment.id.as_str();
+                let group = indexed.duplicate_of.as_deref().unwrap_or(id);
+                match representatives.get(group) {
+                    Some(representative) => groups.entry(representative).or_default().push(id.to_string()),
+                    None => {
+                        representatives.insert(group, id);
+                        groups.insert(id, Vec::new());
+                    }
+                }
+            }
+            groups
+        });
 
         let mut results: Vec<SearchResult> = segments
             .iter()
             .filter_map(|indexed| {
-                let score = match mode {
-                    SearchMode::Combined => cosine_similarity(&indexed.embedding, query_embedding),
+                if !in_filter(indexed) {
+                    return None;
+                }
+                let duplicate_ids = match &collapsed {
+                    Some(groups) => groups.get(indexed.segment.id.as_str())?.clone(),
+                    None => Vec::new(),
+                };
+
+                // A blank source/target has nothing to match in a single-language search
+                let text = match options.mode {
+                    SearchMode::Source => Some(&indexed.segment.source),
+                    SearchMode::Target => Some(&indexed.segment.target),
+                    SearchMode::Combined | SearchMode::Both => None,
+                };
+                if text.is_some_and(|t| t.trim().is_empty()) {
+                    return None;
+                }
+
+                let mut sub_scores = None;
+                let mut fallback = false;
+                let score = match options.mode {
+                    SearchMode::Combined => indexed.embedding.similarity(query_embedding),
                     SearchMode::Source => {
                         indexed.source_embedding.as_ref()
-                            .map(|e| cosine_similarity(e, query_embedding))
-                            .unwrap_or_else(|| cosine_similarity(&indexed.embedding, query_embedding))
+                            .map(|e| e.similarity(query_embedding))
+                            .unwrap_or_else(|| {
+                                fallback = true;
+                                indexed.embedding.similarity(query_embedding)
+                            })
                     }
                     SearchMode::Target => {
                         indexed.target_embedding.as_ref()
-                            .map(|e| cosine_similarity(e, query_embedding))
-                            .unwrap_or_else(|| cosine_similarity(&indexed.embedding, query_embedding))
+                            .map(|e| e.similarity(query_embedding))
+                            .unwrap_or_else(|| {
+                                fallback = true;
+                                indexed.embedding.similarity(query_embedding)
+                            })
                     }
                     SearchMode::Both => {
+                        // Source/target scores are absent when the file has no separate embeddings
                         let source_score = indexed.source_embedding.as_ref()
-                            .map(|e| cosine_similarity(e, query_embedding))
-                            .unwrap_or(0.0);
+                            .map(|e| e.similarity(query_embedding));
                         let target_score = indexed.target_embedding.as_ref()
-                            .map(|e| cosine_similarity(e, query_embedding))
-                            .unwrap_or(0.0);
-                        let combined_score = cosine_similarity(&indexed.embedding, query_embedding);
-                        // Return max of all three
-                        source_score.max(target_score).max(combined_score)
+                            .map(|e| e.similarity(query_embedding));
+                        let combined_score = indexed.embedding.similarity(query_embedding);
+                        sub_scores = Some((source_score, target_score, combined_score));
+                        match options.both_weights {
+                            Some(weights) => weights.blend(source_score, target_score, combined_score),
+                            // Return max of all three
+                            None => source_score.unwrap_or(0.0)
+                                .max(target_score.unwrap_or(0.0))
+                                .max(combined_score),
+                        }
                     }
                 };
 
                 // Apply threshold filter
-                if score >= min_score {
+                if score >= options.min_score {
                     Some(SearchResult {
+                        file_path: file_path.to_string(),
                         segment: indexed.segment.clone(),
                         score,
+                        position: indexed.position,
+                        context: Vec::new(),
+                        duplicate_ids,
+                        source_score: sub_scores.and_then(|(source, _, _)| source),
+                        target_score: sub_scores.and_then(|(_, target, _)| target),
+                        combined_score: sub_scores.map(|(_, _, combined)| combined),
+                        fallback,
+                        relevance: None,
+                        rerank_score: None,
                     })
                 } else {
                     None
@@ -150,11 +828,68 @@ impl VectorStore {
 
         // Sort by score descending
         results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        normalize_scores(&mut results, options.normalization);
+
+        match options.mmr_lambda {
+            Some(lambda) => {
+                results.truncate(options.limit.saturating_mul(MMR_CANDIDATE_FACTOR));
+                results = mmr_select(segments, results, options.limit, lambda);
+            }
+            None => results.truncate(options.limit),
+        }
+
+        // Optionally present the kept hits in reading order
+        if options.sort == SortOrder::DocumentPosition {
+            results.sort_by_key(|r| r.position);
+        }
 
-        results.truncate(limit);
         results
     }
 
+    /// Pairs of segments whose combined embeddings score at least `threshold`,
+    /// best first, capped at `limit`. Exact duplicates are skipped since they're
+    /// already linked at index time.
+    pub fn similar_pairs(&self, file_path: &str, threshold: f32, limit: usize) -> Option<Vec<SimilarPair>> {
+        let segments = self.indices.get(file_path)?;
+        let candidates: Vec<(&IndexedSegment, Cow<'_, [f32]>)> = segments
+            .iter()
+            .filter(|indexed| indexed.duplicate_of.is_none())
+            .map(|indexed| (indexed, indexed.embedding.to_f32()))
+            .collect();
+
+        let by_score = |a: &(usize, usize, f32), b: &(usize, usize, f32)| {
+            b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal)
+        };
+
+        // Every pair has to be scored, but only the best `limit` are kept in memory
+        let mut pairs: Vec<(usize, usize, f32)> = Vec::new();
+        for i in 0..candidates.len() {
+            for j in (i + 1)..candidates.len() {
+                let score = cosine_similarity(&candidates[i].1, &candidates[j].1);
+                if score >= threshold {
+                    pairs.push((i, j, score));
+                }
+            }
+            if pairs.len() > limit.saturating_mul(2) {
+                pairs.sort_by(by_score);
+                pairs.truncate(limit);
+            }
+        }
+        pairs.sort_by(by_score);
+        pairs.truncate(limit);
+
+        Some(
+            pairs
+                .into_iter()
+                .map(|(i, j, score)| SimilarPair {
+                    first: candidates[i].0.segment.clone(),
+                    second: candidates[j].0.segment.clone(),
+                    score,
+                })
+                .collect(),
+        )
+    }
+
     /// Get stats about indexed files
     pub fn stats(&self) -> HashMap<String, usize> {
         self.indices
@@ -163,22 +898,179 @@ impl VectorStore {
             .collect()
     }
 
+    /// Get per-file and total size stats (approximate embedding memory)
+    pub fn detailed_stats(&self) -> IndexStats {
+        let files: HashMap<String, FileIndexStats> = self
+            .indices
+            .iter()
+            .map(|(path, segs)| {
+                let index_model = self.index_models.get(path);
+                let file_stats = FileIndexStats {
+                    segments: segs.len(),
+                    model: index_model.map(|m| m.model.clone()),
+                    dimension: index_model.map(|m| m.dimension).unwrap_or(0),
+                    quantization: index_model.map(|m| m.quantization).unwrap_or_default(),
+                    separate_embeddings: index_model.is_some_and(|m| m.separate_embeddings),
+                    bytes: segs.iter().map(IndexedSegment::embedding_bytes).sum(),
+                };
+                (path.clone(), file_stats)
+            })
+            .collect();
+
+        IndexStats {
+            total_segments: files.values().map(|f| f.segments).sum(),
+            total_bytes: files.values().map(|f| f.bytes).sum(),
+            files,
+        }
+    }
+
+    /// Indexed state of a file, for deciding whether it needs (re-)indexing
+    pub fn status(&self, file_path: &str) -> IndexStatus {
+        let index_model = self.index_models.get(file_path);
+        IndexStatus {
+            indexed: self.indices.contains_key(file_path),
+            file_hash: self.file_hashes.get(file_path).cloned(),
+            segments: self.indices.get(file_path).map_or(0, Vec::len),
+            model: index_model.map(|m| m.model.clone()),
+            separate_embeddings: index_model.is_some_and(|m| m.separate_embeddings),
+        }
+    }
+
+    /// Model and dimension a file was indexed with
+    pub fn index_model(&self, file_path: &str) -> Option<&IndexModel> {
+        self.index_models.get(file_path)
+    }
+
+    /// Files whose index was built with a model other than `model`
+    pub fn files_not_built_with(&self, model: &str) -> Vec<String> {
+        self.index_models
+            .iter()
+            .filter(|(_, index_model)| index_model.model != model)
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Forget a file's stored hash so the next `is_indexed` check fails and the
+    /// file is re-indexed. Its segments stay searchable until then.
+    /// Returns false if the file had no stored hash.
+    pub fn invalidate(&mut self, file_path: &str) -> bool {
+        self.file_hashes.remove(file_path).is_some()
+    }
+
+    /// Paths of all indexed files
+    pub fn indexed_files(&self) -> Vec<String> {
+        self.indices.keys().cloned().collect()
+    }
+
     /// Clear index for a file
     pub fn clear(&mut self, file_path: &str) {
         self.indices.remove(file_path);
         self.file_hashes.remove(file_path);
+        self.index_models.remove(file_path);
+        self.id_maps.remove(file_path);
+        self.last_access.remove(file_path);
+    }
+
+    /// Clear every file's index; returns how many files were cleared
+    pub fn clear_all(&mut self) -> usize {
+        let count = self.indices.len();
+        self.indices.clear();
+        self.file_hashes.clear();
+        self.index_models.clear();
+        self.id_maps.clear();
+        self.last_access.clear();
+        count
+    }
+}
+
+// MMR picks from this many times `limit` of the top-scoring hits
+const MMR_CANDIDATE_FACTOR: usize = 5;
+
+/// Greedily pick `limit` results from score-sorted `candidates`, each maximizing
+/// `lambda * score - (1 - lambda) * max similarity to the already picked ones`.
+/// Similarity between segments uses their combined embeddings.
+fn mmr_select(
+    segments: &[IndexedSegment],
+    candidates: Vec<SearchResult>,
+    limit: usize,
+    lambda: f32,
+) -> Vec<SearchResult> {
+    let embeddings: Vec<Cow<'_, [f32]>> = candidates
+        .iter()
+        .map(|c| segments[c.position].embedding.to_f32())
+        .collect();
+    // Highest similarity of each candidate to anything selected so far
+    let mut max_similarity = vec![f32::NEG_INFINITY; candidates.len()];
+    let mut remaining: Vec<usize> = (0..candidates.len()).collect();
+    let mut order = Vec::with_capacity(limit.min(candidates.len()));
+
+    while order.len() < limit && !remaining.is_empty() {
+        let mmr = |i: usize| {
+            let redundancy = if order.is_empty() { 0.0 } else { max_similarity[i] };
+            lambda * candidates[i].score - (1.0 - lambda) * redundancy
+        };
+        let (slot, _) = remaining
+            .iter()
+            .enumerate()
+            .max_by(|(_, &a), (_, &b)| mmr(a).partial_cmp(&mmr(b)).unwrap_or(std::cmp::Ordering::Equal))
+            .expect("remaining is not empty");
+        let picked = remaining.swap_remove(slot);
+        order.push(picked);
+
+        for &i in &remaining {
+            let similarity = cosine_similarity(&embeddings[i], &embeddings[picked]);
+            max_similarity[i] = max_similarity[i].max(similarity);
+        }
     }
+
+    // Move the picked results out in selection order
+    let mut taken: Vec<Option<SearchResult>> = candidates.into_iter().map(Some).collect();
+    order.into_iter().filter_map(|i| taken[i].take()).collect()
 }
 
+// Independent accumulators per lane let the compiler vectorize the main loop
+const COSINE_LANES: usize = 8;
+
 fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    cosine_similarity_mixed(a, b)
+}
+
+/// Cosine similarity of a stored vector (f32 or int8) against an f32 query
+fn cosine_similarity_mixed<T: Copy + Into<f32>>(a: &[T], b: &[f32]) -> f32 {
     if a.len() != b.len() || a.is_empty() {
         return 0.0;
     }
 
-    let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    // Single pass over both vectors, accumulating dot product and norms together
+    let mut dot = [0.0f32; COSINE_LANES];
+    let mut sq_a = [0.0f32; COSINE_LANES];
+    let mut sq_b = [0.0f32; COSINE_LANES];
+
+    let chunks_a = a.chunks_exact(COSINE_LANES);
+    let chunks_b = b.chunks_exact(COSINE_LANES);
+    let (rest_a, rest_b) = (chunks_a.remainder(), chunks_b.remainder());
+
+    for (ca, cb) in chunks_a.zip(chunks_b) {
+        for lane in 0..COSINE_LANES {
+            let (x, y): (f32, f32) = (ca[lane].into(), cb[lane]);
+            dot[lane] += x * y;
+            sq_a[lane] += x * x;
+            sq_b[lane] += y * y;
+        }
+    }
+
+    let mut dot: f32 = dot.iter().sum();
+    let mut sq_a: f32 = sq_a.iter().sum();
+    let mut sq_b: f32 = sq_b.iter().sum();
+    for (&x, y) in rest_a.iter().zip(rest_b) {
+        let x: f32 = x.into();
+        dot += x * y;
+        sq_a += x * x;
+        sq_b += y * y;
+    }
 
+    let norm_a = sq_a.sqrt();
+    let norm_b = sq_b.sqrt();
     if norm_a == 0.0 || norm_b == 0.0 {
         return 0.0;
     }
@@ -187,15 +1079,395 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
 }
 
 // ============================================================================
-// Embedding Client
+// Embedding Cache
 // ============================================================================
 
-#[derive(Clone)]
-pub struct EmbeddingClient {
-    client: Client,
-    api_url: String,
-    api_key: Option<String>,
-    model: String,
+/// Default cap on cached embeddings (at 1536 dims, about 120MB in memory and
+/// 165MB on disk, since the file stores them base64 encoded)
+pub const DEFAULT_CACHE_ENTRIES: usize = 20_000;
+
+/// Embeddings of previously seen texts, shared across files and sessions.
+/// Keys combine the model, its dimension and a hash of the text; when over the
+/// cap, the least recently used entries are dropped.
+struct EmbeddingCache {
+    /// Where the cache is persisted (None until opened)
+    path: Option<PathBuf>,
+    entries: HashMap<String, CachedEmbedding>,
+    /// Dimension last seen per model, so lookups can build the key
+    dimensions: HashMap<String, usize>,
+    max_entries: usize,
+    /// Logical clock for LRU ordering
+    clock: u64,
+    /// Entries were added or dropped since the last save. Lookups only move
+    /// LRU positions, which are saved along with the next real change.
+    dirty: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedEmbedding {
+    last_used: u64,
+    /// Little-endian f32 bytes, base64 encoded (much smaller than a JSON array)
+    #[serde(with = "base64_f32")]
+    embedding: Vec<f32>,
+}
+
+/// On-disk format of the cache
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    dimensions: HashMap<String, usize>,
+    entries: HashMap<String, CachedEmbedding>,
+}
+
+mod base64_f32 {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(values: &[f32], serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f32>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(serde::de::Error::custom)?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect())
+    }
+}
+
+impl EmbeddingCache {
+    fn new() -> Self {
+        Self {
+            path: None,
+            entries: HashMap::new(),
+            dimensions: HashMap::new(),
+            max_entries: DEFAULT_CACHE_ENTRIES,
+            clock: 0,
+            dirty: false,
+        }
+    }
+
+    fn key(model: &str, dimension: usize, text: &str) -> String {
+        format!("{}:{}:{}", model, dimension, blake3::hash(text.as_bytes()).to_hex())
+    }
+
+    /// The model part of a key. Split from the right, since model names can
+    /// contain colons themselves (e.g. Ollama tags).
+    fn key_model(key: &str) -> &str {
+        key.rsplitn(3, ':').nth(2).unwrap_or_default()
+    }
+
+    fn get(&mut self, model: &str, text: &str) -> Option<Vec<f32>> {
+        let dimension = *self.dimensions.get(model)?;
+        let entry = self.entries.get_mut(&Self::key(model, dimension, text))?;
+        self.clock += 1;
+        entry.last_used = self.clock;
+        Some(entry.embedding.clone())
+    }
+
+    fn insert(&mut self, model: &str, text: &str, embedding: &[f32]) {
+        let dimension = embedding.len();
+        if self.dimensions.insert(model.to_string(), dimension).is_some_and(|d| d != dimension) {
+            // Same model name, different output size (e.g. a reconfigured deployment)
+            self.entries.retain(|key, _| Self::key_model(key) != model);
+        }
+
+        self.clock += 1;
+        self.entries.insert(
+            Self::key(model, dimension, text),
+            CachedEmbedding {
+                last_used: self.clock,
+                embedding: embedding.to_vec(),
+            },
+        );
+        self.dirty = true;
+        self.evict();
+    }
+
+    /// Drop least recently used entries down to 90% of the cap, so eviction
+    /// doesn't run again on every insert
+    fn evict(&mut self) {
+        if self.entries.len() <= self.max_entries {
+            return;
+        }
+        let keep = self.max_entries * 9 / 10;
+        let mut by_age: Vec<(u64, String)> = self
+            .entries
+            .iter()
+            .map(|(key, entry)| (entry.last_used, key.clone()))
+            .collect();
+        by_age.sort_unstable();
+        for (_, key) in by_age.into_iter().take(self.entries.len() - keep) {
+            self.entries.remove(&key);
+        }
+        self.dirty = true;
+    }
+
+    fn set_max_entries(&mut self, max_entries: usize) {
+        self.max_entries = max_entries;
+        self.evict();
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether `text` is cached for `model`, without touching its LRU position
+    fn contains(&self, model: &str, text: &str) -> bool {
+        self.dimensions
+            .get(model)
+            .is_some_and(|&dimension| self.entries.contains_key(&Self::key(model, dimension, text)))
+    }
+
+    /// Load a persisted cache, replacing the in-memory one. A missing or corrupt
+    /// file starts empty (the corrupt one is overwritten by the next save).
+    fn open(&mut self, path: PathBuf) -> Result<(), String> {
+        let file = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice::<CacheFile>(&bytes)
+                .map_err(|e| log::warn!("Discarding corrupt embedding cache {}: {}", path.display(), e))
+                .ok(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let file = file.unwrap_or_else(|| CacheFile {
+            dimensions: HashMap::new(),
+            entries: HashMap::new(),
+        });
+        self.clock = file.entries.values().map(|e| e.last_used).max().unwrap_or(0);
+        self.entries = file.entries;
+        self.dimensions = file.dimensions;
+        self.path = Some(path);
+        self.dirty = false;
+        self.evict();
+        Ok(())
+    }
+
+    /// Serialized cache and its path, if it has unsaved changes
+    fn snapshot(&mut self) -> Result<Option<(PathBuf, Vec<u8>)>, String> {
+        let Some(path) = self.path.clone().filter(|_| self.dirty) else {
+            return Ok(None);
+        };
+        #[derive(Serialize)]
+        struct CacheFileRef<'a> {
+            dimensions: &'a HashMap<String, usize>,
+            entries: &'a HashMap<String, CachedEmbedding>,
+        }
+        let bytes = serde_json::to_vec(&CacheFileRef {
+            dimensions: &self.dimensions,
+            entries: &self.entries,
+        })
+        .map_err(|e| e.to_string())?;
+        self.dirty = false;
+        Ok(Some((path, bytes)))
+    }
+}
+
+// ============================================================================
+// Embedding Client
+// ============================================================================
+
+/// Embedding model pulled and used by default with Ollama
+const DEFAULT_OLLAMA_MODEL: &str = "mxbai-embed-large";
+
+/// OpenAI embedding model used unless another is chosen
+const DEFAULT_OPENAI_MODEL: &str = "text-embedding-3-small";
+/// OpenAI API root; an OpenAI-compatible gateway can stand in for it
+const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+#[derive(Clone)]
+pub struct EmbeddingClient {
+    client: Client,
+    api_url: String,
+    api_key: Option<String>,
+    model: String,
+    api: EmbeddingApi,
+    /// Paces requests to hosted APIs (None for local servers)
+    limiter: Option<Arc<RateLimiter>>,
+    /// Tokens embedded per model (shared with `RagState` once installed)
+    usage: Arc<Mutex<HashMap<String, EmbeddingUsage>>>,
+    /// Recent request latency, for indexing ETAs
+    latency: Arc<Mutex<LatencyStats>>,
+}
+
+/// Embedding tokens sent to a model
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EmbeddingUsage {
+    pub requests: u64,
+    pub tokens: u64,
+    /// Part of `tokens` estimated from text length (provider didn't report usage)
+    pub estimated_tokens: u64,
+    /// Estimated cost in USD (None if the model's price is unknown)
+    pub cost_usd: Option<f64>,
+}
+
+// Weight of the newest sample in the moving average
+const LATENCY_SMOOTHING: f64 = 0.2;
+// Samples kept for the p95
+const LATENCY_WINDOW: usize = 100;
+
+/// Embedding latency per text: an exponential moving average, plus recent
+/// samples for the p95. Batched APIs spread a request's time over its texts.
+#[derive(Debug, Default)]
+struct LatencyStats {
+    average_ms: Option<f64>,
+    recent: VecDeque<f64>,
+    samples: u64,
+}
+
+impl LatencyStats {
+    fn record(&mut self, ms_per_text: f64) {
+        self.average_ms = Some(match self.average_ms {
+            Some(average) => average + LATENCY_SMOOTHING * (ms_per_text - average),
+            None => ms_per_text,
+        });
+        if self.recent.len() == LATENCY_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(ms_per_text);
+        self.samples += 1;
+    }
+
+    fn p95(&self) -> Option<f64> {
+        let mut sorted: Vec<f64> = self.recent.iter().copied().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = (sorted.len() as f64 * 0.95).ceil() as usize;
+        sorted.get(rank.checked_sub(1)?).copied()
+    }
+}
+
+/// Embedding latency of the current client
+#[derive(Debug, Serialize)]
+pub struct LatencyReport {
+    pub model: String,
+    /// Requests measured since the client was set up
+    pub samples: u64,
+    pub average_ms_per_text: f64,
+    /// Over the last 100 requests
+    pub p95_ms_per_text: f64,
+}
+
+/// How far a running index job has come
+#[derive(Debug, Serialize)]
+pub struct IndexingEta {
+    /// Texts still to embed (cached and resumed texts don't count)
+    pub remaining_texts: usize,
+    /// None until the client has finished a request to time
+    pub eta_seconds: Option<f64>,
+}
+
+/// Usage across all embedding models this session
+#[derive(Debug, Serialize)]
+pub struct EmbeddingUsageReport {
+    pub models: HashMap<String, EmbeddingUsage>,
+    pub total_tokens: u64,
+    pub total_cost_usd: f64,
+}
+
+/// USD per million input tokens; local models are free
+fn embedding_price_per_million(model: &str) -> Option<f64> {
+    match model {
+        "text-embedding-3-small" => Some(0.02),
+        "text-embedding-3-large" => Some(0.13),
+        "text-embedding-ada-002" => Some(0.10),
+        "mxbai-embed-large" | "nomic-embed-text" => Some(0.0),
+        _ => None,
+    }
+}
+
+/// Request/response format spoken by the embedding endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EmbeddingApi {
+    /// `{"input": [...], "model": ...}` -> `{"data": [{"embedding": [...]}]}`
+    OpenAi,
+    /// OpenAI body format, but `api-key` auth and a deployment URL
+    AzureOpenAi,
+    /// One `{"model", "prompt"}` request per text
+    Ollama,
+    /// Hugging Face Text Embeddings Inference: `{"inputs": [...]}` -> `[[...], ...]`
+    Tei,
+}
+
+// TEI's default --max-client-batch-size
+const TEI_BATCH_SIZE: usize = 32;
+
+/// Provider limits the embedding client stays under
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimits {
+    pub requests_per_minute: u32,
+    pub tokens_per_minute: u32,
+}
+
+// OpenAI's tier-1 limits for text-embedding-3-small
+const DEFAULT_RATE_LIMITS: RateLimits = RateLimits {
+    requests_per_minute: 3_000,
+    tokens_per_minute: 1_000_000,
+};
+
+/// Token buckets for requests and tokens, refilled continuously
+struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    requests: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(Buckets {
+                requests: limits.requests_per_minute as f64,
+                tokens: limits.tokens_per_minute as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until a request of `tokens` fits in both buckets, then take it.
+    /// Requests larger than a whole minute's tokens go through once the bucket is full.
+    async fn acquire(&self, tokens: usize) {
+        let request_rate = self.limits.requests_per_minute.max(1) as f64 / 60.0;
+        let token_rate = self.limits.tokens_per_minute.max(1) as f64 / 60.0;
+        let needed = (tokens as f64).min(self.limits.tokens_per_minute as f64);
+
+        loop {
+            let wait = {
+                let Ok(mut buckets) = self.buckets.lock() else {
+                    return;
+                };
+                let elapsed = buckets.refilled.elapsed().as_secs_f64();
+                buckets.refilled = Instant::now();
+                buckets.requests =
+                    (buckets.requests + elapsed * request_rate).min(self.limits.requests_per_minute as f64);
+                buckets.tokens =
+                    (buckets.tokens + elapsed * token_rate).min(self.limits.tokens_per_minute as f64);
+
+                if buckets.requests >= 1.0 && buckets.tokens >= needed {
+                    buckets.requests -= 1.0;
+                    buckets.tokens -= needed;
+                    return;
+                }
+                let request_wait = (1.0 - buckets.requests).max(0.0) / request_rate;
+                let token_wait = (needed - buckets.tokens).max(0.0) / token_rate;
+                request_wait.max(token_wait)
+            };
+            log::debug!("Embedding rate limit: waiting {:.1}s", wait);
+            tokio::time::sleep(std::time::Duration::from_secs_f64(wait)).await;
+        }
+    }
+}
+
+/// Rough token count for rate limiting (about 4 characters per token)
+fn estimate_tokens(texts: &[String]) -> usize {
+    texts.iter().map(|t| t.chars().count().div_ceil(4).max(1)).sum()
 }
 
 #[derive(Serialize)]
@@ -207,6 +1479,12 @@ struct EmbeddingRequest {
 #[derive(Deserialize)]
 struct EmbeddingResponse {
     data: Vec<EmbeddingData>,
+    usage: Option<EmbeddingResponseUsage>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponseUsage {
+    prompt_tokens: u64,
 }
 
 #[derive(Deserialize)]
@@ -216,86 +1494,230 @@ struct EmbeddingData {
 
 impl EmbeddingClient {
     /// Create client for OpenAI-compatible embedding API
-    pub fn new(api_url: String, api_key: Option<String>, model: String) -> Self {
+    pub fn new(client: Client, api_url: String, api_key: Option<String>, model: String) -> Self {
+        // Ollama URLs are still recognised here for callers that predate the explicit constructors
+        let api = if api_url.contains("11434") || api_url.contains("ollama") {
+            EmbeddingApi::Ollama
+        } else {
+            EmbeddingApi::OpenAi
+        };
+        // Local servers are limited by their own throughput, not a quota
+        let limiter = (api == EmbeddingApi::OpenAi).then(|| Arc::new(RateLimiter::new(DEFAULT_RATE_LIMITS)));
         Self {
-            client: Client::new(),
+            client,
             api_url,
             api_key,
             model,
+            api,
+            limiter,
+            usage: Arc::default(),
+            latency: Arc::default(),
+        }
+    }
+
+    /// Create client for an Azure OpenAI embedding deployment
+    pub fn azure(client: Client, endpoint: String, deployment: String, api_key: String, api_version: String) -> Self {
+        Self {
+            client,
+            api_url: format!(
+                "{}/openai/deployments/{}/embeddings?api-version={}",
+                endpoint.trim_end_matches('/'),
+                deployment,
+                api_version
+            ),
+            api_key: Some(api_key),
+            model: deployment,
+            api: EmbeddingApi::AzureOpenAi,
+            limiter: Some(Arc::new(RateLimiter::new(DEFAULT_RATE_LIMITS))),
+            usage: Arc::default(),
+            latency: Arc::default(),
+        }
+    }
+
+    /// Create client for a self-hosted Text Embeddings Inference server.
+    /// `model` is informational (TEI serves a single model) but is recorded with each index.
+    pub fn tei(client: Client, base_url: String, model: String) -> Self {
+        Self {
+            client,
+            api_url: format!("{}/embed", base_url.trim_end_matches('/')),
+            api_key: None,
+            model,
+            api: EmbeddingApi::Tei,
+            limiter: None,
+            usage: Arc::default(),
+            latency: Arc::default(),
         }
     }
 
     /// Create client for OpenAI
-    pub fn openai(api_key: String) -> Self {
+    pub fn openai(client: Client, api_key: String) -> Self {
         Self::new(
-            "https://api.openai.com/v1/embeddings".to_string(),
+            client,
+            format!("{}/embeddings", DEFAULT_OPENAI_BASE_URL),
             Some(api_key),
-            "text-embedding-3-small".to_string(),
+            DEFAULT_OPENAI_MODEL.to_string(),
         )
     }
 
+    /// Create client for OpenAI with an optional model and base URL (defaults when None)
+    pub fn openai_configured(client: Client, api_key: String, model: Option<String>, base_url: Option<&str>) -> Self {
+        let mut embedding_client = Self::openai(client, api_key);
+        if let Some(model) = model.filter(|model| !model.trim().is_empty()) {
+            embedding_client = embedding_client.with_model(model);
+        }
+        if let Some(base_url) = base_url.filter(|url| !url.trim().is_empty()) {
+            embedding_client = embedding_client.with_base_url(base_url.trim());
+        }
+        embedding_client
+    }
+
+    /// Use another model, e.g. text-embedding-3-large
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Send requests to an OpenAI-compatible server instead of api.openai.com.
+    /// `base_url` is the API root before `/embeddings`, e.g. `https://gateway.example/v1`.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.api_url = format!("{}/embeddings", base_url.trim_end_matches('/'));
+        self
+    }
+
     /// Create client for local Ollama
     /// Uses mxbai-embed-large for better multilingual support
-    pub fn ollama() -> Self {
+    pub fn ollama(client: Client) -> Self {
         Self::new(
+            client,
             "http://localhost:11434/api/embeddings".to_string(),
             None,
-            "mxbai-embed-large".to_string(),
+            DEFAULT_OLLAMA_MODEL.to_string(),
         )
     }
 
     /// Create client for local Ollama with nomic (smaller, faster)
-    pub fn ollama_nomic() -> Self {
+    pub fn ollama_nomic(client: Client) -> Self {
         Self::new(
+            client,
             "http://localhost:11434/api/embeddings".to_string(),
             None,
             "nomic-embed-text".to_string(),
         )
     }
 
+    /// Add a request's tokens to this model's usage; `reported` is the provider's count if it gave one
+    fn record_usage(&self, texts: &[String], reported: Option<u64>) {
+        let Ok(mut usage) = self.usage.lock() else {
+            return;
+        };
+        let entry = usage.entry(self.model.clone()).or_default();
+        entry.requests += 1;
+        match reported {
+            Some(tokens) => entry.tokens += tokens,
+            None => {
+                let estimated = estimate_tokens(texts) as u64;
+                entry.tokens += estimated;
+                entry.estimated_tokens += estimated;
+            }
+        }
+    }
+
+    /// Text to embed for a query searched against source-only or target-only
+    /// embeddings, where the query may be in neither language of the file.
+    ///
+    /// OpenAI and Azure models place translations close together on their own,
+    /// so the query is left as is. Local models (Ollama, TEI) are mostly
+    /// English-centric; for them the query is prefixed with its language tag,
+    /// the only cue they get that it isn't in the segments' language.
+    fn query_text(&self, query: String, language: Option<&str>) -> String {
+        match language.map(str::trim).filter(|language| !language.is_empty()) {
+            Some(language) if matches!(self.api, EmbeddingApi::Ollama | EmbeddingApi::Tei) => {
+                format!("({}) {}", language, query)
+            }
+            _ => query,
+        }
+    }
+
+    /// Whether this client talks to Ollama (different API format)
+    fn is_ollama(&self) -> bool {
+        self.api == EmbeddingApi::Ollama
+    }
+
     /// Get embeddings for texts
-    pub async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, String> {
+    pub async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, AppError> {
         if texts.is_empty() {
             return Ok(Vec::new());
         }
 
-        // Check if this is Ollama (different API format)
-        if self.api_url.contains("11434") || self.api_url.contains("ollama") {
-            return self.embed_ollama(texts).await;
+        // Only OpenAI-style APIs are rate limited; time spent waiting for the
+        // limiter isn't request latency, so acquire before starting the clock
+        if let (Some(limiter), EmbeddingApi::OpenAi | EmbeddingApi::AzureOpenAi) = (&self.limiter, &self.api) {
+            limiter.acquire(estimate_tokens(&texts)).await;
+        }
+
+        let count = texts.len();
+        let started = Instant::now();
+        let embeddings = self.request_embeddings(texts).await?;
+        if let Ok(mut latency) = self.latency.lock() {
+            latency.record(started.elapsed().as_secs_f64() * 1000.0 / count as f64);
+        }
+        Ok(embeddings)
+    }
+
+    /// Average and p95 time per embedded text, None before the first request
+    fn latency_report(&self) -> Option<LatencyReport> {
+        let latency = self.latency.lock().ok()?;
+        Some(LatencyReport {
+            model: self.model.clone(),
+            samples: latency.samples,
+            average_ms_per_text: latency.average_ms?,
+            p95_ms_per_text: latency.p95()?,
+        })
+    }
+
+    async fn request_embeddings(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, AppError> {
+        match self.api {
+            EmbeddingApi::Ollama => return self.embed_ollama(texts).await,
+            EmbeddingApi::Tei => return self.embed_tei(texts).await,
+            EmbeddingApi::OpenAi | EmbeddingApi::AzureOpenAi => {}
         }
 
         let request = EmbeddingRequest {
-            input: texts,
+            input: texts.clone(),
             model: self.model.clone(),
         };
 
         let mut req = self.client.post(&self.api_url).json(&request);
 
         if let Some(ref key) = self.api_key {
-            req = req.header("Authorization", format!("Bearer {}", key));
+            req = match self.api {
+                EmbeddingApi::AzureOpenAi => req.header("api-key", key),
+                _ => req.header("Authorization", format!("Bearer {}", key)),
+            };
         }
 
         let response = req
             .send()
             .await
-            .map_err(|e| format!("Embedding request failed: {}", e))?;
+            .map_err(|e| AppError::request("Embedding request failed", e))?;
 
         if !response.status().is_success() {
             let status = response.status();
             let body = response.text().await.unwrap_or_default();
-            return Err(format!("Embedding API error {}: {}", status, body));
+            return Err(AppError::from_status(status, format!("Embedding API error {}: {}", status, body)));
         }
 
         let result: EmbeddingResponse = response
             .json()
             .await
-            .map_err(|e| format!("Failed to parse embedding response: {}", e))?;
+            .map_err(|e| AppError::InvalidResponse(format!("Failed to parse embedding response: {}", e)))?;
+        self.record_usage(&texts, result.usage.map(|u| u.prompt_tokens));
 
         Ok(result.data.into_iter().map(|d| d.embedding).collect())
     }
 
     /// Ollama has a different API format
-    async fn embed_ollama(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, String> {
+    async fn embed_ollama(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, AppError> {
         let mut embeddings = Vec::new();
 
         // Ollama processes one at a time
@@ -311,12 +1733,12 @@ impl EmbeddingClient {
                 .json(&request)
                 .send()
                 .await
-                .map_err(|e| format!("Ollama request failed: {}", e))?;
+                .map_err(|e| AppError::request("Ollama request failed", e))?;
 
             if !response.status().is_success() {
                 let status = response.status();
                 let body = response.text().await.unwrap_or_default();
-                return Err(format!("Ollama error {}: {}", status, body));
+                return Err(AppError::from_status(status, format!("Ollama error {}: {}", status, body)));
             }
 
             #[derive(Deserialize)]
@@ -327,7 +1749,8 @@ impl EmbeddingClient {
             let result: OllamaResponse = response
                 .json()
                 .await
-                .map_err(|e| format!("Failed to parse Ollama response: {}", e))?;
+                .map_err(|e| AppError::InvalidResponse(format!("Failed to parse Ollama response: {}", e)))?;
+            self.record_usage(std::slice::from_ref(&text), None);
 
             embeddings.push(result.embedding);
         }
@@ -335,13 +1758,189 @@ impl EmbeddingClient {
         Ok(embeddings)
     }
 
+    /// TEI takes `inputs` and returns a bare array of vectors
+    async fn embed_tei(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, AppError> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+
+        for batch in texts.chunks(TEI_BATCH_SIZE) {
+            let response = self
+                .client
+                .post(&self.api_url)
+                .json(&serde_json::json!({ "inputs": batch }))
+                .send()
+                .await
+                .map_err(|e| AppError::request("TEI request failed", e))?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                return Err(AppError::from_status(status, format!("TEI error {}: {}", status, body)));
+            }
+
+            let result: Vec<Vec<f32>> = response
+                .json()
+                .await
+                .map_err(|e| AppError::InvalidResponse(format!("Failed to parse TEI response: {}", e)))?;
+            self.record_usage(batch, None);
+
+            embeddings.extend(result);
+        }
+
+        Ok(embeddings)
+    }
+
     /// Embed a single text
-    pub async fn embed_one(&self, text: String) -> Result<Vec<f32>, String> {
+    pub async fn embed_one(&self, text: String) -> Result<Vec<f32>, AppError> {
         let results = self.embed(vec![text]).await?;
         results
             .into_iter()
             .next()
-            .ok_or_else(|| "No embedding returned".to_string())
+            .ok_or_else(|| AppError::InvalidResponse("No embedding returned".to_string()))
+    }
+}
+
+// ============================================================================
+// Reranking
+// ============================================================================
+
+/// Hosted rerank API that reorders search candidates by relevance to the query.
+/// Cheaper and faster than asking an LLM to rerank.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum RerankProvider {
+    /// Cohere `/v2/rerank`
+    Cohere {
+        api_key: String,
+        #[serde(default = "default_cohere_rerank_model")]
+        model: String,
+    },
+    /// Voyage AI `/v1/rerank`
+    Voyage {
+        api_key: String,
+        #[serde(default = "default_voyage_rerank_model")]
+        model: String,
+    },
+}
+
+fn default_cohere_rerank_model() -> String {
+    "rerank-v3.5".to_string()
+}
+
+fn default_voyage_rerank_model() -> String {
+    "rerank-2".to_string()
+}
+
+/// Rerank settings applied to every `search_segments` call while set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RerankConfig {
+    #[serde(flatten)]
+    pub provider: RerankProvider,
+    /// Embedding hits sent to the reranker (at least the search limit)
+    #[serde(default = "default_rerank_candidates")]
+    pub candidates: usize,
+}
+
+fn default_rerank_candidates() -> usize {
+    50
+}
+
+/// How long a rerank call may take before the embedding order is used instead
+const RERANK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Clone)]
+struct Reranker {
+    client: Client,
+    config: RerankConfig,
+}
+
+/// One reranked candidate: its index in the request and its relevance
+#[derive(Deserialize)]
+struct RerankScore {
+    index: usize,
+    relevance_score: f32,
+}
+
+impl Reranker {
+    /// Relevance of each document to `query`, best first
+    async fn scores(&self, query: &str, documents: Vec<String>) -> Result<Vec<RerankScore>, AppError> {
+        let (url, api_key, body) = match &self.config.provider {
+            RerankProvider::Cohere { api_key, model } => (
+                "https://api.cohere.com/v2/rerank",
+                api_key,
+                serde_json::json!({ "model": model, "query": query, "documents": documents }),
+            ),
+            RerankProvider::Voyage { api_key, model } => (
+                "https://api.voyageai.com/v1/rerank",
+                api_key,
+                serde_json::json!({ "model": model, "query": query, "documents": documents }),
+            ),
+        };
+
+        let response = self
+            .client
+            .post(url)
+            .bearer_auth(api_key)
+            .timeout(RERANK_TIMEOUT)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| AppError::request("Rerank request failed", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(AppError::from_status(status, format!("Rerank error {}: {}", status, body)));
+        }
+
+        // Cohere returns {"results": [...]}, Voyage {"data": [...]}
+        #[derive(Deserialize)]
+        struct RerankResponse {
+            #[serde(alias = "data")]
+            results: Vec<RerankScore>,
+        }
+        let mut scores = response
+            .json::<RerankResponse>()
+            .await
+            .map_err(|e| AppError::InvalidResponse(format!("Failed to parse rerank response: {}", e)))?
+            .results;
+        scores.sort_by(|a, b| b.relevance_score.partial_cmp(&a.relevance_score).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(scores)
+    }
+
+    /// Reorder `results` by rerank relevance, keeping the best `limit`.
+    /// Candidates are judged on the text the search `mode` matched against.
+    async fn rerank(
+        &self,
+        query: &str,
+        mut results: Vec<SearchResult>,
+        mode: &SearchMode,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, AppError> {
+        if results.is_empty() {
+            return Ok(results);
+        }
+        let documents = results
+            .iter()
+            .map(|result| match mode {
+                SearchMode::Source => result.segment.source.clone(),
+                SearchMode::Target => result.segment.target.clone(),
+                SearchMode::Combined | SearchMode::Both => {
+                    format!("{}\n{}", result.segment.source, result.segment.target)
+                }
+            })
+            .collect();
+
+        let scores = self.scores(query, documents).await?;
+        let mut slots: Vec<Option<SearchResult>> = results.drain(..).map(Some).collect();
+        Ok(scores
+            .into_iter()
+            .filter_map(|score| {
+                let mut result = slots.get_mut(score.index)?.take()?;
+                result.rerank_score = Some(score.relevance_score);
+                Some(result)
+            })
+            .take(limit)
+            .collect())
     }
 }
 
@@ -352,6 +1951,16 @@ impl EmbeddingClient {
 pub struct RagState {
     pub store: Mutex<VectorStore>,
     pub client: Mutex<Option<EmbeddingClient>>,
+    /// Running index jobs, by file path
+    jobs: Mutex<HashMap<String, Arc<JobControl>>>,
+    /// Embeddings finished by interrupted index jobs, by file path
+    progress: Mutex<HashMap<String, IndexProgress>>,
+    /// Embeddings of texts seen before, in any file
+    cache: Mutex<EmbeddingCache>,
+    /// Embedding tokens per model, kept across client switches
+    usage: Arc<Mutex<HashMap<String, EmbeddingUsage>>>,
+    /// Rerank API applied to search results (None = embedding order)
+    reranker: Mutex<Option<Reranker>>,
 }
 
 impl RagState {
@@ -359,148 +1968,1494 @@ impl RagState {
         Self {
             store: Mutex::new(VectorStore::new()),
             client: Mutex::new(None),
+            jobs: Mutex::new(HashMap::new()),
+            progress: Mutex::new(HashMap::new()),
+            cache: Mutex::new(EmbeddingCache::new()),
+            usage: Arc::default(),
+            reranker: Mutex::new(None),
         }
     }
+
+    /// Count `client`'s usage in the session totals
+    fn track_usage(&self, mut client: EmbeddingClient) -> EmbeddingClient {
+        client.usage = self.usage.clone();
+        client
+    }
 }
 
-// ============================================================================
-// Public API for Tauri Commands
-// ============================================================================
+/// Which text of a segment an embedding was computed from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum EmbeddingKind {
+    Combined,
+    Source,
+    Target,
+}
 
-/// Initialize the embedding client
-pub fn init_client(state: &RagState, api_key: Option<String>, use_ollama: bool) -> Result<(), String> {
-    let client = if use_ollama {
-        EmbeddingClient::ollama()
-    } else if let Some(key) = api_key {
-        EmbeddingClient::openai(key)
-    } else {
-        return Err("No API key provided and Ollama not selected".to_string());
-    };
+/// Embeddings computed so far for one file, kept until its index is stored so
+/// an interrupted job can pick up where it stopped
+struct IndexProgress {
+    file_hash: String,
+    model: String,
+    combined_template: String,
+    embeddings: HashMap<(EmbeddingKind, String), Vec<f32>>,
+}
 
-    let mut guard = state.client.lock().map_err(|e| e.to_string())?;
-    *guard = Some(client);
-    Ok(())
+// Texts per embedding request while indexing; cancellation is checked between batches
+const INDEX_BATCH_SIZE: usize = 256;
+
+/// Cancellation flag and progress of an index job, registered in `RagState::jobs`
+/// while it runs so commands can inspect or cancel it
+#[derive(Default)]
+struct JobControl {
+    cancelled: AtomicBool,
+    /// Texts left to embed
+    remaining: AtomicUsize,
 }
 
-/// Index segments for a file
-/// When separate_embeddings is true, creates separate source/target embeddings for better search
-pub async fn index_segments(
-    state: &RagState,
+struct IndexJob<'a> {
+    state: &'a RagState,
     file_path: String,
-    file_hash: String,
-    segments: Vec<Segment>,
-    separate_embeddings: bool,
-) -> Result<usize, String> {
-    // Check if already indexed
-    {
-        let store = state.store.lock().map_err(|e| e.to_string())?;
-        if store.is_indexed(&file_path, &file_hash) {
-            return Ok(segments.len());
+    control: Arc<JobControl>,
+}
+
+impl<'a> IndexJob<'a> {
+    fn start(
+        state: &'a RagState,
+        file_path: &str,
+        file_hash: &str,
+        model: &str,
+        combined_template: &str,
+    ) -> Result<Self, AppError> {
+        let control = Arc::new(JobControl::default());
+        let mut jobs = state.jobs.lock().map_err(|e| e.to_string())?;
+        // A newer job for the same file supersedes the old one
+        if let Some(previous) = jobs.insert(file_path.to_string(), control.clone()) {
+            previous.cancelled.store(true, Ordering::Relaxed);
+        }
+        drop(jobs);
+
+        // Progress only carries over if the content, model and template are unchanged
+        let mut progress = state.progress.lock().map_err(|e| e.to_string())?;
+        let reusable = progress.get(file_path).is_some_and(|p| {
+            p.file_hash == file_hash && p.model == model && p.combined_template == combined_template
+        });
+        if !reusable {
+            progress.insert(
+                file_path.to_string(),
+                IndexProgress {
+                    file_hash: file_hash.to_string(),
+                    model: model.to_string(),
+                    combined_template: combined_template.to_string(),
+                    embeddings: HashMap::new(),
+                },
+            );
         }
+
+        Ok(Self {
+            state,
+            file_path: file_path.to_string(),
+            control,
+        })
     }
 
-    // Get embedding client
+    /// Count `texts` more texts to embed before the job is done
+    fn plan(&self, texts: usize) {
+        self.control.remaining.fetch_add(texts, Ordering::Relaxed);
+    }
+
+    /// Count `texts` planned texts as embedded (or not needing it)
+    fn advance(&self, texts: usize) {
+        let _ = self
+            .control
+            .remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| Some(left.saturating_sub(texts)));
+    }
+
+    /// Drop the saved progress once the index has been stored
+    fn complete(&self) -> Result<(), AppError> {
+        let mut progress = self.state.progress.lock().map_err(|e| e.to_string())?;
+        progress.remove(&self.file_path);
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), AppError> {
+        if self.control.cancelled.load(Ordering::Relaxed) {
+            return Err(AppError::Cancelled(format!("Indexing {} was cancelled", self.file_path)));
+        }
+        Ok(())
+    }
+
+    /// Embed `texts` (one per segment in `ids`) in batches, skipping segments an
+    /// earlier run already embedded and saving progress after every batch.
+    /// Stops at the first batch boundary after a cancel.
+    async fn embed(
+        &self,
+        client: &EmbeddingClient,
+        kind: EmbeddingKind,
+        ids: &[String],
+        texts: Vec<String>,
+    ) -> Result<Vec<Vec<f32>>, AppError> {
+        let mut embeddings: Vec<Option<Vec<f32>>> = {
+            let progress = self.state.progress.lock().map_err(|e| e.to_string())?;
+            let done = progress.get(&self.file_path).map(|p| &p.embeddings);
+            ids.iter()
+                .map(|id| done.and_then(|d| d.get(&(kind, id.clone()))).cloned())
+                .collect()
+        };
+        let missing: Vec<usize> = (0..ids.len()).filter(|&i| embeddings[i].is_none()).collect();
+        self.advance(ids.len() - missing.len());
+        if missing.len() < ids.len() {
+            log::info!(
+                "{}: resuming {:?} embeddings, {} of {} already done",
+                self.file_path,
+                kind,
+                ids.len() - missing.len(),
+                ids.len()
+            );
+        }
+
+        // Texts embedded before (in this or another file) come from the cache
+        let missing: Vec<usize> = {
+            let mut cache = self.state.cache.lock().map_err(|e| e.to_string())?;
+            missing
+                .into_iter()
+                .filter(|&i| match cache.get(&client.model, &texts[i]) {
+                    Some(embedding) => {
+                        embeddings[i] = Some(embedding);
+                        self.advance(1);
+                        false
+                    }
+                    None => true,
+                })
+                .collect()
+        };
+
+        for batch in missing.chunks(INDEX_BATCH_SIZE) {
+            self.check()?;
+            let batch_texts = batch.iter().map(|&i| texts[i].clone()).collect();
+            let batch_embeddings = client.embed(batch_texts).await?;
+            if batch_embeddings.len() != batch.len() {
+                return Err(AppError::InvalidResponse(format!(
+                    "Embedding count mismatch: {} vs {}",
+                    batch_embeddings.len(),
+                    batch.len()
+                )));
+            }
+
+            let mut cache = self.state.cache.lock().map_err(|e| e.to_string())?;
+            for (&i, embedding) in batch.iter().zip(&batch_embeddings) {
+                cache.insert(&client.model, &texts[i], embedding);
+            }
+            drop(cache);
+
+            let mut progress = self.state.progress.lock().map_err(|e| e.to_string())?;
+            let mut saved = progress.get_mut(&self.file_path);
+            for (&i, embedding) in batch.iter().zip(batch_embeddings) {
+                if let Some(saved) = saved.as_mut() {
+                    saved.embeddings.insert((kind, ids[i].clone()), embedding.clone());
+                }
+                embeddings[i] = Some(embedding);
+            }
+            self.advance(batch.len());
+        }
+        self.check()?;
+
+        Ok(embeddings.into_iter().flatten().collect())
+    }
+
+    /// Like `embed`, but texts that are None are skipped and stay None
+    async fn embed_present(
+        &self,
+        client: &EmbeddingClient,
+        kind: EmbeddingKind,
+        ids: &[String],
+        texts: Vec<Option<String>>,
+    ) -> Result<Vec<Option<Vec<f32>>>, AppError> {
+        let (present_ids, present_texts): (Vec<String>, Vec<String>) = ids
+            .iter()
+            .zip(&texts)
+            .filter_map(|(id, text)| text.clone().map(|text| (id.clone(), text)))
+            .unzip();
+        let mut embedded = self.embed(client, kind, &present_ids, present_texts).await?.into_iter();
+        Ok(texts
+            .iter()
+            .map(|text| text.as_ref().and_then(|_| embedded.next()))
+            .collect())
+    }
+}
+
+impl Drop for IndexJob<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut jobs) = self.state.jobs.lock() {
+            if jobs
+                .get(&self.file_path)
+                .is_some_and(|current| Arc::ptr_eq(current, &self.control))
+            {
+                jobs.remove(&self.file_path);
+            }
+        }
+    }
+}
+
+/// Cancel all running index jobs
+fn cancel_all_jobs(state: &RagState) -> Result<(), String> {
+    let jobs = state.jobs.lock().map_err(|e| e.to_string())?;
+    for control in jobs.values() {
+        control.cancelled.store(true, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+// ============================================================================
+// Public API for Tauri Commands
+// ============================================================================
+
+/// Initialize the embedding client.
+/// `model` and `base_url` override the OpenAI defaults; they're ignored for Ollama.
+pub fn init_client(
+    state: &RagState,
+    http: Client,
+    api_key: Option<String>,
+    use_ollama: bool,
+    model: Option<String>,
+    base_url: Option<String>,
+) -> Result<(), AppError> {
+    let client = if use_ollama {
+        EmbeddingClient::ollama(http)
+    } else if let Some(key) = api_key {
+        EmbeddingClient::openai_configured(http, key, model, base_url.as_deref())
+    } else {
+        return Err(AppError::Auth("No API key provided and Ollama not selected".to_string()));
+    };
+
+    let mut guard = state.client.lock().map_err(|e| e.to_string())?;
+    *guard = Some(state.track_usage(client));
+    Ok(())
+}
+
+/// Embedding backends that can be selected at runtime
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "provider", rename_all = "snake_case")]
+pub enum EmbeddingProvider {
+    #[serde(rename = "openai")]
+    OpenAi {
+        api_key: String,
+        /// Default: text-embedding-3-small
+        #[serde(default)]
+        model: Option<String>,
+        /// OpenAI-compatible API root. Default: https://api.openai.com/v1
+        #[serde(default)]
+        base_url: Option<String>,
+    },
+    Azure {
+        endpoint: String,
+        deployment: String,
+        api_key: String,
+        api_version: String,
+    },
+    Ollama,
+    OllamaNomic,
+    Tei { base_url: String, model: String },
+}
+
+impl EmbeddingProvider {
+    fn into_client(self, http: Client) -> EmbeddingClient {
+        match self {
+            EmbeddingProvider::OpenAi { api_key, model, base_url } => {
+                EmbeddingClient::openai_configured(http, api_key, model, base_url.as_deref())
+            }
+            EmbeddingProvider::Azure {
+                endpoint,
+                deployment,
+                api_key,
+                api_version,
+            } => EmbeddingClient::azure(http, endpoint, deployment, api_key, api_version),
+            EmbeddingProvider::Ollama => EmbeddingClient::ollama(http),
+            EmbeddingProvider::OllamaNomic => EmbeddingClient::ollama_nomic(http),
+            EmbeddingProvider::Tei { base_url, model } => EmbeddingClient::tei(http, base_url, model),
+        }
+    }
+}
+
+/// Replace the embedding client. Indexes built with a different model can't be
+/// searched with the new one, so they are cleared; returns the files to re-index.
+pub fn switch_provider(state: &RagState, http: Client, provider: EmbeddingProvider) -> Result<Vec<String>, String> {
+    let client = provider.into_client(http);
+
+    log::info!("Switching embedding model to {}", client.model);
+    // Jobs still embedding with the old client would store stale indexes
+    cancel_all_jobs(state)?;
+    let mut store = state.store.lock().map_err(|e| e.to_string())?;
+    let stale = store.files_not_built_with(&client.model);
+
+    let mut guard = state.client.lock().map_err(|e| e.to_string())?;
+    *guard = Some(state.track_usage(client));
+    drop(guard);
+
+    for path in &stale {
+        store.clear(path);
+    }
+    Ok(stale)
+}
+
+/// Point the current embedding client at a new HTTP client, e.g. after the
+/// proxy settings change
+pub fn set_http_client(state: &RagState, http: Client) -> Result<(), String> {
+    if let Some(reranker) = state.reranker.lock().map_err(|e| e.to_string())?.as_mut() {
+        reranker.client = http.clone();
+    }
+    if let Some(client) = state.client.lock().map_err(|e| e.to_string())?.as_mut() {
+        client.client = http;
+    }
+    Ok(())
+}
+
+/// Rerank search results with a hosted rerank API, or stop reranking with None
+pub fn set_reranker(state: &RagState, http: Client, config: Option<RerankConfig>) -> Result<(), String> {
+    *state.reranker.lock().map_err(|e| e.to_string())? = config.map(|config| Reranker { client: http, config });
+    Ok(())
+}
+
+// A sentence, its German and French translations, and an unrelated English sentence
+const MULTILINGUAL_ANCHOR: &str = "The invoice must be paid within thirty days.";
+const MULTILINGUAL_TRANSLATIONS: [&str; 2] = [
+    "Die Rechnung muss innerhalb von dreißig Tagen bezahlt werden.",
+    "La facture doit être payée dans un délai de trente jours.",
+];
+const MULTILINGUAL_UNRELATED: &str = "The children played football in the park yesterday.";
+// How much closer translations must be than an unrelated sentence in the same language
+const MULTILINGUAL_MARGIN: f32 = 0.05;
+
+/// Whether the embedding model places translations near each other
+#[derive(Debug, Clone, Serialize)]
+pub struct MultilingualCheck {
+    pub model: String,
+    /// Average similarity of a sentence to its translations
+    pub translation_similarity: f32,
+    /// Similarity of the same sentence to an unrelated one in its own language
+    pub unrelated_similarity: f32,
+    /// Set when translations don't score clearly above the unrelated sentence
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+/// Embed a sentence, its translations and an unrelated sentence to see whether
+/// the model matches across languages. Scores are compared with each other
+/// rather than a fixed threshold, since each model has its own score range.
+/// An English-only model scores a translation no higher than unrelated English.
+async fn check_multilingual(client: &EmbeddingClient) -> Result<MultilingualCheck, AppError> {
+    let texts = std::iter::once(MULTILINGUAL_ANCHOR)
+        .chain(MULTILINGUAL_TRANSLATIONS)
+        .chain([MULTILINGUAL_UNRELATED])
+        .map(str::to_string)
+        .collect();
+    let embeddings = client.embed(texts).await?;
+    let check = score_multilingual(&client.model, &embeddings)?;
+    if let Some(warning) = &check.warning {
+        log::warn!("{}", warning);
+    }
+    Ok(check)
+}
+
+/// Compare the check's embeddings: the anchor, one per translation, then the
+/// unrelated sentence. Any other count means the texts can't be paired up.
+fn score_multilingual(model: &str, embeddings: &[Vec<f32>]) -> Result<MultilingualCheck, AppError> {
+    let expected = MULTILINGUAL_TRANSLATIONS.len() + 2;
+    if embeddings.len() != expected {
+        return Err(AppError::InvalidResponse(format!(
+            "Embedding count mismatch: {} vs {}",
+            embeddings.len(),
+            expected
+        )));
+    }
+    let anchor = &embeddings[0];
+    let translations = &embeddings[1..expected - 1];
+    let unrelated = &embeddings[expected - 1];
+
+    let translation_similarity = translations
+        .iter()
+        .map(|translation| cosine_similarity(anchor, translation))
+        .sum::<f32>()
+        / translations.len() as f32;
+    let unrelated_similarity = cosine_similarity(anchor, unrelated);
+    let warning = (translation_similarity < unrelated_similarity + MULTILINGUAL_MARGIN).then(|| {
+        format!(
+            "{} doesn't appear to be multilingual: translations score {:.2}, unrelated text {:.2}. \
+             Searches across languages will be poor; consider a multilingual model such as \
+             text-embedding-3-small or bge-m3.",
+            model, translation_similarity, unrelated_similarity
+        )
+    });
+
+    Ok(MultilingualCheck {
+        model: model.to_string(),
+        translation_similarity,
+        unrelated_similarity,
+        warning,
+    })
+}
+
+/// Initialize the client if needed, send a tiny probe embedding to open the
+/// connection (and load the model, for local servers), and optionally load a
+/// persisted index, so the first real search doesn't pay for any of it.
+pub async fn warmup(
+    state: &RagState,
+    http: Client,
+    provider: Option<EmbeddingProvider>,
+    persisted: Option<PersistedIndex>,
+) -> Result<WarmupTiming, AppError> {
+    let started = Instant::now();
+
+    let client = {
+        let mut guard = state.client.lock().map_err(|e| e.to_string())?;
+        match (guard.as_ref(), provider) {
+            (Some(client), _) => client.clone(),
+            (None, Some(provider)) => guard.insert(state.track_usage(provider.into_client(http))).clone(),
+            (None, None) => {
+                return Err(AppError::NotInitialized("Embedding client not initialized".to_string()))
+            }
+        }
+    };
+    let init_ms = started.elapsed().as_millis() as u64;
+
+    let probe_started = Instant::now();
+    let dimension = client.embed_one("probe".to_string()).await?.len();
+    let probe_ms = probe_started.elapsed().as_millis() as u64;
+
+    // A soft check: a failure here shouldn't stop the warmup
+    let multilingual = match check_multilingual(&client).await {
+        Ok(check) => Some(check),
+        Err(e) => {
+            log::warn!("Multilingual check failed: {}", e);
+            None
+        }
+    };
+
+    let (load_ms, loaded_segments, evicted) = match persisted {
+        Some(persisted) => {
+            let load_started = Instant::now();
+            let segments = load_exported_index(&persisted.export_path, dimension)?;
+            let count = segments.len();
+            let evicted = {
+                let mut store = state.store.lock().map_err(|e| e.to_string())?;
+                // Exports don't record the template; assume the default
+                store.store(
+                    persisted.file_path,
+                    persisted.file_hash,
+                    client.model.clone(),
+                    DEFAULT_COMBINED_TEMPLATE.to_string(),
+                    segments,
+                )
+            };
+            (Some(load_started.elapsed().as_millis() as u64), Some(count), evicted)
+        }
+        None => (None, None, Vec::new()),
+    };
+
+    Ok(WarmupTiming {
+        init_ms,
+        probe_ms,
+        load_ms,
+        loaded_segments,
+        total_ms: started.elapsed().as_millis() as u64,
+        evicted,
+        multilingual,
+    })
+}
+
+/// Read an index written by `export_index`; it must include embeddings of `dimension`
+fn load_exported_index(export_path: &str, dimension: usize) -> Result<Vec<IndexedSegment>, String> {
+    let bytes = std::fs::read(export_path)
+        .map_err(|e| format!("Failed to read {}: {}", export_path, e))?;
+    let imported: Vec<ImportedSegment> = serde_json::from_slice(&bytes)
+        .map_err(|e| format!("Failed to parse {}: {}", export_path, e))?;
+
+    let mut first_by_pair: HashMap<(String, String), usize> = HashMap::new();
+    let mut segments: Vec<IndexedSegment> = Vec::with_capacity(imported.len());
+    for item in imported {
+        let embedding = item
+            .embedding
+            .ok_or_else(|| format!("{} was exported without embeddings", export_path))?;
+        if embedding.len() != dimension {
+            return Err(format!(
+                "{} has {}-dim embeddings but the current model produces {}",
+                export_path,
+                embedding.len(),
+                dimension
+            ));
+        }
+
+        // Rebuild duplicate links, which aren't part of the export
+        let pair = (item.segment.source.clone(), item.segment.target.clone());
+        let duplicate_of = match first_by_pair.get(&pair) {
+            Some(&first) => {
+                let id = item.segment.id.clone();
+                segments[first].duplicate_ids.push(id);
+                Some(segments[first].segment.id.clone())
+            }
+            None => {
+                first_by_pair.insert(pair, segments.len());
+                None
+            }
+        };
+
+        segments.push(IndexedSegment {
+            segment: item.segment,
+            position: item.position,
+            embedding: Embedding::F32(embedding),
+            source_embedding: item.source_embedding.map(Embedding::F32),
+            target_embedding: item.target_embedding.map(Embedding::F32),
+            duplicate_of,
+            duplicate_ids: Vec::new(),
+        });
+    }
+
+    // Positions index into the segment list
+    segments.sort_by_key(|s| s.position);
+    if segments.iter().enumerate().any(|(i, s)| s.position != i) {
+        return Err(format!("{} has gaps in segment positions", export_path));
+    }
+
+    Ok(segments)
+}
+
+/// Embedding tokens and estimated cost per model since the app started
+pub fn embedding_usage(state: &RagState) -> Result<EmbeddingUsageReport, String> {
+    let mut models = state.usage.lock().map_err(|e| e.to_string())?.clone();
+    for (model, usage) in models.iter_mut() {
+        usage.cost_usd = embedding_price_per_million(model)
+            .map(|price| usage.tokens as f64 / 1_000_000.0 * price);
+    }
+    Ok(EmbeddingUsageReport {
+        total_tokens: models.values().map(|u| u.tokens).sum(),
+        total_cost_usd: models.values().filter_map(|u| u.cost_usd).sum(),
+        models,
+    })
+}
+
+/// Change the request/token pacing of a hosted embedding provider (None = unlimited).
+/// Has no effect for local providers, which aren't rate limited.
+pub fn set_rate_limits(state: &RagState, limits: Option<RateLimits>) -> Result<(), String> {
+    let mut guard = state.client.lock().map_err(|e| e.to_string())?;
+    let client = guard.as_mut().ok_or("Embedding client not initialized")?;
+    if matches!(client.api, EmbeddingApi::OpenAi | EmbeddingApi::AzureOpenAi) {
+        client.limiter = limits.map(|limits| Arc::new(RateLimiter::new(limits)));
+    }
+    Ok(())
+}
+
+/// Ollama model in use, or the default one if Ollama isn't the configured provider
+pub fn ollama_model(state: &RagState) -> Result<String, String> {
+    let guard = state.client.lock().map_err(|e| e.to_string())?;
+    Ok(match guard.as_ref() {
+        Some(client) if client.is_ollama() => client.model.clone(),
+        _ => DEFAULT_OLLAMA_MODEL.to_string(),
+    })
+}
+
+/// Text embedded for the combined source+target vector unless a template is given
+pub const DEFAULT_COMBINED_TEMPLATE: &str = "Source: {source} Target: {target}";
+
+/// Fill `{source}`, `{target}`, `{origin}` and `{status}` in a combined-text template
+fn render_combined_text(template: &str, segment: &Segment) -> String {
+    template
+        .replace("{source}", &segment.source)
+        .replace("{target}", &segment.target)
+        .replace("{origin}", segment.origin.as_deref().unwrap_or(""))
+        .replace("{status}", &segment.status)
+}
+
+// Stands in for a combined text that renders blank (some providers reject empty input)
+const EMPTY_TEXT_PLACEHOLDER: &str = "(empty)";
+
+/// Texts to embed for each unique segment
+struct EmbeddingTexts {
+    combined: Vec<String>,
+    /// None where the source is blank, so no meaningless vector is stored
+    source: Vec<Option<String>>,
+    /// None where the target is blank (e.g. untranslated segments)
+    target: Vec<Option<String>>,
+}
+
+impl EmbeddingTexts {
+    fn new(segments: &[&Segment], combined_template: &str) -> Self {
+        let present = |text: &str| (!text.trim().is_empty()).then(|| text.to_string());
+        Self {
+            combined: segments
+                .iter()
+                .map(|s| {
+                    let text = render_combined_text(combined_template, s);
+                    if text.trim().is_empty() {
+                        EMPTY_TEXT_PLACEHOLDER.to_string()
+                    } else {
+                        text
+                    }
+                })
+                .collect(),
+            source: segments.iter().map(|s| present(&s.source)).collect(),
+            target: segments.iter().map(|s| present(&s.target)).collect(),
+        }
+    }
+
+    /// Number of source and target texts to embed separately
+    fn separate_count(&self) -> usize {
+        self.source.iter().chain(&self.target).flatten().count()
+    }
+}
+
+/// What indexing a file would cost, computed without calling the provider
+#[derive(Debug, Serialize)]
+pub struct IndexEstimate {
+    pub model: String,
+    /// Texts that would be sent to the provider
+    pub texts: usize,
+    /// Texts skipped because they're cached or saved from an interrupted run
+    pub reused: usize,
+    pub tokens: u64,
+    /// HTTP requests, after batching
+    pub requests: u64,
+    /// None if the model's price is unknown
+    pub cost_usd: Option<f64>,
+    /// Lower bound on duration imposed by the rate limiter (None if unlimited)
+    pub min_duration_secs: Option<f64>,
+}
+
+/// Estimate tokens, requests and cost of indexing `segments` with the current client.
+/// Mirrors `index_segments` (duplicates embedded once, blank texts skipped) but makes no network calls.
+pub fn estimate_indexing(
+    state: &RagState,
+    file_path: &str,
+    segments: &[Segment],
+    separate_embeddings: bool,
+    combined_template: Option<String>,
+) -> Result<IndexEstimate, AppError> {
     let client = {
         let guard = state.client.lock().map_err(|e| e.to_string())?;
-        guard.clone().ok_or("Embedding client not initialized")?
+        guard
+            .clone()
+            .ok_or_else(|| AppError::NotInitialized("Embedding client not initialized".to_string()))?
     };
+    let combined_template = combined_template.unwrap_or_else(|| DEFAULT_COMBINED_TEMPLATE.to_string());
+
+    let mut seen: HashSet<(&str, &str)> = HashSet::new();
+    let unique: Vec<&Segment> = segments
+        .iter()
+        .filter(|s| seen.insert((s.source.as_str(), s.target.as_str())))
+        .collect();
+    let ids: Vec<String> = unique.iter().map(|s| s.id.clone()).collect();
+    let texts = EmbeddingTexts::new(&unique, &combined_template);
+
+    let mut kinds: Vec<(EmbeddingKind, Vec<Option<String>>)> =
+        vec![(EmbeddingKind::Combined, texts.combined.into_iter().map(Some).collect())];
+    if separate_embeddings {
+        kinds.push((EmbeddingKind::Source, texts.source));
+        kinds.push((EmbeddingKind::Target, texts.target));
+    }
+
+    // Texts a real run would send, minus those it would reuse
+    let mut to_embed: Vec<Vec<String>> = Vec::new();
+    let mut reused = 0;
+    {
+        let progress = state.progress.lock().map_err(|e| e.to_string())?;
+        let saved = progress
+            .get(file_path)
+            .filter(|p| p.model == client.model && p.combined_template == combined_template);
+        let cache = state.cache.lock().map_err(|e| e.to_string())?;
+        for (kind, kind_texts) in kinds {
+            let present: Vec<(&String, String)> = ids
+                .iter()
+                .zip(kind_texts)
+                .filter_map(|(id, text)| text.map(|text| (id, text)))
+                .collect();
+            let count = present.len();
+            let texts: Vec<String> = present
+                .into_iter()
+                .filter(|(id, text)| {
+                    !saved.is_some_and(|p| p.embeddings.contains_key(&(kind, (*id).clone())))
+                        && !cache.contains(&client.model, text)
+                })
+                .map(|(_, text)| text)
+                .collect();
+            reused += count - texts.len();
+            to_embed.push(texts);
+        }
+    }
+
+    let mut requests: u64 = 0;
+    let mut tokens: u64 = 0;
+    for kind_texts in &to_embed {
+        tokens += estimate_tokens(kind_texts) as u64;
+        for batch in kind_texts.chunks(INDEX_BATCH_SIZE) {
+            requests += match client.api {
+                EmbeddingApi::Ollama => batch.len(),
+                EmbeddingApi::Tei => batch.len().div_ceil(TEI_BATCH_SIZE),
+                EmbeddingApi::OpenAi | EmbeddingApi::AzureOpenAi => 1,
+            } as u64;
+        }
+    }
+
+    let min_duration_secs = client.limiter.as_ref().map(|limiter| {
+        let limits = limiter.limits;
+        let by_requests = requests as f64 / limits.requests_per_minute.max(1) as f64;
+        let by_tokens = tokens as f64 / limits.tokens_per_minute.max(1) as f64;
+        by_requests.max(by_tokens) * 60.0
+    });
+
+    Ok(IndexEstimate {
+        cost_usd: embedding_price_per_million(&client.model)
+            .map(|price| tokens as f64 / 1_000_000.0 * price),
+        model: client.model,
+        texts: to_embed.iter().map(Vec::len).sum(),
+        reused,
+        tokens,
+        requests,
+        min_duration_secs,
+    })
+}
+
+/// BLAKE3 hash (hex) of the segments being indexed: the `file_hash` to index
+/// them under. Hashing what read_sdlxliff returned, rather than the bytes on
+/// disk, means unsaved edits change the hash too.
+pub fn hash_segments(segments: &[Segment]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for segment in segments {
+        let percent = segment.percent.map(|p| p.to_string()).unwrap_or_default();
+        let fields = [
+            segment.id.as_str(),
+            &segment.source,
+            &segment.target,
+            &segment.status,
+            &percent,
+            segment.origin.as_deref().unwrap_or_default(),
+        ];
+        for field in fields {
+            // Length-prefixed so moving text between fields changes the hash
+            hasher.update(&(field.len() as u64).to_le_bytes());
+            hasher.update(field.as_bytes());
+        }
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Index segments for a file
+/// When separate_embeddings is true, creates separate source/target embeddings for better search
+/// quantization controls how the embeddings are stored (see `Quantization` for the recall trade-off)
+/// combined_template is the text embedded for the combined vector (see `render_combined_text`)
+pub async fn index_segments(
+    state: &RagState,
+    file_path: String,
+    file_hash: String,
+    segments: Vec<Segment>,
+    separate_embeddings: bool,
+    quantization: Quantization,
+    combined_template: Option<String>,
+) -> Result<IndexOutcome, AppError> {
+    let combined_template = combined_template.unwrap_or_else(|| DEFAULT_COMBINED_TEMPLATE.to_string());
+    if !combined_template.contains("{source}") && !combined_template.contains("{target}") {
+        return Err(AppError::Other(
+            "Combined template must contain {source} or {target}".to_string(),
+        ));
+    }
+
+    // Get embedding client
+    let client = {
+        let guard = state.client.lock().map_err(|e| e.to_string())?;
+        guard
+            .clone()
+            .ok_or_else(|| AppError::NotInitialized("Embedding client not initialized".to_string()))?
+    };
+
+    // Check if already indexed (with the current model and storage)
+    let missing_separate = {
+        let mut store = state.store.lock().map_err(|e| e.to_string())?;
+        let index_model = store.index_model(&file_path);
+        let unchanged = store.is_indexed(&file_path, &file_hash)
+            && index_model.is_some_and(|m| {
+                m.model == client.model
+                    && m.quantization == quantization
+                    && m.combined_template == combined_template
+            });
+        let has_separate = index_model.is_some_and(|m| m.separate_embeddings);
+        if unchanged && (has_separate || !separate_embeddings) {
+            store.touch(&file_path);
+            return Ok(IndexOutcome {
+                count: segments.len(),
+                evicted: Vec::new(),
+                added_separate_embeddings: false,
+            });
+        }
+        unchanged && separate_embeddings
+    };
+
+    // Nothing is stored until every batch is embedded, so a cancelled job leaves
+    // the previous index (if any) untouched; finished batches are kept for a re-run
+    let job = IndexJob::start(state, &file_path, &file_hash, &client.model, &combined_template)?;
+
+    // Same content, only source/target embeddings missing: keep the combined ones
+    if missing_separate {
+        if let Some(outcome) = add_separate_embeddings(state, &job, &client, &file_path, &file_hash).await? {
+            return Ok(outcome);
+        }
+    }
+
+    // Group exact-duplicate source+target pairs so each unique pair is embedded once
+    let mut slot_by_pair: HashMap<(&str, &str), usize> = HashMap::new();
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let slots: Vec<usize> = segments
+        .iter()
+        .enumerate()
+        .map(|(i, s)| {
+            let slot = *slot_by_pair
+                .entry((s.source.as_str(), s.target.as_str()))
+                .or_insert(groups.len());
+            if slot == groups.len() {
+                groups.push(Vec::new());
+            }
+            groups[slot].push(i);
+            slot
+        })
+        .collect();
+
+    let unique: Vec<&Segment> = groups.iter().map(|g| &segments[g[0]]).collect();
+    if unique.len() < segments.len() {
+        log::info!(
+            "{}: {} duplicate segments share embeddings with earlier occurrences",
+            file_path,
+            segments.len() - unique.len()
+        );
+    }
+
+    // Embeddings are saved per unique segment, under its first occurrence's id
+    let unique_ids: Vec<String> = unique.iter().map(|s| s.id.clone()).collect();
+
+    // Prepare texts for embedding (blank ones are substituted or skipped)
+    let texts = EmbeddingTexts::new(&unique, &combined_template);
+
+    job.plan(texts.combined.len() + if separate_embeddings { texts.separate_count() } else { 0 });
+
+    // Get combined embeddings
+    let combined_embeddings = job
+        .embed(&client, EmbeddingKind::Combined, &unique_ids, texts.combined)
+        .await?;
+
+    if combined_embeddings.len() != unique.len() {
+        return Err(AppError::InvalidResponse(format!(
+            "Embedding count mismatch: {} vs {}",
+            combined_embeddings.len(),
+            unique.len()
+        )));
+    }
+
+    // Optionally get separate source/target embeddings
+    let (source_embeddings, target_embeddings) = if separate_embeddings {
+        let source_emb = job
+            .embed_present(&client, EmbeddingKind::Source, &unique_ids, texts.source)
+            .await?;
+        let target_emb = job
+            .embed_present(&client, EmbeddingKind::Target, &unique_ids, texts.target)
+            .await?;
+
+        (Some(source_emb), Some(target_emb))
+    } else {
+        (None, None)
+    };
+
+    // Link each duplicate to the first occurrence of its source+target pair
+    let duplicate_links: Vec<(Option<String>, Vec<String>)> = slots
+        .iter()
+        .enumerate()
+        .map(|(i, &slot)| {
+            let group = &groups[slot];
+            if group[0] == i {
+                (None, group[1..].iter().map(|&j| segments[j].id.clone()).collect())
+            } else {
+                (Some(segments[group[0]].id.clone()), Vec::new())
+            }
+        })
+        .collect();
+
+    // Create indexed segments
+    let indexed: Vec<IndexedSegment> = segments
+        .into_iter()
+        .zip(duplicate_links)
+        .enumerate()
+        .map(|(i, (segment, (duplicate_of, duplicate_ids)))| {
+            let slot = slots[i];
+            IndexedSegment {
+                segment,
+                position: i,
+                embedding: Embedding::new(combined_embeddings[slot].clone(), quantization),
+                source_embedding: source_embeddings
+                    .as_ref()
+                    .and_then(|v| v[slot].clone())
+                    .map(|e| Embedding::new(e, quantization)),
+                target_embedding: target_embeddings
+                    .as_ref()
+                    .and_then(|v| v[slot].clone())
+                    .map(|e| Embedding::new(e, quantization)),
+                duplicate_of,
+                duplicate_ids,
+            }
+        })
+        .collect();
+
+    let count = indexed.len();
+
+    // Store in vector store
+    let evicted = {
+        let mut store = state.store.lock().map_err(|e| e.to_string())?;
+        // Checked under the store lock so a clear can't slip in before storing
+        job.check()?;
+        store.store(file_path, file_hash, client.model.clone(), combined_template, indexed)
+    };
+    job.complete()?;
+    // The index is usable either way; a failed save only costs re-embedding later
+    if let Err(e) = save_embedding_cache(state).await {
+        log::warn!("Failed to save the embedding cache: {}", e);
+    }
+
+    Ok(IndexOutcome {
+        count,
+        evicted,
+        added_separate_embeddings: false,
+    })
+}
+
+/// Compute only the source/target embeddings for an index that has combined ones.
+/// Returns None if the index changed while embedding (the caller then re-indexes fully).
+async fn add_separate_embeddings(
+    state: &RagState,
+    job: &IndexJob<'_>,
+    client: &EmbeddingClient,
+    file_path: &str,
+    file_hash: &str,
+) -> Result<Option<IndexOutcome>, AppError> {
+    let (count, ids, texts) = {
+        let store = state.store.lock().map_err(|e| e.to_string())?;
+        let (Some(segments), Some(index_model)) =
+            (store.indices.get(file_path), store.index_model(file_path))
+        else {
+            return Ok(None);
+        };
+        let unique: Vec<&Segment> = segments
+            .iter()
+            .filter(|s| s.duplicate_of.is_none())
+            .map(|s| &s.segment)
+            .collect();
+        let ids: Vec<String> = unique.iter().map(|s| s.id.clone()).collect();
+        let texts = EmbeddingTexts::new(&unique, &index_model.combined_template);
+        (segments.len(), ids, texts)
+    };
+
+    log::info!(
+        "{}: adding source/target embeddings for {} segments, keeping combined embeddings",
+        file_path,
+        ids.len()
+    );
+    job.plan(texts.separate_count());
+    let source_emb = job.embed_present(client, EmbeddingKind::Source, &ids, texts.source).await?;
+    let target_emb = job.embed_present(client, EmbeddingKind::Target, &ids, texts.target).await?;
+
+    let evicted = {
+        let mut store = state.store.lock().map_err(|e| e.to_string())?;
+        // Checked under the store lock so a clear can't slip in before storing
+        job.check()?;
+        store.add_separate_embeddings(
+            file_path,
+            file_hash,
+            source_emb.into_iter().zip(target_emb).collect(),
+        )
+    };
+    if evicted.is_some() {
+        job.complete()?;
+        if let Err(e) = save_embedding_cache(state).await {
+            log::warn!("Failed to save the embedding cache: {}", e);
+        }
+    }
+    Ok(evicted.map(|evicted| IndexOutcome {
+        count,
+        evicted,
+        added_separate_embeddings: true,
+    }))
+}
+
+/// Search for similar segments
+/// - mode: search combined, source-only, target-only, or both
+/// - min_score: minimum relevance threshold (0.0-1.0, default 0.5)
+/// - context_window: number of preceding/following segments to attach to each hit
+/// - sort: return the kept hits by score (default) or in document order
+/// - mmr_lambda: trade relevance for diversity among the returned hits
+/// - id_filter: restrict scoring to a set of segment ids
+/// - query_language: language of the query, used by Source/Target searches
+///   (see `EmbeddingClient::query_text`); without it the query is embedded as is
+/// - both_weights: blend the Both mode scores (e.g. 0.5 source, 0.3 target,
+///   0.2 combined) instead of taking the highest
+/// - normalization: add a 0-1 `relevance` (min-max or sigmoid) next to the raw score
+///
+/// When a reranker is set (`set_reranker`), the best `candidates` embedding hits
+/// are reordered by the rerank API before `limit` is applied, and MMR is skipped
+/// so the reranker sees the plain relevance pool. If the rerank call fails or
+/// times out, the search runs as if no reranker were set.
+pub async fn search_segments(
+    state: &RagState,
+    file_path: String,
+    query: String,
+    options: SearchOptions,
+) -> Result<Vec<SearchResult>, AppError> {
+    let (model, query_embedding) = embed_query(state, query.clone(), &options).await?;
+    let reranker = state.reranker.lock().map_err(|e| e.to_string())?.clone();
+
+    let Some(reranker) = reranker else {
+        let mut store = state.store.lock().map_err(|e| e.to_string())?;
+        let mut results = search_file(&mut store, &file_path, &model, &query_embedding, &options)?;
+        attach_context(&store, &mut results, options.context_window);
+        return Ok(results);
+    };
+
+    // Give the reranker a wider pool than will be returned, ranked by relevance
+    // alone: MMR would drop candidates the reranker rates highly
+    let candidate_options = SearchOptions {
+        limit: options.limit.max(reranker.config.candidates),
+        sort: SortOrder::Score,
+        mmr_lambda: None,
+        ..options.clone()
+    };
+    let candidates = {
+        let mut store = state.store.lock().map_err(|e| e.to_string())?;
+        search_file(&mut store, &file_path, &model, &query_embedding, &candidate_options)?
+    };
+
+    let mut results = match reranker.rerank(&query, candidates, &options.mode, options.limit).await {
+        Ok(mut results) => {
+            if options.sort == SortOrder::DocumentPosition {
+                results.sort_by_key(|r| r.position);
+            }
+            results
+        }
+        Err(e) => {
+            log::warn!("Reranking failed, keeping embedding order: {}", e);
+            let mut store = state.store.lock().map_err(|e| e.to_string())?;
+            search_file(&mut store, &file_path, &model, &query_embedding, &options)?
+        }
+    };
+
+    let store = state.store.lock().map_err(|e| e.to_string())?;
+    attach_context(&store, &mut results, options.context_window);
+    Ok(results)
+}
+
+/// Search several indexed files at once and merge their hits into one ranking.
+/// Takes the same options as `search_segments`; `limit` applies to the merged
+/// results, MMR diversifies within each file, and document order sorts by file
+/// (in the order given) then position. Files that aren't indexed are skipped.
+pub async fn search_corpus(
+    state: &RagState,
+    file_paths: Vec<String>,
+    query: String,
+    options: SearchOptions,
+) -> Result<Vec<SearchResult>, AppError> {
+    let mut seen = HashSet::new();
+    let file_paths: Vec<String> = file_paths.into_iter().filter(|path| seen.insert(path.clone())).collect();
+    if file_paths.is_empty() {
+        return Err(AppError::Other("file_paths must not be empty".to_string()));
+    }
+
+    let (model, query_embedding) = embed_query(state, query, &options).await?;
+
+    let mut store = state.store.lock().map_err(|e| e.to_string())?;
+    let mut results = Vec::new();
+    for file_path in &file_paths {
+        // Each file's own best `limit` hits are enough to fill the merged `limit`
+        results.extend(search_file(&mut store, file_path, &model, &query_embedding, &options)?);
+    }
+
+    results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    // Min-max ranges must span all files, not each one separately
+    normalize_scores(&mut results, options.normalization);
+    results.truncate(options.limit);
+    if options.sort == SortOrder::DocumentPosition {
+        let file_order: HashMap<&str, usize> =
+            file_paths.iter().enumerate().map(|(i, path)| (path.as_str(), i)).collect();
+        results.sort_by_key(|r| (file_order[r.file_path.as_str()], r.position));
+    }
+
+    attach_context(&store, &mut results, options.context_window);
+    Ok(results)
+}
+
+/// Segments nearest to an indexed segment, best first, using its stored combined
+/// embedding so no embedding call is made. The segment itself is left out.
+pub fn similar_to_segment(
+    state: &RagState,
+    file_path: &str,
+    segment_id: &str,
+    limit: usize,
+) -> Result<Vec<SearchResult>, AppError> {
+    let mut store = state.store.lock().map_err(|e| e.to_string())?;
+    let embedding = store
+        .indexed(file_path, segment_id)
+        .map(|indexed| indexed.embedding.to_f32().into_owned())
+        .ok_or_else(|| AppError::NotFound(format!("Segment {} is not indexed in {}", segment_id, file_path)))?;
+    store.touch(file_path);
+
+    let options = SearchOptions {
+        // One extra, since the segment is its own best match
+        limit: limit.saturating_add(1),
+        mode: SearchMode::Combined,
+        min_score: -1.0,
+        context_window: 0,
+        sort: SortOrder::Score,
+        collapse_duplicates: false,
+        mmr_lambda: None,
+        id_filter: None,
+        query_language: None,
+        both_weights: None,
+        normalization: ScoreNormalization::None,
+    };
+    let mut results = store.search(file_path, &embedding, &options);
+    results.retain(|result| result.segment.id != segment_id);
+    results.truncate(limit);
+    Ok(results)
+}
+
+/// Embed a search query with the current client after validating `options`.
+/// Returns the model name alongside the embedding.
+async fn embed_query(state: &RagState, query: String, options: &SearchOptions) -> Result<(String, Vec<f32>), AppError> {
+    if let Some(lambda) = options.mmr_lambda {
+        if !(0.0..=1.0).contains(&lambda) {
+            return Err(AppError::Other(format!("mmr_lambda must be between 0 and 1, got {}", lambda)));
+        }
+    }
+    if let Some(weights) = &options.both_weights {
+        weights.validate()?;
+    }
+
+    // Get embedding client
+    let client = {
+        let guard = state.client.lock().map_err(|e| e.to_string())?;
+        guard
+            .clone()
+            .ok_or_else(|| AppError::NotInitialized("Embedding client not initialized".to_string()))?
+    };
+
+    // Embed query, tagged with its language when matched against one side only
+    let query = match options.mode {
+        SearchMode::Source | SearchMode::Target => client.query_text(query, options.query_language.as_deref()),
+        SearchMode::Combined | SearchMode::Both => query,
+    };
+    let query_embedding = client.embed_one(query).await?;
+    Ok((client.model, query_embedding))
+}
+
+/// Search one file with an embedded query, checking it was indexed with the same model
+fn search_file(
+    store: &mut VectorStore,
+    file_path: &str,
+    model: &str,
+    query_embedding: &[f32],
+    options: &SearchOptions,
+) -> Result<Vec<SearchResult>, AppError> {
+    // Scores across different models are meaningless (all near zero)
+    if let Some(index_model) = store.index_model(file_path) {
+        if index_model.model != model || index_model.dimension != query_embedding.len() {
+            return Err(AppError::Other(format!(
+                "{} was indexed with {} ({} dims) but the current model is {} ({} dims); re-index it",
+                file_path,
+                index_model.model,
+                index_model.dimension,
+                model,
+                query_embedding.len()
+            )));
+        }
+    }
+
+    store.touch(file_path);
+    let results = store.search(file_path, query_embedding, options);
+
+    if results.iter().any(|r| r.fallback) {
+        log::warn!(
+            "{} has no separate source/target embeddings; {:?} search used the combined embedding",
+            file_path,
+            options.mode
+        );
+    }
+    Ok(results)
+}
+
+/// Attach surrounding segments for context
+fn attach_context(store: &VectorStore, results: &mut [SearchResult], context_window: usize) {
+    if context_window == 0 {
+        return;
+    }
+    for result in results {
+        result.context = store.neighbors(&result.file_path, &result.segment.id, context_window);
+    }
+}
+
+/// Get RAG stats
+pub fn get_stats(state: &RagState) -> Result<HashMap<String, usize>, String> {
+    let store = state.store.lock().map_err(|e| e.to_string())?;
+    Ok(store.stats())
+}
+
+/// Get RAG size stats (embedding dimension and approximate memory per file)
+pub fn get_detailed_stats(state: &RagState) -> Result<IndexStats, String> {
+    let store = state.store.lock().map_err(|e| e.to_string())?;
+    Ok(store.detailed_stats())
+}
+
+/// Find near-duplicate segment pairs in an indexed file using the stored embeddings
+pub fn find_similar_pairs(
+    state: &RagState,
+    file_path: &str,
+    threshold: f32,
+    limit: usize,
+) -> Result<Vec<SimilarPair>, String> {
+    if !(-1.0..=1.0).contains(&threshold) {
+        return Err(format!("threshold must be between -1 and 1, got {}", threshold));
+    }
 
-    // Prepare combined texts for embedding
-    let combined_texts: Vec<String> = segments
-        .iter()
-        .map(|s| format!("Source: {} Target: {}", s.source, s.target))
-        .collect();
+    let mut store = state.store.lock().map_err(|e| e.to_string())?;
+    store.touch(file_path);
+    store
+        .similar_pairs(file_path, threshold, limit)
+        .ok_or_else(|| format!("File not indexed: {}", file_path))
+}
 
-    // Get combined embeddings
-    let combined_embeddings = client.embed(combined_texts).await?;
+/// Load the persisted embedding cache from `path` (and save there from now on)
+/// Returns the number of cached embeddings
+pub fn open_embedding_cache(state: &RagState, path: PathBuf) -> Result<usize, String> {
+    let mut cache = state.cache.lock().map_err(|e| e.to_string())?;
+    cache.open(path)?;
+    Ok(cache.len())
+}
 
-    if combined_embeddings.len() != segments.len() {
-        return Err(format!(
-            "Embedding count mismatch: {} vs {}",
-            combined_embeddings.len(),
-            segments.len()
-        ));
+/// Write the embedding cache to disk if entries changed since the last save
+pub async fn save_embedding_cache(state: &RagState) -> Result<(), String> {
+    // Serialize under the lock, write without it
+    let snapshot = state.cache.lock().map_err(|e| e.to_string())?.snapshot()?;
+    let Some((path, bytes)) = snapshot else {
+        return Ok(());
+    };
+    let result = tauri::async_runtime::spawn_blocking(move || write_atomically(&path, &bytes))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|r| r);
+    if result.is_err() {
+        // Keep the changes pending so the next save retries them
+        state.cache.lock().map_err(|e| e.to_string())?.dirty = true;
     }
+    result
+}
 
-    // Optionally get separate source/target embeddings
-    let (source_embeddings, target_embeddings) = if separate_embeddings {
-        let source_texts: Vec<String> = segments.iter().map(|s| s.source.clone()).collect();
-        let target_texts: Vec<String> = segments.iter().map(|s| s.target.clone()).collect();
+/// Write then rename, so a crash mid-save doesn't corrupt the previous copy
+fn write_atomically(path: &std::path::Path, bytes: &[u8]) -> Result<(), String> {
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, bytes)
+        .and_then(|()| std::fs::rename(&tmp_path, path))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
 
-        let source_emb = client.embed(source_texts).await?;
-        let target_emb = client.embed(target_texts).await?;
+/// Set the maximum number of cached embeddings, evicting the least recently used
+pub async fn set_cache_limit(state: &RagState, max_entries: usize) -> Result<(), String> {
+    state.cache.lock().map_err(|e| e.to_string())?.set_max_entries(max_entries);
+    save_embedding_cache(state).await
+}
 
-        (Some(source_emb), Some(target_emb))
-    } else {
-        (None, None)
+/// Get whether a file is indexed, its stored hash, size and embedding model
+pub fn index_status(state: &RagState, file_path: &str) -> Result<IndexStatus, String> {
+    let store = state.store.lock().map_err(|e| e.to_string())?;
+    Ok(store.status(file_path))
+}
+
+/// Stop a running index job for a file; its partial embeddings are discarded.
+/// Returns false if no job was running.
+pub fn cancel_indexing(state: &RagState, file_path: &str) -> Result<bool, String> {
+    let jobs = state.jobs.lock().map_err(|e| e.to_string())?;
+    let Some(control) = jobs.get(file_path) else {
+        return Ok(false);
     };
+    log::info!("Cancelling indexing of {}", file_path);
+    control.cancelled.store(true, Ordering::Relaxed);
+    Ok(true)
+}
 
-    // Create indexed segments
-    let indexed: Vec<IndexedSegment> = segments
-        .into_iter()
-        .enumerate()
-        .map(|(i, segment)| {
-            IndexedSegment {
-                segment,
-                embedding: combined_embeddings[i].clone(),
-                source_embedding: source_embeddings.as_ref().map(|v| v[i].clone()),
-                target_embedding: target_embeddings.as_ref().map(|v| v[i].clone()),
-            }
-        })
-        .collect();
+/// Texts left and estimated time for a running index job, from the client's
+/// average latency. None if no job is running for the file.
+pub fn indexing_eta(state: &RagState, file_path: &str) -> Result<Option<IndexingEta>, String> {
+    let remaining_texts = {
+        let jobs = state.jobs.lock().map_err(|e| e.to_string())?;
+        let Some(control) = jobs.get(file_path) else {
+            return Ok(None);
+        };
+        control.remaining.load(Ordering::Relaxed)
+    };
+    let average_ms = state
+        .client
+        .lock()
+        .map_err(|e| e.to_string())?
+        .as_ref()
+        .and_then(EmbeddingClient::latency_report)
+        .map(|report| report.average_ms_per_text);
+    Ok(Some(IndexingEta {
+        remaining_texts,
+        eta_seconds: average_ms.map(|ms| ms * remaining_texts as f64 / 1000.0),
+    }))
+}
 
-    let count = indexed.len();
+/// Run `check_multilingual` with the current embedding client
+pub async fn check_client_multilingual(state: &RagState) -> Result<MultilingualCheck, AppError> {
+    let client = state
+        .client
+        .lock()
+        .map_err(|e| e.to_string())?
+        .clone()
+        .ok_or_else(|| AppError::NotInitialized("Embedding client not initialized".to_string()))?;
+    check_multilingual(&client).await
+}
 
-    // Store in vector store
-    {
-        let mut store = state.store.lock().map_err(|e| e.to_string())?;
-        store.store(file_path, file_hash, indexed);
+/// Embedding latency of the current client, None before its first request
+pub fn embedding_latency(state: &RagState) -> Result<Option<LatencyReport>, String> {
+    let guard = state.client.lock().map_err(|e| e.to_string())?;
+    Ok(guard.as_ref().and_then(EmbeddingClient::latency_report))
+}
+
+/// Whether a file was indexed with separate source/target embeddings
+/// Returns None if the file isn't indexed
+pub fn has_separate_embeddings(state: &RagState, file_path: &str) -> Result<Option<bool>, String> {
+    let store = state.store.lock().map_err(|e| e.to_string())?;
+    Ok(store.index_model(file_path).map(|m| m.separate_embeddings))
+}
+
+/// Get a single indexed segment by id
+pub fn get_segment(state: &RagState, file_path: &str, segment_id: &str) -> Result<Option<Segment>, String> {
+    let mut store = state.store.lock().map_err(|e| e.to_string())?;
+    store.touch(file_path);
+    Ok(store.get_segment(file_path, segment_id))
+}
+
+/// Find segments containing `pattern` in their source or target, without embeddings.
+/// `pattern` is a literal substring unless `regex` is set.
+pub fn grep_segments(
+    state: &RagState,
+    file_path: &str,
+    pattern: &str,
+    case_insensitive: bool,
+    regex: bool,
+) -> Result<Vec<GrepMatch>, AppError> {
+    if pattern.is_empty() {
+        return Err(AppError::Other("pattern must not be empty".to_string()));
     }
+    let source = if regex { pattern.to_string() } else { regex::escape(pattern) };
+    let pattern = RegexBuilder::new(&source)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|e| AppError::Other(format!("Invalid pattern: {}", e)))?;
+
+    let mut store = state.store.lock().map_err(|e| e.to_string())?;
+    store.touch(file_path);
+    store
+        .grep(file_path, &pattern)
+        .ok_or_else(|| AppError::NotFound(format!("File not indexed: {}", file_path)))
+}
+
+/// Write a file's indexed segments to a JSON file
+/// Returns the number of segments exported
+pub fn export_index(
+    state: &RagState,
+    file_path: &str,
+    output_path: &str,
+    include_embeddings: bool,
+) -> Result<usize, String> {
+    let (count, json) = {
+        let store = state.store.lock().map_err(|e| e.to_string())?;
+        let exported = store
+            .exported_segments(file_path, include_embeddings)
+            .ok_or_else(|| format!("File not indexed: {}", file_path))?;
+        let json = serde_json::to_vec_pretty(&exported).map_err(|e| e.to_string())?;
+        (exported.len(), json)
+    };
 
+    std::fs::write(output_path, json)
+        .map_err(|e| format!("Failed to write {}: {}", output_path, e))?;
     Ok(count)
 }
 
-/// Search for similar segments
-/// - mode: search combined, source-only, target-only, or both
-/// - min_score: minimum relevance threshold (0.0-1.0, default 0.5)
-pub async fn search_segments(
+/// Run a search and write its results to a JSON file
+/// Returns the number of results exported
+pub async fn export_search_results(
     state: &RagState,
     file_path: String,
     query: String,
-    limit: usize,
-    mode: SearchMode,
-    min_score: f32,
-) -> Result<Vec<SearchResult>, String> {
-    // Get embedding client
-    let client = {
-        let guard = state.client.lock().map_err(|e| e.to_string())?;
-        guard.clone().ok_or("Embedding client not initialized")?
+    options: SearchOptions,
+    output_path: &str,
+    include_embeddings: bool,
+) -> Result<usize, String> {
+    let results = search_segments(state, file_path.clone(), query, options).await?;
+
+    let json = {
+        let store = state.store.lock().map_err(|e| e.to_string())?;
+        let exported: Vec<ExportedSearchResult> = results
+            .iter()
+            .map(|result| ExportedSearchResult {
+                result,
+                embedding: include_embeddings
+                    .then(|| store.indexed(&file_path, &result.segment.id))
+                    .flatten()
+                    .map(|indexed| indexed.embedding.to_f32()),
+            })
+            .collect();
+        serde_json::to_vec_pretty(&exported).map_err(|e| e.to_string())?
     };
 
-    // Embed query
-    let query_embedding = client.embed_one(query).await?;
+    std::fs::write(output_path, json)
+        .map_err(|e| format!("Failed to write {}: {}", output_path, e))?;
+    Ok(results.len())
+}
 
-    // Search with mode and threshold
-    let store = state.store.lock().map_err(|e| e.to_string())?;
-    Ok(store.search(&file_path, &query_embedding, limit, &mode, min_score))
+/// Set the embedding memory budget in bytes (None = unbounded)
+/// Returns the paths of files evicted to fit the new budget
+pub fn set_memory_budget(state: &RagState, max_bytes: Option<usize>) -> Result<Vec<String>, String> {
+    let mut store = state.store.lock().map_err(|e| e.to_string())?;
+    Ok(store.set_max_bytes(max_bytes))
 }
 
-/// Get RAG stats
-pub fn get_stats(state: &RagState) -> Result<HashMap<String, usize>, String> {
+/// Mark a file's index stale after the file changed on disk
+/// Returns false if the file wasn't indexed or was already stale
+pub fn invalidate_index(state: &RagState, file_path: &str) -> Result<bool, String> {
+    let mut store = state.store.lock().map_err(|e| e.to_string())?;
+    Ok(store.invalidate(file_path))
+}
+
+/// Paths of all indexed files
+pub fn indexed_files(state: &RagState) -> Result<Vec<String>, String> {
     let store = state.store.lock().map_err(|e| e.to_string())?;
-    Ok(store.stats())
+    Ok(store.indexed_files())
 }
 
-/// Clear index for a file
+/// Clear index for a file, cancelling a job still indexing it
 pub fn clear_index(state: &RagState, file_path: &str) -> Result<(), String> {
+    cancel_indexing(state, file_path)?;
     let mut store = state.store.lock().map_err(|e| e.to_string())?;
     store.clear(file_path);
     Ok(())
 }
 
+/// Clear all indexes (and progress saved by interrupted jobs), cancelling
+/// running jobs so they don't store an index afterwards.
+/// Returns the number of files cleared
+pub fn clear_all_indexes(state: &RagState) -> Result<usize, String> {
+    cancel_all_jobs(state)?;
+    let count = {
+        let mut store = state.store.lock().map_err(|e| e.to_string())?;
+        store.clear_all()
+    };
+    state.progress.lock().map_err(|e| e.to_string())?.clear();
+    Ok(count)
+}
+
 /// Check if Ollama is running and if the model is installed
-pub async fn check_ollama() -> Result<bool, String> {
-    let client = reqwest::Client::new();
+pub async fn check_ollama(client: &Client) -> Result<bool, String> {
     match client
         .get("http://localhost:11434/api/tags")
         .timeout(std::time::Duration::from_secs(2))
@@ -513,8 +3468,7 @@ pub async fn check_ollama() -> Result<bool, String> {
 }
 
 /// Check if a specific model is installed
-pub async fn check_ollama_model(model: &str) -> Result<bool, String> {
-    let client = reqwest::Client::new();
+pub async fn check_ollama_model(client: &Client, model: &str) -> Result<bool, String> {
     let response = client
         .get("http://localhost:11434/api/tags")
         .timeout(std::time::Duration::from_secs(2))
@@ -541,6 +3495,93 @@ pub async fn check_ollama_model(model: &str) -> Result<bool, String> {
     Ok(false)
 }
 
+/// A model pulled into the local Ollama
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaModel {
+    /// Name with tag, e.g. "mxbai-embed-large:latest"
+    pub name: String,
+    /// Size on disk in bytes
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub modified_at: String,
+}
+
+/// Models pulled into the local Ollama
+pub async fn list_ollama_models(client: &Client) -> Result<Vec<OllamaModel>, String> {
+    #[derive(Deserialize)]
+    struct Tags {
+        #[serde(default)]
+        models: Vec<OllamaModel>,
+    }
+
+    let response = client
+        .get("http://localhost:11434/api/tags")
+        .timeout(std::time::Duration::from_secs(2))
+        .send()
+        .await
+        .map_err(|_| "Ollama not running")?;
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to list Ollama models: {}", response.status()));
+    }
+    let tags: Tags = response.json().await.map_err(|e| e.to_string())?;
+    Ok(tags.models)
+}
+
+/// Ollama names without a tag refer to ":latest"
+fn ollama_model_name(name: &str) -> String {
+    if name.contains(':') {
+        name.to_string()
+    } else {
+        format!("{}:latest", name)
+    }
+}
+
+/// Delete a pulled Ollama model and return the models left.
+/// Deleting the model the embedding client uses requires `confirmed`, since
+/// indexing and search stop working until it's pulled again.
+pub async fn delete_ollama_model(
+    state: &RagState,
+    client: &Client,
+    name: &str,
+    confirmed: bool,
+) -> Result<Vec<OllamaModel>, String> {
+    let in_use = {
+        let guard = state.client.lock().map_err(|e| e.to_string())?;
+        guard
+            .as_ref()
+            .is_some_and(|embedding| embedding.is_ollama() && ollama_model_name(&embedding.model) == ollama_model_name(name))
+    };
+    if in_use && !confirmed {
+        return Err(format!(
+            "{} is the configured embedding model; confirm to delete it anyway",
+            name
+        ));
+    }
+
+    let response = client
+        .delete("http://localhost:11434/api/delete")
+        // Older Ollama versions read "name", newer ones "model"
+        .json(&serde_json::json!({ "model": name, "name": name }))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to delete model: {}", e))?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        let message = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|value| value["error"].as_str().map(str::to_string))
+            .unwrap_or(body);
+        return Err(format!("Failed to delete model {}: {} {}", name, status, message.trim()));
+    }
+
+    log::info!("Deleted Ollama model {}", name);
+    list_ollama_models(client).await
+}
+
 /// Install Ollama via Homebrew (macOS)
 pub fn install_ollama() -> Result<String, String> {
     use std::process::Command;
@@ -580,7 +3621,7 @@ pub fn install_ollama() -> Result<String, String> {
 }
 
 /// Start Ollama server
-pub fn start_ollama() -> Result<String, String> {
+pub fn start_ollama() -> Result<std::process::Child, String> {
     use std::process::Command;
 
     // Check if ollama is installed
@@ -597,17 +3638,89 @@ pub fn start_ollama() -> Result<String, String> {
     Command::new("ollama")
         .arg("serve")
         .spawn()
-        .map_err(|e| format!("Failed to start Ollama: {}", e))?;
+        .map_err(|e| format!("Failed to start Ollama: {}", e))
+}
+
+// How long `ollama serve` gets to shut down before it's killed
+#[cfg(unix)]
+const OLLAMA_SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
 
-    Ok("Ollama starting... wait a few seconds then refresh.".to_string())
+/// Stop an `ollama serve` started by `start_ollama`: SIGTERM first so it can
+/// unload models cleanly, then kill if it hasn't exited in time.
+/// Returns false if the process had already exited (e.g. another server held the port).
+pub fn stop_ollama(child: &mut std::process::Child) -> bool {
+    if !matches!(child.try_wait(), Ok(None)) {
+        return false;
+    }
+
+    #[cfg(unix)]
+    {
+        // SAFETY: kill(2) only sends a signal, and the child hasn't been reaped
+        // (try_wait above saw it running), so its pid can't have been reused
+        let delivered = unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } == 0;
+        if delivered {
+            let deadline = std::time::Instant::now() + OLLAMA_SHUTDOWN_GRACE;
+            while std::time::Instant::now() < deadline {
+                if let Ok(Some(_)) = child.try_wait() {
+                    return true;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            log::warn!("Ollama did not exit after SIGTERM; killing it");
+        } else {
+            log::warn!("Failed to send SIGTERM to Ollama ({}); killing it", std::io::Error::last_os_error());
+        }
+    }
+
+    let _ = child.kill();
+    let _ = child.wait();
+    true
 }
 
-/// Pull Ollama model
-pub async fn pull_ollama_model(model: &str) -> Result<String, String> {
-    let client = reqwest::Client::new();
+/// One progress line of Ollama's streamed /api/pull response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PullProgress {
+    /// e.g. "pulling manifest", "downloading", "success"
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// Bytes of the layer being downloaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<u64>,
+}
+
+/// Parse one NDJSON line of a pull. Blank and unrecognised lines give None;
+/// an `{"error": ...}` line gives the server's message as the error.
+fn parse_pull_line(line: &[u8]) -> Result<Option<PullProgress>, String> {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let value: serde_json::Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => {
+            log::warn!("Skipping unparseable pull progress line {:?}: {}", line, e);
+            return Ok(None);
+        }
+    };
+    if let Some(error) = value.get("error") {
+        return Err(error.as_str().map_or_else(|| error.to_string(), str::to_string));
+    }
+    Ok(serde_json::from_value(value).ok())
+}
 
+/// Pull an Ollama model, reporting each progress line. Fails with the server's
+/// message if it reports an error, or if the stream ends without "success".
+pub async fn pull_ollama_model(
+    client: &Client,
+    model: &str,
+    on_progress: impl Fn(&PullProgress),
+) -> Result<String, String> {
     // First check if Ollama is running
-    if !check_ollama().await.unwrap_or(false) {
+    if !check_ollama(client).await.unwrap_or(false) {
         return Err("Ollama is not running. Start it with: ollama serve".to_string());
     }
 
@@ -619,17 +3732,536 @@ pub async fn pull_ollama_model(model: &str) -> Result<String, String> {
         .map_err(|e| format!("Failed to pull model: {}", e))?;
 
     if !response.status().is_success() {
-        return Err(format!("Failed to pull model: {}", response.status()));
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Failed to pull model {}: {} {}", model, status, body.trim()));
     }
 
-    // Read stream to completion (pull is streamed)
-    let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+    // The pull is streamed as one JSON object per line; errors arrive as a line too
+    let mut stream = response.bytes_stream();
+    let mut buffer = Vec::new();
+    let mut succeeded = false;
+    let mut handle_line = |line: &[u8]| -> Result<(), String> {
+        if let Some(progress) = parse_pull_line(line).map_err(|e| format!("Failed to pull model {}: {}", model, e))? {
+            succeeded |= progress.status == "success";
+            on_progress(&progress);
+        }
+        Ok(())
+    };
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("Failed to read pull progress: {}", e))?;
+        buffer.extend_from_slice(&chunk);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            handle_line(&line)?;
+        }
+    }
+    handle_line(&buffer)?;
 
-    // Check last line for success
-    let text = String::from_utf8_lossy(&bytes);
-    if text.contains("\"status\":\"success\"") {
+    if succeeded {
         Ok(format!("Model {} pulled successfully", model))
     } else {
-        Ok(format!("Model {} pull completed", model))
+        Err(format!("Pull of model {} ended without a success status", model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The original straightforward implementation, kept as a reference
+    fn cosine_similarity_scalar(a: &[f32], b: &[f32]) -> f32 {
+        if a.len() != b.len() || a.is_empty() {
+            return 0.0;
+        }
+
+        let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+        let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+        if norm_a == 0.0 || norm_b == 0.0 {
+            return 0.0;
+        }
+
+        dot / (norm_a * norm_b)
+    }
+
+    /// Deterministic pseudo-random vector in [-1, 1)
+    fn test_vector(len: usize, seed: u64) -> Vec<f32> {
+        let mut state = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                ((state >> 40) as f32 / (1u64 << 24) as f32) * 2.0 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunked_cosine_matches_scalar() {
+        // Include lengths that aren't a multiple of the lane count
+        for len in [1, 7, 8, 9, 384, 1024, 1536, 3072, 3077] {
+            for seed in 0..5 {
+                let a = test_vector(len, seed);
+                let b = test_vector(len, seed + 100);
+                let expected = cosine_similarity_scalar(&a, &b);
+                let actual = cosine_similarity(&a, &b);
+                assert!(
+                    (expected - actual).abs() < 1e-5,
+                    "len {}: scalar {} vs chunked {}",
+                    len,
+                    expected,
+                    actual
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cosine_edge_cases() {
+        assert_eq!(cosine_similarity(&[], &[]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0, 2.0], &[1.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0; 16], &[1.0; 16]), 0.0);
+        assert!((cosine_similarity(&[1.0; 20], &[1.0; 20]) - 1.0).abs() < 1e-6);
+    }
+
+    fn indexed_segment(i: usize, vector: Vec<f32>, quantization: Quantization) -> IndexedSegment {
+        IndexedSegment {
+            segment: Segment {
+                id: i.to_string(),
+                source: String::new(),
+                target: String::new(),
+                status: String::new(),
+                percent: None,
+                origin: None,
+            },
+            position: i,
+            embedding: Embedding::new(vector, quantization),
+            source_embedding: None,
+            target_embedding: None,
+            duplicate_of: None,
+            duplicate_ids: Vec::new(),
+        }
+    }
+
+    /// Plain options for `mode`: top 10 by score, nothing filtered out
+    fn options(mode: SearchMode) -> SearchOptions {
+        SearchOptions {
+            limit: 10,
+            mode,
+            min_score: -1.0,
+            context_window: 0,
+            sort: SortOrder::Score,
+            collapse_duplicates: false,
+            mmr_lambda: None,
+            id_filter: None,
+            query_language: None,
+            both_weights: None,
+            normalization: ScoreNormalization::None,
+        }
+    }
+
+    fn segment(id: &str, source: &str, target: &str) -> Segment {
+        Segment {
+            id: id.to_string(),
+            source: source.to_string(),
+            target: target.to_string(),
+            status: String::new(),
+            percent: None,
+            origin: None,
+        }
+    }
+
+    #[test]
+    fn blank_texts_are_not_embedded() {
+        let segments = [
+            segment("1", "Hello", "Hallo"),
+            segment("2", "Untranslated", ""),
+            segment("3", "   ", "Nur Ziel"),
+            segment("4", "", ""),
+        ];
+        let refs: Vec<&Segment> = segments.iter().collect();
+
+        let texts = EmbeddingTexts::new(&refs, DEFAULT_COMBINED_TEMPLATE);
+        assert_eq!(texts.combined[0], "Source: Hello Target: Hallo");
+        assert!(texts.combined.iter().all(|t| !t.trim().is_empty()));
+        assert_eq!(
+            texts.source,
+            vec![Some("Hello".to_string()), Some("Untranslated".to_string()), None, None]
+        );
+        assert_eq!(
+            texts.target,
+            vec![Some("Hallo".to_string()), None, Some("Nur Ziel".to_string()), None]
+        );
+
+        // A template that renders blank gets the placeholder instead
+        let texts = EmbeddingTexts::new(&refs, "{target}");
+        assert_eq!(texts.combined[1], EMPTY_TEXT_PLACEHOLDER);
+        assert_eq!(texts.combined[3], EMPTY_TEXT_PLACEHOLDER);
+    }
+
+    #[test]
+    fn target_search_skips_untranslated_segments() {
+        let mut translated = indexed_segment(0, vec![1.0, 0.0], Quantization::None);
+        translated.segment = segment("0", "Hello", "Hallo");
+        translated.target_embedding = Some(Embedding::new(vec![1.0, 0.0], Quantization::None));
+        let mut untranslated = indexed_segment(1, vec![1.0, 0.0], Quantization::None);
+        untranslated.segment = segment("1", "Hello again", "");
+
+        let mut store = VectorStore::new();
+        store.store(
+            "file".to_string(),
+            "hash".to_string(),
+            "model".to_string(),
+            DEFAULT_COMBINED_TEMPLATE.to_string(),
+            vec![translated, untranslated],
+        );
+        assert!(store.index_model("file").unwrap().separate_embeddings);
+
+        let mut options = SearchOptions {
+            min_score: 0.0,
+            ..options(SearchMode::Target)
+        };
+        let results = store.search("file", &[1.0, 0.0], &options);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].segment.id, "0");
+        assert!(!results[0].fallback);
+
+        options.mode = SearchMode::Combined;
+        assert_eq!(store.search("file", &[1.0, 0.0], &options).len(), 2);
+    }
+
+    #[test]
+    fn id_filter_applies_before_collapsing_duplicates() {
+        let mut segments: Vec<IndexedSegment> =
+            (0..3).map(|i| indexed_segment(i, vec![1.0, 0.0], Quantization::None)).collect();
+        segments[0].duplicate_ids = vec!["1".to_string(), "2".to_string()];
+        for duplicate in &mut segments[1..] {
+            duplicate.duplicate_of = Some("0".to_string());
+        }
+        let mut store = VectorStore::new();
+        store.store(
+            "file".to_string(),
+            "hash".to_string(),
+            "model".to_string(),
+            DEFAULT_COMBINED_TEMPLATE.to_string(),
+            segments,
+        );
+
+        let mut options = SearchOptions {
+            min_score: 0.0,
+            collapse_duplicates: true,
+            ..options(SearchMode::Combined)
+        };
+        let results = store.search("file", &[1.0, 0.0], &options);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].segment.id, "0");
+        assert_eq!(results[0].duplicate_ids, vec!["1", "2"]);
+
+        // The first occurrence is filtered out; a selected duplicate stands in for it
+        options.id_filter = Some(HashSet::from(["2".to_string()]));
+        let results = store.search("file", &[1.0, 0.0], &options);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].segment.id, "2");
+        assert!(results[0].duplicate_ids.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn both_weights_spread_over_available_scores() {
+        let weights = BothWeights { source: 0.5, target: 0.3, combined: 0.2 };
+        assert!((weights.blend(Some(1.0), Some(0.5), 0.0) - 0.65).abs() < 1e-6);
+        // No target embedding: source and combined share the weight
+        assert!((weights.blend(Some(1.0), None, 0.3) - (0.5 + 0.2 * 0.3) / 0.7).abs() < 1e-6);
+        // Only the combined score present, but it has no weight
+        let weights = BothWeights { source: 1.0, target: 0.0, combined: 0.0 };
+        assert_eq!(weights.blend(None, None, 0.4), 0.4);
+        assert!(BothWeights { source: 0.0, target: 0.0, combined: 0.0 }.validate().is_err());
+        assert!(BothWeights { source: -1.0, target: 1.0, combined: 1.0 }.validate().is_err());
+    }
+
+    #[test]
+    fn normalized_relevance_stays_in_unit_range() {
+        let mut store = VectorStore::new();
+        store.store(
+            "file".to_string(),
+            "hash".to_string(),
+            "model".to_string(),
+            DEFAULT_COMBINED_TEMPLATE.to_string(),
+            vec![
+                indexed_segment(0, vec![1.0, 0.0], Quantization::None),
+                indexed_segment(1, vec![0.6, 0.8], Quantization::None),
+                indexed_segment(2, vec![0.0, 1.0], Quantization::None),
+            ],
+        );
+        let mut options = options(SearchMode::Combined);
+        assert!(store.search("file", &[1.0, 0.0], &options).iter().all(|r| r.relevance.is_none()));
+
+        options.normalization = ScoreNormalization::MinMax;
+        let relevance: Vec<f32> = store
+            .search("file", &[1.0, 0.0], &options)
+            .iter()
+            .map(|r| r.relevance.unwrap())
+            .collect();
+        assert_eq!(relevance.first(), Some(&1.0));
+        assert_eq!(relevance.last(), Some(&0.0));
+
+        options.normalization = ScoreNormalization::Sigmoid { center: 0.5, steepness: 10.0 };
+        let results = store.search("file", &[1.0, 0.0], &options);
+        assert!(results.iter().all(|r| (0.0..=1.0).contains(&r.relevance.unwrap())));
+        assert!(results[0].relevance > results[1].relevance);
+    }
+
+    #[test]
+    fn latency_stats_smooth_and_track_p95() {
+        let mut latency = LatencyStats::default();
+        assert_eq!(latency.p95(), None);
+        latency.record(100.0);
+        assert_eq!(latency.average_ms, Some(100.0));
+
+        // One slow request moves the average only part of the way
+        latency.record(600.0);
+        assert_eq!(latency.average_ms, Some(100.0 + LATENCY_SMOOTHING * 500.0));
+
+        for ms in 1..=100 {
+            latency.record(ms as f64);
+        }
+        assert_eq!(latency.recent.len(), LATENCY_WINDOW);
+        assert_eq!(latency.samples, 102);
+        assert_eq!(latency.p95(), Some(95.0));
+    }
+
+    #[test]
+    fn pull_lines_report_errors_and_progress() {
+        let progress = parse_pull_line(br#"{"status":"downloading","digest":"sha256:ab","total":100,"completed":40}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!((progress.total, progress.completed), (Some(100), Some(40)));
+        assert_eq!(parse_pull_line(b"{\"status\":\"success\"}\n").unwrap().unwrap().status, "success");
+        assert_eq!(
+            parse_pull_line(br#"{"error":"pull model manifest: file does not exist"}"#).unwrap_err(),
+            "pull model manifest: file does not exist"
+        );
+        assert!(parse_pull_line(b"  \n").unwrap().is_none());
+    }
+
+    #[test]
+    fn grep_matches_literal_and_regex_patterns() {
+        let mut first = indexed_segment(0, vec![1.0, 0.0], Quantization::None);
+        first.segment = segment("0", "Copyright ©2024 Example", "Urheberrecht ©2024 Beispiel");
+        let mut second = indexed_segment(1, vec![0.0, 1.0], Quantization::None);
+        second.segment = segment("1", "See section 4.2", "Siehe Abschnitt 4.2");
+
+        let mut store = VectorStore::new();
+        store.store(
+            "file".to_string(),
+            "hash".to_string(),
+            "model".to_string(),
+            DEFAULT_COMBINED_TEMPLATE.to_string(),
+            vec![first, second],
+        );
+        let grep = |pattern: &str, case_insensitive: bool| {
+            let pattern = RegexBuilder::new(pattern).case_insensitive(case_insensitive).build().unwrap();
+            store.grep("file", &pattern).unwrap()
+        };
+
+        let matches = grep(&regex::escape("©2024"), false);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].field, MatchedField::Source);
+        assert_eq!(matches[1].field, MatchedField::Target);
+
+        // A literal "." doesn't match any character
+        assert!(grep(&regex::escape("4x2"), false).is_empty());
+        assert_eq!(grep(&regex::escape("4.2"), false).len(), 2);
+
+        assert!(grep("^siehe", false).is_empty());
+        let matches = grep("^siehe", true);
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].position, matches[0].field), (1, MatchedField::Target));
+        assert!(store.grep("other", &Regex::new("x").unwrap()).is_none());
+    }
+
+    #[test]
+    fn int8_top_k_overlaps_full_precision() {
+        // Clustered vectors, so each query has a meaningful neighbourhood to rank
+        let dims = 384;
+        let centers: Vec<Vec<f32>> = (0..20).map(|c| test_vector(dims, 1000 + c)).collect();
+        let vectors: Vec<Vec<f32>> = (0..1000)
+            .map(|i| {
+                let noise = test_vector(dims, 5000 + i as u64);
+                centers[i % centers.len()]
+                    .iter()
+                    .zip(noise)
+                    .map(|(c, n)| c + 0.5 * n)
+                    .collect()
+            })
+            .collect();
+
+        let mut full = VectorStore::new();
+        let mut quantized = VectorStore::new();
+        for (store, quantization) in [(&mut full, Quantization::None), (&mut quantized, Quantization::Int8)] {
+            let segments = vectors
+                .iter()
+                .enumerate()
+                .map(|(i, v)| indexed_segment(i, v.clone(), quantization))
+                .collect();
+            store.store(
+                "file".to_string(),
+                "hash".to_string(),
+                "model".to_string(),
+                DEFAULT_COMBINED_TEMPLATE.to_string(),
+                segments,
+            );
+        }
+
+        let quantized_stats = quantized.detailed_stats();
+        assert!(quantized_stats.total_bytes * 3 < full.detailed_stats().total_bytes);
+        assert_eq!(quantized_stats.files["file"].quantization, Quantization::Int8);
+
+        let options = options(SearchMode::Combined);
+
+        let mut overlap = 0;
+        for (q, center) in centers.iter().enumerate() {
+            let query: Vec<f32> = center
+                .iter()
+                .zip(test_vector(dims, 9000 + q as u64))
+                .map(|(c, n)| c + 0.5 * n)
+                .collect();
+            let expected: Vec<String> = full
+                .search("file", &query, &options)
+                .into_iter()
+                .map(|r| r.segment.id)
+                .collect();
+            let actual = quantized.search("file", &query, &options);
+            overlap += actual.iter().filter(|r| expected.contains(&r.segment.id)).count();
+
+            for result in &actual {
+                let exact = cosine_similarity(&vectors[result.position], &query);
+                assert!((result.score - exact).abs() < 0.01);
+            }
+        }
+
+        // At least 90% of the full-precision top-10 survives quantization
+        assert!(overlap * 10 >= centers.len() * options.limit * 9, "overlap {}", overlap);
+    }
+
+    fn cache_in(name: &str) -> (EmbeddingCache, PathBuf) {
+        let path = std::env::temp_dir().join(format!("rag-cache-test-{}-{}.json", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut cache = EmbeddingCache::new();
+        cache.open(path.clone()).unwrap();
+        (cache, path)
+    }
+
+    fn save(cache: &mut EmbeddingCache) {
+        if let Some((path, bytes)) = cache.snapshot().unwrap() {
+            write_atomically(&path, &bytes).unwrap();
+        }
+    }
+
+    #[test]
+    fn embedding_cache_round_trip() {
+        let (mut cache, path) = cache_in("round-trip");
+        cache.max_entries = 10;
+        for i in 0..10 {
+            cache.insert("model", &format!("text {}", i), &[i as f32, 1.0]);
+        }
+        // Touch the oldest so the next eviction keeps it
+        assert_eq!(cache.get("model", "text 0"), Some(vec![0.0, 1.0]));
+        cache.insert("model", "text 10", &[10.0, 1.0]);
+        assert_eq!(cache.len(), 9);
+        assert!(cache.contains("model", "text 0"));
+        assert!(!cache.contains("model", "text 1"));
+        save(&mut cache);
+
+        let mut reloaded = EmbeddingCache::new();
+        reloaded.open(path.clone()).unwrap();
+        assert_eq!(reloaded.len(), 9);
+        assert_eq!(reloaded.get("model", "text 10"), Some(vec![10.0, 1.0]));
+        // A lookup alone leaves nothing to save
+        assert!(reloaded.snapshot().unwrap().is_none());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn embedding_cache_dimension_change_keeps_other_tags() {
+        let mut cache = EmbeddingCache::new();
+        cache.insert("embed:latest", "a", &[1.0, 0.0]);
+        cache.insert("embed:small", "a", &[1.0]);
+        cache.insert("embed", "a", &[1.0, 0.0, 0.0]);
+        cache.insert("embed", "a", &[1.0, 0.0]);
+        assert!(cache.contains("embed:latest", "a"));
+        assert!(cache.contains("embed:small", "a"));
+        assert_eq!(cache.get("embed", "a"), Some(vec![1.0, 0.0]));
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn corrupt_embedding_cache_starts_empty_and_is_replaced() {
+        let (_, path) = cache_in("corrupt");
+        std::fs::write(&path, b"{not json").unwrap();
+        let mut cache = EmbeddingCache::new();
+        cache.open(path.clone()).unwrap();
+        assert_eq!(cache.len(), 0);
+        cache.insert("model", "text", &[1.0]);
+        save(&mut cache);
+
+        let mut reloaded = EmbeddingCache::new();
+        reloaded.open(path.clone()).unwrap();
+        assert!(reloaded.contains("model", "text"));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn segment_hash_follows_content() {
+        let segments = vec![segment("1", "Hello", "Hallo"), segment("2", "World", "Welt")];
+        assert_eq!(hash_segments(&segments), hash_segments(&segments.clone()));
+
+        let mut edited = segments.clone();
+        edited[1].target = "Erde".to_string();
+        assert_ne!(hash_segments(&segments), hash_segments(&edited));
+
+        let shifted = vec![segment("1", "Hello", "HalloWorld"), segment("2", "", "Welt")];
+        assert_ne!(hash_segments(&segments), hash_segments(&shifted));
+    }
+
+    #[test]
+    fn multilingual_check_rejects_a_short_response() {
+        let short = vec![vec![1.0, 0.0], vec![1.0, 0.0], vec![0.0, 1.0]];
+        assert!(matches!(
+            score_multilingual("model", &short),
+            Err(AppError::InvalidResponse(_))
+        ));
+
+        let full = vec![vec![1.0, 0.0], vec![1.0, 0.0], vec![0.9, 0.1], vec![0.0, 1.0]];
+        let check = score_multilingual("model", &full).unwrap();
+        assert!(check.translation_similarity > check.unrelated_similarity);
+        assert!(check.warning.is_none());
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture cosine_benchmark`
+    #[test]
+    #[ignore]
+    fn cosine_benchmark() {
+        let query = test_vector(3072, 1);
+        let index: Vec<Vec<f32>> = (0..2000).map(|i| test_vector(3072, i + 10)).collect();
+
+        let time = |f: fn(&[f32], &[f32]) -> f32| {
+            let started = Instant::now();
+            let mut total = 0.0;
+            for _ in 0..10 {
+                total += index.iter().map(|v| f(&query, v)).sum::<f32>();
+            }
+            (started.elapsed(), total)
+        };
+
+        let (scalar, scalar_total) = time(cosine_similarity_scalar);
+        let (chunked, chunked_total) = time(cosine_similarity);
+        println!(
+            "3072 dims x 2000 vectors x 10: scalar {:?}, chunked {:?} ({:.2}x)",
+            scalar,
+            chunked,
+            scalar.as_secs_f64() / chunked.as_secs_f64()
+        );
+        assert!((scalar_total - chunked_total).abs() < 1e-2);
+    }
+}
@@ -28,12 +28,31 @@ export interface TokenUsage {
 	outputTokens: number;
 	cacheReadTokens?: number;
 	cacheWriteTokens?: number;
+	serviceTier?: string;
+	/** cacheRead / (cacheRead + input) for the turn; undefined without a prompt */
+	cacheHitRatio?: number;
+}
+
+/** A tool call whose input JSON is still streaming in */
+export interface ToolInputProgress {
+	id: string;
+	name: string;
+	partialJson: string;
 }
 
 export interface StreamEvent {
-	type: 'text' | 'tool_use' | 'tool_result' | 'done' | 'error' | 'usage' | 'model_selected';
+	type:
+		| 'text'
+		| 'tool_use'
+		| 'tool_input_delta'
+		| 'tool_result'
+		| 'done'
+		| 'error'
+		| 'usage'
+		| 'model_selected';
 	content?: string;
 	toolUse?: ToolUseBlock;
+	toolInput?: ToolInputProgress;
 	error?: string;
 	usage?: TokenUsage;
 }
@@ -53,8 +72,14 @@ interface ChatEvent {
 		output_tokens: number;
 		cache_read_tokens?: number;
 		cache_write_tokens?: number;
+		service_tier?: string | null;
+		cache_hit_ratio?: number | null;
 	};
 	error?: string;
+	stop?: {
+		reason?: string;
+		sequence?: string;
+	};
 }
 
 interface Tool {
@@ -76,6 +101,25 @@ export async function isClientInitialized(): Promise<boolean> {
 	return await invoke<boolean>('has_api_key');
 }
 
+/** Proxy and TLS settings for every outbound request (chat, embeddings, Ollama) */
+export interface HttpConfig {
+	/** Proxy URL; when unset, HTTP_PROXY / HTTPS_PROXY / ALL_PROXY are used */
+	proxy?: string | null;
+	/** Hosts that bypass the proxy, comma separated like NO_PROXY */
+	no_proxy?: string | null;
+	/** PEM files with extra root certificates, e.g. a TLS-inspecting proxy's CA */
+	ca_certificates?: string[];
+}
+
+export async function getHttpConfig(): Promise<HttpConfig> {
+	return await invoke<HttpConfig>('get_http_config');
+}
+
+/** Apply and save HTTP settings; rejects if the proxy URL or a certificate is invalid */
+export async function setHttpConfig(config: HttpConfig): Promise<void> {
+	await invoke('set_http_config', { config });
+}
+
 export async function* streamChatWithTools(
 	messages: ConversationMessage[],
 	systemPrompt: string,
@@ -83,6 +127,7 @@ export async function* streamChatWithTools(
 	onToolCall?: (toolUse: ToolUseBlock) => Promise<string>,
 	model: ModelChoice = 'sonnet'
 ): AsyncGenerator<StreamEvent> {
+	// Must be unique per request and only letters, digits, '-' or '_' (checked by chat_stream)
 	const streamId = crypto.randomUUID();
 	const eventName = `chat-event-${streamId}`;
 
@@ -114,6 +159,15 @@ export async function* streamChatWithTools(
 					input: payload.tool_use.input
 				}
 			});
+		} else if (payload.event_type === 'tool_input_delta' && payload.tool_use) {
+			events.push({
+				type: 'tool_input_delta',
+				toolInput: {
+					id: payload.tool_use.id,
+					name: payload.tool_use.name,
+					partialJson: payload.content ?? ''
+				}
+			});
 		} else if (payload.event_type === 'usage' && payload.usage) {
 			events.push({
 				type: 'usage',
@@ -121,7 +175,9 @@ export async function* streamChatWithTools(
 					inputTokens: payload.usage.input_tokens,
 					outputTokens: payload.usage.output_tokens,
 					cacheReadTokens: payload.usage.cache_read_tokens,
-					cacheWriteTokens: payload.usage.cache_write_tokens
+					cacheWriteTokens: payload.usage.cache_write_tokens,
+					serviceTier: payload.usage.service_tier ?? undefined,
+					cacheHitRatio: payload.usage.cache_hit_ratio ?? undefined
 				}
 			});
 		} else if (payload.event_type === 'done') {
@@ -17,8 +17,53 @@ export interface Segment {
 }
 
 export interface SearchResult {
+	/** Indexed file the segment belongs to */
+	file_path: string;
 	segment: Segment;
 	score: number;
+	/** Per-embedding scores, only present in 'both' mode */
+	source_score?: number;
+	target_score?: number;
+	combined_score?: number;
+	/** Source/target mode fell back to the combined embedding */
+	fallback: boolean;
+	/** Score mapped to 0-1, present when normalization was requested */
+	relevance?: number;
+	/** Relevance from the rerank API, present when a reranker is set */
+	rerank_score?: number;
+}
+
+/** A segment whose source or target contains a grepSegments pattern */
+export interface GrepMatch {
+	segment: Segment;
+	/** Position of the segment in the file (document order) */
+	position: number;
+	field: 'source' | 'target';
+}
+
+/** Structured error returned by the converted backend commands */
+export interface AppError {
+	kind:
+		| 'not_initialized'
+		| 'network'
+		| 'invalid_response'
+		| 'auth'
+		| 'timeout'
+		| 'not_found'
+		| 'cancelled'
+		| 'other';
+	message: string;
+}
+
+export function isAppError(error: unknown): error is AppError {
+	return typeof error === 'object' && error !== null && 'kind' in error && 'message' in error;
+}
+
+/** Message text from an Error, an AppError, or a plain string error */
+export function errorMessage(error: unknown, fallback: string): string {
+	if (error instanceof Error || isAppError(error)) return error.message;
+	if (typeof error === 'string') return error;
+	return fallback;
 }
 
 export interface RagStats {
@@ -33,6 +78,7 @@ export interface RagStats {
  * - both: Search both and return max score
  */
 export type SearchMode = 'combined' | 'source' | 'target' | 'both';
+export type SortOrder = 'score' | 'document_position';
 
 export interface RagSearchOptions {
 	filePath: string;
@@ -40,6 +86,25 @@ export interface RagSearchOptions {
 	limit?: number;
 	mode?: SearchMode;
 	minScore?: number;
+	/** Return the kept hits best-first (default) or in document order */
+	sort?: SortOrder;
+	/** Blend 'both' mode scores with these weights instead of taking the highest */
+	bothWeights?: { source: number; target: number; combined: number };
+	/** Add a 0-1 relevance to each result, comparable across models */
+	normalization?: { method: 'none' | 'min_max' } | { method: 'sigmoid'; center?: number; steepness?: number };
+	/**
+	 * Language of the query (e.g. "en") for source/target searches.
+	 * Helps local Ollama/TEI models; OpenAI and Azure ignore it.
+	 */
+	queryLanguage?: string;
+}
+
+/** Result of ragIndex */
+export interface RagIndexReport {
+	/** Segments in the index */
+	count: number;
+	/** Only source/target embeddings were computed; the combined ones were reused */
+	added_separate_embeddings: boolean;
 }
 
 export interface RagIndexOptions {
@@ -50,17 +115,33 @@ export interface RagIndexOptions {
 	separateEmbeddings?: boolean;
 }
 
+/** OpenAI embedding models to offer, default first */
+export const OPENAI_EMBEDDING_MODELS = [
+	'text-embedding-3-small',
+	'text-embedding-3-large',
+	'text-embedding-ada-002'
+] as const;
+
 /**
  * Initialize the RAG embedding client.
  *
  * @param apiKey - OpenAI API key (optional if using Ollama)
  * @param useOllama - Use local Ollama instead of OpenAI
+ * @param model - OpenAI embedding model (default: text-embedding-3-small), see OPENAI_EMBEDDING_MODELS
+ * @param baseUrl - OpenAI-compatible API root (default: https://api.openai.com/v1)
  */
-export async function ragInit(apiKey?: string, useOllama = false): Promise<string> {
+export async function ragInit(
+	apiKey?: string,
+	useOllama = false,
+	model?: string,
+	baseUrl?: string
+): Promise<string> {
 	return invoke<string>('rag_init', {
 		request: {
 			api_key: apiKey,
-			use_ollama: useOllama
+			use_ollama: useOllama,
+			model,
+			base_url: baseUrl
 		}
 	});
 }
@@ -68,19 +149,23 @@ export async function ragInit(apiKey?: string, useOllama = false): Promise<strin
 /**
  * Index segments for semantic search.
  *
+ * Also emits `index_ready` ({ file_path, segments }) on success or
+ * `index_failed` ({ file_path, error: AppError }) on failure, so a UI that
+ * didn't start the job can react without polling.
+ *
  * @param filePath - Path to the SDLXLIFF file
- * @param fileHash - Hash of file contents (for cache invalidation)
+ * @param fileHash - Hash of the segments from hashSegments (for cache invalidation)
  * @param segments - Segments to index
  * @param separateEmbeddings - Create separate source/target embeddings (3x API calls but better search)
- * @returns Number of segments indexed
+ * @returns Number of segments indexed, and whether only source/target embeddings were added
  */
 export async function ragIndex(
 	filePath: string,
 	fileHash: string,
 	segments: Segment[],
 	separateEmbeddings = false
-): Promise<number> {
-	return invoke<number>('rag_index', {
+): Promise<RagIndexReport> {
+	return invoke<RagIndexReport>('rag_index', {
 		request: {
 			file_path: filePath,
 			file_hash: fileHash,
@@ -98,6 +183,8 @@ export async function ragIndex(
  * @param limit - Maximum results to return (default: 10)
  * @param mode - Search mode: combined, source, target, or both (default: combined)
  * @param minScore - Minimum relevance threshold 0.0-1.0 (default: 0.5)
+ * @param queryLanguage - Language of the query for source/target modes (e.g. "en"); used by local models
+ * @param sort - 'score' (default) or 'document_position' to list the kept hits in reading order
  * @returns Matching segments with relevance scores
  */
 export async function ragSearch(
@@ -105,7 +192,9 @@ export async function ragSearch(
 	query: string,
 	limit = 10,
 	mode: SearchMode = 'combined',
-	minScore = 0.5
+	minScore = 0.5,
+	queryLanguage?: string,
+	sort: SortOrder = 'score'
 ): Promise<SearchResult[]> {
 	return invoke<SearchResult[]>('rag_search', {
 		request: {
@@ -113,11 +202,59 @@ export async function ragSearch(
 			query,
 			limit,
 			mode,
+			min_score: minScore,
+			query_language: queryLanguage,
+			sort
+		}
+	});
+}
+
+/**
+ * Search several indexed files at once, merging their hits into one ranking.
+ * Each result's file_path says which file it came from.
+ *
+ * @param filePaths - Paths of the indexed SDLXLIFF files to search
+ * @param query - Natural language search query
+ * @param limit - Maximum results to return across all files (default: 10)
+ * @param mode - Search mode: combined, source, target, or both (default: combined)
+ * @param minScore - Minimum relevance threshold 0.0-1.0 (default: 0.5)
+ * @returns Matching segments with relevance scores, best first
+ */
+export async function searchCorpus(
+	filePaths: string[],
+	query: string,
+	limit = 10,
+	mode: SearchMode = 'combined',
+	minScore = 0.5
+): Promise<SearchResult[]> {
+	return invoke<SearchResult[]>('search_corpus', {
+		request: {
+			file_paths: filePaths,
+			query,
+			limit,
+			mode,
 			min_score: minScore
 		}
 	});
 }
 
+/**
+ * Find the segments closest in meaning to an indexed segment, e.g. to review
+ * consistency. Uses the stored embedding, so no embedding request is made.
+ *
+ * @param filePath - Path to the indexed SDLXLIFF file
+ * @param segmentId - Segment to find neighbors of (excluded from the results)
+ * @param limit - Maximum results to return (default: 10)
+ * @returns Nearest segments with similarity scores, best first
+ */
+export async function similarToSegment(
+	filePath: string,
+	segmentId: string,
+	limit = 10
+): Promise<SearchResult[]> {
+	return invoke<SearchResult[]>('similar_to_segment', { filePath, segmentId, limit });
+}
+
 /**
  * Get statistics about indexed files.
  *
@@ -127,6 +264,24 @@ export async function ragStats(): Promise<RagStats> {
 	return invoke<RagStats>('rag_stats');
 }
 
+/**
+ * Find segments literally containing a pattern, without embeddings.
+ *
+ * @param filePath - Path to the indexed SDLXLIFF file
+ * @param pattern - Substring to look for, or a regular expression if `regex` is set
+ * @param caseInsensitive - Ignore case (default: false)
+ * @param regex - Treat pattern as a regular expression (default: false)
+ * @returns One match per matching field, in document order
+ */
+export async function grepSegments(
+	filePath: string,
+	pattern: string,
+	caseInsensitive = false,
+	regex = false
+): Promise<GrepMatch[]> {
+	return invoke<GrepMatch[]>('grep_segments', { filePath, pattern, caseInsensitive, regex });
+}
+
 /**
  * Clear the index for a file.
  *
@@ -137,16 +292,70 @@ export async function ragClear(filePath: string): Promise<void> {
 }
 
 /**
- * Compute a simple hash of file content for cache invalidation.
+ * Hash segments the way indexing expects (BLAKE3 of their ids, text and status).
+ * Pass the result to ragIndex as fileHash, so unsaved edits trigger a re-index.
+ */
+export async function hashSegments(segments: Segment[]): Promise<string> {
+	return invoke<string>('hash_segments', { segments });
+}
+
+/** Hosted rerank API; model defaults to rerank-v3.5 (Cohere) or rerank-2 (Voyage) */
+export interface RerankConfig {
+	provider: 'cohere' | 'voyage';
+	api_key: string;
+	model?: string;
+	/** Embedding hits sent to the reranker (default: 50) */
+	candidates?: number;
+}
+
+/**
+ * Rerank search results with a dedicated rerank API, or pass null to turn it off.
+ */
+export async function setReranker(config: RerankConfig | null): Promise<void> {
+	return invoke('set_reranker', { config });
+}
+
+/** Progress of a running index job */
+export interface IndexingEta {
+	remaining_texts: number;
+	/** Null until the embedding client has timed a request */
+	eta_seconds: number | null;
+}
+
+/**
+ * Texts left and estimated time remaining while a file is being indexed,
+ * based on the embedding client's recent latency. Null if it isn't being indexed.
+ */
+export async function indexingEta(filePath: string): Promise<IndexingEta | null> {
+	return invoke<IndexingEta | null>('indexing_eta', { filePath });
+}
+
+/**
+ * Watch indexed files and mark their index stale when they change on disk.
+ * Each invalidated file is reported with an `index_invalidated` event carrying
+ * `{ file_path }`; re-index it to refresh search results.
+ */
+export async function setIndexWatching(enabled: boolean): Promise<void> {
+	return invoke('set_index_watching', { enabled });
+}
+
+/** Result of the embedding model's cross-language check */
+export interface MultilingualCheck {
+	model: string;
+	/** Average similarity of a sentence to its German and French translations */
+	translation_similarity: number;
+	/** Similarity of the same sentence to unrelated English text */
+	unrelated_similarity: number;
+	/** Present when the model looks English-only; show it as a soft warning */
+	warning?: string;
+}
+
+/**
+ * Check whether the configured embedding model matches text across languages.
+ * Costs one small embedding request.
  */
-export function computeHash(content: string): string {
-	let hash = 0;
-	for (let i = 0; i < content.length; i++) {
-		const char = content.charCodeAt(i);
-		hash = (hash << 5) - hash + char;
-		hash = hash & hash; // Convert to 32bit integer
-	}
-	return hash.toString(16);
+export async function checkEmbeddingMultilingual(): Promise<MultilingualCheck> {
+	return invoke<MultilingualCheck>('check_embedding_multilingual');
 }
 
 /**
@@ -178,8 +387,66 @@ export async function startOllama(): Promise<string> {
 }
 
 /**
- * Pull an Ollama model.
+ * Stop the Ollama server started with startOllama. An Ollama started outside
+ * the app is left running.
+ *
+ * @returns true if a server started by the app was stopped
+ */
+export async function stopOllama(): Promise<boolean> {
+	return invoke<boolean>('stop_ollama');
+}
+
+/** A model pulled into the local Ollama */
+export interface OllamaModel {
+	/** Name with tag, e.g. "mxbai-embed-large:latest" */
+	name: string;
+	/** Size on disk in bytes */
+	size: number;
+	modified_at: string;
+}
+
+/**
+ * List the models pulled into the local Ollama.
+ */
+export async function listOllamaModels(): Promise<OllamaModel[]> {
+	return invoke<OllamaModel[]>('list_ollama_models');
+}
+
+/**
+ * Delete a pulled Ollama model to free disk space.
+ * Deleting the configured embedding model is refused unless `confirmed` is true.
+ *
+ * @returns The models left after deletion
+ */
+export async function deleteOllamaModel(name: string, confirmed = false): Promise<OllamaModel[]> {
+	return invoke<OllamaModel[]>('delete_ollama_model', { name, confirmed });
+}
+
+/** Payload of the `ollama-pull-progress` event */
+export interface PullProgress {
+	model: string;
+	/** e.g. "pulling manifest", "downloading", "success" */
+	status: string;
+	digest?: string;
+	/** Bytes of the layer being downloaded */
+	total?: number;
+	completed?: number;
+}
+
+/**
+ * Pull an Ollama model. Progress is reported with `ollama-pull-progress` events;
+ * rejects with Ollama's message if the pull fails (e.g. the model doesn't exist).
  */
 export async function pullOllamaModel(model: string): Promise<string> {
 	return invoke<string>('rag_pull_ollama_model', { model });
+}
+
+/**
+ * Stop a running pull. The pending pullOllamaModel call rejects and a
+ * `pull_cancelled` event ({ model }) is emitted.
+ *
+ * @returns false if the model wasn't being pulled
+ */
+export async function cancelOllamaPull(model: string): Promise<boolean> {
+	return invoke<boolean>('cancel_ollama_pull', { model });
 }
\ No newline at end of file
@@ -3,14 +3,16 @@
  */
 
 import { get, writable } from 'svelte/store';
+import { listen } from '@tauri-apps/api/event';
 import {
 	ragInit,
 	ragIndex,
 	ragSearch,
 	ragStats,
 	ragClear,
-	computeHash,
+	hashSegments,
 	checkOllama,
+	errorMessage,
 	type Segment,
 	type SearchResult,
 	type SearchMode
@@ -43,12 +45,34 @@ export const ragInjectedContext = writable<string>('');
 
 // Track indexed files
 const indexedFiles = new Map<string, string>(); // path -> hash
+let listeningForIndexChanges = false;
+
+/**
+ * Forget cached hashes when the backend invalidates or drops an index, so the
+ * next indexFile call re-indexes instead of trusting the cache
+ */
+async function listenForIndexChanges(): Promise<void> {
+	if (listeningForIndexChanges) {
+		return;
+	}
+	listeningForIndexChanges = true;
+	await listen<{ file_path: string }>('index_invalidated', (event) => {
+		indexedFiles.delete(event.payload.file_path);
+	});
+	await listen<{ file_paths: string[] }>('rag-index-evicted', (event) => {
+		for (const filePath of event.payload.file_paths) {
+			indexedFiles.delete(filePath);
+		}
+	});
+	await listen('rag-indexes-cleared', () => indexedFiles.clear());
+}
 
 /**
  * Initialize RAG from stored settings
  */
 export async function initializeRag(): Promise<void> {
 	try {
+		await listenForIndexChanges();
 		const settings = await getRagSettings();
 
 		ragEnabled.set(settings.enabled);
@@ -72,7 +96,7 @@ export async function initializeRag(): Promise<void> {
 		}
 	} catch (error) {
 		console.error('Failed to initialize RAG:', error);
-		ragError.set(error instanceof Error ? error.message : 'RAG initialization failed');
+		ragError.set(errorMessage(error, 'RAG initialization failed'));
 		ragInitialized.set(false);
 	}
 }
@@ -185,9 +209,8 @@ export async function indexFile(filePath: string): Promise<number> {
 			return 0;
 		}
 
-		// Compute hash for cache invalidation
-		const segmentText = segments.map((s) => `${s.source}|${s.target}`).join('\n');
-		const fileHash = computeHash(segmentText);
+		// Hash the segments as read (including unsaved edits) for cache invalidation
+		const fileHash = await hashSegments(segments);
 
 		// Check if already indexed with same hash
 		if (indexedFiles.get(filePath) === fileHash) {
@@ -196,7 +219,10 @@ export async function indexFile(filePath: string): Promise<number> {
 		}
 
 		// Index segments with separate source/target embeddings for better bilingual search
-		const count = await ragIndex(filePath, fileHash, segments, true);
+		const { count, added_separate_embeddings } = await ragIndex(filePath, fileHash, segments, true);
+		if (added_separate_embeddings) {
+			console.log('RAG: Added source/target embeddings, reusing combined ones:', filePath);
+		}
 		indexedFiles.set(filePath, fileHash);
 
 		// Update total indexed count
@@ -206,7 +232,7 @@ export async function indexFile(filePath: string): Promise<number> {
 		return count;
 	} catch (error) {
 		console.error('RAG indexing failed:', error);
-		ragError.set(error instanceof Error ? error.message : 'Indexing failed');
+		ragError.set(errorMessage(error, 'Indexing failed'));
 		return 0;
 	} finally {
 		ragIndexing.set(false);
@@ -237,7 +263,7 @@ export async function searchSegments(
 		return results;
 	} catch (error) {
 		console.error('RAG search failed:', error);
-		ragError.set(error instanceof Error ? error.message : 'Search failed');
+		ragError.set(errorMessage(error, 'Search failed'));
 		return [];
 	}
 }